@@ -0,0 +1,45 @@
+#![no_main]
+
+//! Feed arbitrary TCP requests, RTU responses and clock ticks through the gateway
+//!
+//! Every chunk of the input starts with a selector byte: its lowest two bits pick the operation,
+//! the remaining bits the length of the frame which follows.
+
+use core::{cell::Cell, time::Duration};
+
+use libfuzzer_sys::fuzz_target;
+use modbus_server::gateway::{Gateway, GatewayAction};
+
+fuzz_target!(|data: &[u8]| {
+    let now = Cell::new(Duration::ZERO);
+    let mut gateway = Gateway::new(|| now.get(), [1..=10, 100..=110], Duration::from_millis(50));
+    let mut out = [0u8; 260];
+
+    let mut rest = data;
+    while let Some((&selector, tail)) = rest.split_first() {
+        let len = usize::from(selector >> 2) * 4;
+        let (frame, tail) = tail.split_at(len.min(tail.len()));
+        rest = tail;
+
+        let action = match selector & 0x03 {
+            0 => gateway.handle_request(frame, &mut out),
+            1 => gateway.handle_response(frame, &mut out),
+            _ => {
+                now.set(now.get() + Duration::from_millis(u64::from(selector)));
+                gateway.poll(&mut out)
+            }
+        }
+        .unwrap();
+
+        match action {
+            GatewayAction::SendTcp(len) => {
+                assert!(len <= out.len());
+                assert_eq!(modbus_server::tcp::frame_len(&out[..len]), Some(len));
+            }
+            GatewayAction::SendRtu(len) => {
+                assert!(len <= 256);
+            }
+            GatewayAction::Malformed | GatewayAction::Idle => {}
+        }
+    }
+});
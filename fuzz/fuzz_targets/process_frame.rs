@@ -0,0 +1,27 @@
+#![no_main]
+
+//! Feed arbitrary byte sequences as RTU frames through the server
+//!
+//! The input is split into several frames, each prefixed by its length byte, so that sequences
+//! of requests are processed by the same server instance.
+
+mod reference;
+
+use libfuzzer_sys::fuzz_target;
+use modbus_server::{ModbusServer, Outcome};
+use reference::{ReferenceHandler, UNIT_ID, check_response};
+
+fuzz_target!(|data: &[u8]| {
+    let mut server = ModbusServer::new(UNIT_ID, ReferenceHandler::new());
+    let mut tx = [0u8; 256];
+
+    let mut rest = data;
+    while let Some((&len, tail)) = rest.split_first() {
+        let (frame, tail) = tail.split_at(usize::from(len).min(tail.len()));
+        rest = tail;
+
+        if let Outcome::Responded(len) = server.process_frame(frame, &mut tx).unwrap() {
+            check_response(frame, &tx[..len]);
+        }
+    }
+});
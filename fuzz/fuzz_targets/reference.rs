@@ -0,0 +1,97 @@
+//! Reference handler and response checks shared by the fuzz targets
+
+use modbus_core::rtu::crc16;
+use modbus_server::{error::Error, handler::ModbusHandler};
+
+/// Unit ID of the fuzzed server
+pub const UNIT_ID: u8 = 1;
+
+/// Handler holding the complete 16 bit address space of every data type
+///
+/// Writes are stored and show up in later reads, so sequences of frames exercise the server
+/// like a real device.
+pub struct ReferenceHandler {
+    coils: Vec<bool>,
+    registers: Vec<u16>,
+}
+
+impl ReferenceHandler {
+    pub fn new() -> Self {
+        Self {
+            coils: vec![false; 0x10000],
+            registers: vec![0; 0x10000],
+        }
+    }
+}
+
+impl ModbusHandler for ReferenceHandler {
+    fn read_coils(&mut self, addr: usize, len: usize, out: &mut [bool]) -> Result<usize, Error> {
+        out[..len].copy_from_slice(&self.coils[addr..addr + len]);
+        Ok(len)
+    }
+
+    fn read_discrete_input(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [bool],
+    ) -> Result<usize, Error> {
+        // discrete inputs mirror the inverted coils
+        for (slot, coil) in out[..len].iter_mut().zip(&self.coils[addr..addr + len]) {
+            *slot = !coil;
+        }
+        Ok(len)
+    }
+
+    fn read_holding_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        out[..len].copy_from_slice(&self.registers[addr..addr + len]);
+        Ok(len)
+    }
+
+    fn read_input_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        // input registers expose the addresses themselves, odd addresses are not mapped
+        if addr % 2 == 1 {
+            return Err(Error::InvalidAddress);
+        }
+        for (i, slot) in out[..len].iter_mut().enumerate() {
+            *slot = (addr + i) as u16;
+        }
+        Ok(len)
+    }
+
+    fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+        self.coils[addr..addr + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn write_registers(&mut self, addr: usize, len: usize, buf: &[u16]) -> Result<usize, Error> {
+        self.registers[addr..addr + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// Assert that a response is a valid RTU frame answering `request`
+pub fn check_response(request: &[u8], response: &[u8]) {
+    assert!(response.len() >= 5, "response too short: {response:02X?}");
+    assert!(response.len() <= 256, "response too long: {response:02X?}");
+
+    let (adu, crc) = response.split_at(response.len() - 2);
+    assert_eq!(crc16(adu), u16::from_be_bytes([crc[0], crc[1]]));
+    assert_eq!(adu[0], UNIT_ID);
+    assert_eq!(adu[1] & 0x7F, request[1]);
+
+    if adu[1] & 0x80 != 0 {
+        assert_eq!(response.len(), 5);
+        assert!((0x01..=0x04).contains(&adu[2]));
+    }
+}
@@ -0,0 +1,84 @@
+//! Simulator configuration file
+
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+/// Contents of the configuration file
+///
+/// ```toml
+/// unit_id = 1
+///
+/// [serial]
+/// baud_rate = 19200
+/// parity = "even"
+///
+/// [[holding_registers]]
+/// address = 100
+/// values = [1, 2, 3]
+///
+/// [[coils]]
+/// address = 0
+/// values = [true, false]
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Unit ID of the simulated device
+    pub unit_id: u8,
+    /// Serial line settings
+    #[serde(default)]
+    pub serial: Serial,
+    #[serde(default)]
+    pub coils: Vec<Block<bool>>,
+    #[serde(default)]
+    pub discrete_inputs: Vec<Block<bool>>,
+    #[serde(default)]
+    pub holding_registers: Vec<Block<u16>>,
+    #[serde(default)]
+    pub input_registers: Vec<Block<u16>>,
+}
+
+impl Config {
+    /// Load and parse a configuration file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+/// Serial line settings
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Serial {
+    pub baud_rate: u32,
+    pub parity: Parity,
+    pub stop_bits: u8,
+}
+
+impl Default for Serial {
+    /// The default settings of the Modbus specification: 19200 baud, even parity, 1 stop bit
+    fn default() -> Self {
+        Self {
+            baud_rate: 19200,
+            parity: Parity::Even,
+            stop_bits: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Consecutive values starting at `address`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Block<T> {
+    pub address: u16,
+    pub values: Vec<T>,
+}
@@ -0,0 +1,102 @@
+//! Handler serving the register map of the configuration
+
+use std::collections::BTreeMap;
+
+use modbus_server::{error::Error, handler::ModbusHandler};
+
+use crate::config::{Block, Config};
+
+/// Values of one data type by address, only configured addresses exist
+struct Table<T>(BTreeMap<usize, T>);
+
+impl<T: Copy> Table<T> {
+    fn new(blocks: &[Block<T>]) -> Self {
+        let values = blocks.iter().flat_map(|block| {
+            let start = usize::from(block.address);
+            block
+                .values
+                .iter()
+                .enumerate()
+                .map(move |(i, v)| (start + i, *v))
+        });
+        Self(values.collect())
+    }
+
+    fn read(&self, addr: usize, out: &mut [T]) -> Result<usize, Error> {
+        for (i, slot) in out.iter_mut().enumerate() {
+            *slot = *self.0.get(&(addr + i)).ok_or(Error::InvalidAddress)?;
+        }
+        Ok(out.len())
+    }
+
+    fn write(&mut self, addr: usize, values: &[T]) -> Result<usize, Error> {
+        // check the complete range first, so a failed write changes nothing
+        if !(addr..addr + values.len()).all(|a| self.0.contains_key(&a)) {
+            return Err(Error::InvalidAddress);
+        }
+        for (i, value) in values.iter().enumerate() {
+            self.0.insert(addr + i, *value);
+        }
+        Ok(values.len())
+    }
+}
+
+/// Simulated device, coils and holding registers are writable
+pub struct SimHandler {
+    coils: Table<bool>,
+    discrete_inputs: Table<bool>,
+    holding_registers: Table<u16>,
+    input_registers: Table<u16>,
+}
+
+impl SimHandler {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            coils: Table::new(&config.coils),
+            discrete_inputs: Table::new(&config.discrete_inputs),
+            holding_registers: Table::new(&config.holding_registers),
+            input_registers: Table::new(&config.input_registers),
+        }
+    }
+}
+
+impl ModbusHandler for SimHandler {
+    fn read_coils(&mut self, addr: usize, len: usize, out: &mut [bool]) -> Result<usize, Error> {
+        self.coils.read(addr, &mut out[..len])
+    }
+
+    fn read_discrete_input(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [bool],
+    ) -> Result<usize, Error> {
+        self.discrete_inputs.read(addr, &mut out[..len])
+    }
+
+    fn read_holding_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.holding_registers.read(addr, &mut out[..len])
+    }
+
+    fn read_input_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.input_registers.read(addr, &mut out[..len])
+    }
+
+    fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+        self.coils.write(addr, &buf[..len])
+    }
+
+    fn write_registers(&mut self, addr: usize, len: usize, buf: &[u16]) -> Result<usize, Error> {
+        self.holding_registers.write(addr, &buf[..len])
+    }
+}
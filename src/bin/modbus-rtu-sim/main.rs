@@ -0,0 +1,134 @@
+//! Modbus RTU slave simulator
+//!
+//! Serves the register map of a configuration file on a serial device, e.g. to test masters
+//! against a simulated device from a laptop:
+//!
+//! ```sh
+//! modbus-rtu-sim /dev/ttyUSB0 device.toml
+//! ```
+//!
+//! Every received frame is logged to stderr together with the response. See [`config::Config`]
+//! for the format of the configuration file.
+
+mod config;
+mod handler;
+
+use std::{
+    env,
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use modbus_server::{
+    ModbusServer, Outcome, UNIT_ID_RANGE,
+    rtu::{FrameAssembler, inter_frame_delay},
+};
+use serialport::{DataBits, SerialPort, StopBits};
+
+use config::{Config, Parity};
+use handler::SimHandler;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [device, config] = args.as_slice() else {
+        eprintln!("usage: modbus-rtu-sim <serial device> <config file>");
+        return ExitCode::FAILURE;
+    };
+
+    match run(device, PathBuf::from(config)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(device: &str, config: PathBuf) -> Result<(), String> {
+    let config = Config::load(&config)?;
+    if !UNIT_ID_RANGE.contains(&config.unit_id) {
+        return Err(format!("invalid unit ID: {}", config.unit_id));
+    }
+    let serial = &config.serial;
+    let parity = match serial.parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Even => serialport::Parity::Even,
+        Parity::Odd => serialport::Parity::Odd,
+    };
+    let stop_bits = match serial.stop_bits {
+        1 => StopBits::One,
+        2 => StopBits::Two,
+        n => return Err(format!("invalid number of stop bits: {n}")),
+    };
+
+    let mut port = serialport::new(device, serial.baud_rate)
+        .data_bits(DataBits::Eight)
+        .parity(parity)
+        .stop_bits(stop_bits)
+        .timeout(inter_frame_delay(serial.baud_rate))
+        .open()
+        .map_err(|e| format!("{device}: {e}"))?;
+
+    let mut server = ModbusServer::new(config.unit_id, SimHandler::new(&config));
+    eprintln!("serving unit {} on {device}", config.unit_id);
+
+    let mut assembler = FrameAssembler::new();
+    let mut rx = [0u8; 64];
+    loop {
+        match port.read(&mut rx) {
+            Ok(len) => {
+                for &byte in &rx[..len] {
+                    if let Some(frame) = assembler.push(byte) {
+                        serve(&mut server, port.as_mut(), frame)?;
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                if let Some(frame) = assembler.idle() {
+                    serve(&mut server, port.as_mut(), frame)?;
+                }
+            }
+            Err(e) => return Err(format!("{device}: {e}")),
+        }
+    }
+}
+
+/// Process a received frame, transmit and log the response
+fn serve(
+    server: &mut ModbusServer<SimHandler>,
+    port: &mut dyn SerialPort,
+    frame: &[u8],
+) -> Result<(), String> {
+    let mut tx = [0u8; 256];
+    let outcome = server
+        .process_frame(frame, &mut tx)
+        .map_err(|e| format!("processing failed: {e:?}"))?;
+
+    match outcome {
+        Outcome::Responded(len) => {
+            eprintln!("rx {} -> tx {}", Hex(frame), Hex(&tx[..len]));
+            port.write_all(&tx[..len])
+                .and_then(|()| port.flush())
+                .map_err(|e| format!("transmit failed: {e}"))?;
+        }
+        outcome => eprintln!("rx {} -> {outcome:?}", Hex(frame)),
+    }
+    server.transmit_complete();
+    Ok(())
+}
+
+/// Hex dump of a frame
+struct Hex<'a>(&'a [u8]);
+
+impl std::fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{byte:02X}")?;
+        }
+        Ok(())
+    }
+}
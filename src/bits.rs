@@ -0,0 +1,276 @@
+//! Views on packed bit buffers
+//!
+//! Modbus transfers coils and discrete inputs packed into bytes, the first bit being the LSB of
+//! the first byte. [`Bits`] and [`BitsMut`] give handlers access to such buffers without
+//! unpacking them into `bool` slices.
+
+/// Read only view on `len` packed bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bits<'a> {
+    data: &'a [u8],
+    len: usize,
+}
+
+impl<'a> Bits<'a> {
+    /// Create a view on the first `len` bits of `data`
+    ///
+    /// Returns `None` if `data` is too short to hold `len` bits.
+    pub fn new(data: &'a [u8], len: usize) -> Option<Self> {
+        if data.len() < len.div_ceil(8) {
+            return None;
+        }
+        Some(Self { data, len })
+    }
+
+    /// Number of bits
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the view holds no bits
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the bit at `idx`, `None` if out of range
+    pub fn get(&self, idx: usize) -> Option<bool> {
+        if idx >= self.len {
+            return None;
+        }
+        Some(self.data[idx / 8] & (1 << (idx % 8)) != 0)
+    }
+
+    /// Iterate over all bits
+    pub fn iter(&self) -> BitsIter<'a> {
+        BitsIter {
+            bits: *self,
+            idx: 0,
+        }
+    }
+
+    /// Packed bytes, unused bits of the last byte are unspecified
+    pub fn as_bytes(&self) -> &'a [u8] {
+        &self.data[..self.len.div_ceil(8)]
+    }
+
+    /// Copy the bits into a `bool` slice, which must hold at least `len` items
+    pub fn copy_to_bools(&self, out: &mut [bool]) {
+        for (slot, bit) in out[..self.len].iter_mut().zip(self.iter()) {
+            *slot = bit;
+        }
+    }
+}
+
+impl<'a> IntoIterator for Bits<'a> {
+    type Item = bool;
+    type IntoIter = BitsIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the bits of a [`Bits`] view
+#[derive(Debug, Clone)]
+pub struct BitsIter<'a> {
+    bits: Bits<'a>,
+    idx: usize,
+}
+
+impl Iterator for BitsIter<'_> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        let bit = self.bits.get(self.idx)?;
+        self.idx += 1;
+        Some(bit)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.bits.len - self.idx;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for BitsIter<'_> {}
+
+/// Mutable view on `len` packed bits
+#[derive(Debug, PartialEq, Eq)]
+pub struct BitsMut<'a> {
+    data: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> BitsMut<'a> {
+    /// Create a mutable view on the first `len` bits of `data`
+    ///
+    /// Returns `None` if `data` is too short to hold `len` bits.
+    pub fn new(data: &'a mut [u8], len: usize) -> Option<Self> {
+        if data.len() < len.div_ceil(8) {
+            return None;
+        }
+        Some(Self { data, len })
+    }
+
+    /// Number of bits
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the view holds no bits
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the bit at `idx`, `None` if out of range
+    pub fn get(&self, idx: usize) -> Option<bool> {
+        self.as_bits().get(idx)
+    }
+
+    /// Set the bit at `idx`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is out of range.
+    pub fn set(&mut self, idx: usize, value: bool) {
+        assert!(idx < self.len, "bit index {idx} out of range");
+        let mask = 1 << (idx % 8);
+        if value {
+            self.data[idx / 8] |= mask;
+        } else {
+            self.data[idx / 8] &= !mask;
+        }
+    }
+
+    /// Set all bits to `value`
+    pub fn fill(&mut self, value: bool) {
+        let byte = if value { 0xFF } else { 0x00 };
+        self.data[..self.len.div_ceil(8)].fill(byte);
+    }
+
+    /// Set the bits from a `bool` slice, which must hold at least `len` items
+    pub fn copy_from_bools(&mut self, bools: &[bool]) {
+        for (idx, value) in bools[..self.len].iter().enumerate() {
+            self.set(idx, *value);
+        }
+    }
+
+    /// Iterate over all bits
+    pub fn iter(&self) -> BitsIter<'_> {
+        self.as_bits().iter()
+    }
+
+    /// Read only view on the same bits
+    pub fn as_bits(&self) -> Bits<'_> {
+        Bits {
+            data: self.data,
+            len: self.len,
+        }
+    }
+
+    /// Mutable view on the bits `start..start + len`
+    ///
+    /// The sub-view has to start at a byte boundary, so `start` must be a multiple of 8. Returns
+    /// `None` otherwise or if the range exceeds the view.
+    pub fn slice_mut(&mut self, start: usize, len: usize) -> Option<BitsMut<'_>> {
+        if !start.is_multiple_of(8) || start + len > self.len {
+            return None;
+        }
+        BitsMut::new(&mut self.data[start / 8..], len)
+    }
+
+    /// Packed bytes for direct access, bits beyond `len` in the last byte are ignored
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data[..self.len.div_ceil(8)]
+    }
+
+    /// Clear the unused bits of the last byte, e.g. after writing through
+    /// [`BitsMut::as_bytes_mut`]
+    pub fn clear_padding(&mut self) {
+        if !self.len.is_multiple_of(8) {
+            self.data[self.len / 8] &= (1 << (self.len % 8)) - 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_and_iter() {
+        let data = [0b0000_0101, 0b0000_0010];
+        let bits = Bits::new(&data, 10).unwrap();
+
+        assert_eq!(bits.len(), 10);
+        assert_eq!(bits.get(0), Some(true));
+        assert_eq!(bits.get(1), Some(false));
+        assert_eq!(bits.get(2), Some(true));
+        assert_eq!(bits.get(9), Some(true));
+        assert_eq!(bits.get(10), None);
+
+        let mut bools = [false; 10];
+        bits.copy_to_bools(&mut bools);
+        assert_eq!(
+            bools,
+            [
+                true, false, true, false, false, false, false, false, false, true
+            ]
+        );
+        assert!(bits.iter().eq(bools.iter().copied()));
+        assert_eq!(bits.iter().len(), 10);
+    }
+
+    #[test]
+    fn too_short_buffer() {
+        assert!(Bits::new(&[0], 9).is_none());
+        assert!(BitsMut::new(&mut [0], 9).is_none());
+        assert!(Bits::new(&[], 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn set_and_fill() {
+        let mut data = [0u8; 2];
+        let mut bits = BitsMut::new(&mut data, 12).unwrap();
+
+        bits.set(0, true);
+        bits.set(11, true);
+        assert_eq!(bits.get(11), Some(true));
+        bits.set(0, false);
+        assert_eq!(bits.as_bytes_mut(), [0x00, 0x08]);
+
+        bits.fill(true);
+        bits.clear_padding();
+        assert_eq!(data, [0xFF, 0x0F]);
+    }
+
+    #[test]
+    fn copy_from_bools() {
+        let mut data = [0xFFu8; 1];
+        let mut bits = BitsMut::new(&mut data, 3).unwrap();
+
+        bits.copy_from_bools(&[false, true, false]);
+        assert_eq!(data[0], 0b1111_1010);
+    }
+
+    #[test]
+    fn sub_view() {
+        let mut data = [0u8; 3];
+        let mut bits = BitsMut::new(&mut data, 20).unwrap();
+
+        assert!(bits.slice_mut(4, 4).is_none());
+        assert!(bits.slice_mut(16, 5).is_none());
+
+        let mut sub = bits.slice_mut(8, 12).unwrap();
+        sub.set(0, true);
+        sub.set(11, true);
+        assert_eq!(data, [0x00, 0x01, 0x08]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_out_of_range() {
+        let mut data = [0u8; 1];
+        BitsMut::new(&mut data, 3).unwrap().set(3, true);
+    }
+}
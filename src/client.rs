@@ -0,0 +1,535 @@
+//! Modbus RTU client (master)
+//!
+//! The client counterpart of [`crate::ModbusServer`]: [`encode_request`] builds a request frame
+//! for one of the function codes the server supports, [`decode_response`] checks the response of
+//! the server against the request and decodes its data.
+//!
+//! Like the server, the client does not do any I/O. Errors are reported with the crate's
+//! [`Error`] type, exception responses as [`Error::Exception`].
+//!
+//! ```
+//! use modbus_server::client::{self, Request, Response};
+//!
+//! let request = Request::ReadHoldingRegisters(0x0010, 2);
+//! let mut tx = [0u8; 8];
+//! let len = client::encode_request(1, request, &mut tx).unwrap();
+//! // uart.write(&tx[..len]), then receive the response frame
+//! # let rx = [0x01, 0x03, 0x04, 0x00, 0x2A, 0x12, 0x34, 0xD6, 0x8C];
+//! # assert!(client::decode_response(1, request, &rx).is_ok());
+//!
+//! if let Ok(Response::ReadHoldingRegisters(registers)) = client::decode_response(1, request, &rx) {
+//!     assert_eq!(registers.get(0), Some(42));
+//! }
+//! ```
+
+use modbus_core::{Exception, FunctionCode, rtu::crc16};
+
+use crate::{
+    BROADCAST_ID, EXCEPTION_PDU_LEN, MAX_READ_BITS, MAX_READ_REGISTERS, MAX_WRITE_REGISTERS,
+    MIN_FRAME_LEN, UNIT_ID_RANGE, bits::Bits, check_range, error::Error, finish_frame,
+};
+
+/// Length of the request frames of reads and single writes built by [`encode_request`]
+///
+/// Write Multiple Registers requests are longer, see [`Request::frame_len`].
+pub const REQUEST_FRAME_LEN: usize = 8;
+
+/// Request to a server
+///
+/// The fields follow the Modbus requests: address and quantity for reads, address and value for
+/// writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request<'a> {
+    /// Read Coils: address, quantity
+    ReadCoils(u16, u16),
+    /// Read Discrete Inputs: address, quantity
+    ReadDiscreteInputs(u16, u16),
+    /// Read Holding Registers: address, quantity
+    ReadHoldingRegisters(u16, u16),
+    /// Read Input Registers: address, quantity
+    ReadInputRegisters(u16, u16),
+    /// Write Single Coil: address, value
+    WriteSingleCoil(u16, bool),
+    /// Write Single Register: address, value
+    WriteSingleRegister(u16, u16),
+    /// Write Multiple Registers: address, values
+    WriteMultipleRegisters(u16, &'a [u16]),
+}
+
+impl Request<'_> {
+    /// Function code of the request
+    pub fn function_code(&self) -> FunctionCode {
+        match self {
+            Request::ReadCoils(_, _) => FunctionCode::ReadCoils,
+            Request::ReadDiscreteInputs(_, _) => FunctionCode::ReadDiscreteInputs,
+            Request::ReadHoldingRegisters(_, _) => FunctionCode::ReadHoldingRegisters,
+            Request::ReadInputRegisters(_, _) => FunctionCode::ReadInputRegisters,
+            Request::WriteSingleCoil(_, _) => FunctionCode::WriteSingleCoil,
+            Request::WriteSingleRegister(_, _) => FunctionCode::WriteSingleRegister,
+            Request::WriteMultipleRegisters(_, _) => FunctionCode::WriteMultipleRegisters,
+        }
+    }
+
+    /// Returns `true` for write requests, which may be broadcast
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Request::WriteSingleCoil(_, _)
+                | Request::WriteSingleRegister(_, _)
+                | Request::WriteMultipleRegisters(_, _)
+        )
+    }
+
+    /// Length of the request frame built by [`encode_request`], including unit ID and CRC
+    pub fn frame_len(&self) -> usize {
+        match self {
+            // byte count and values after address and quantity
+            Request::WriteMultipleRegisters(_, values) => REQUEST_FRAME_LEN + 1 + 2 * values.len(),
+            _ => REQUEST_FRAME_LEN,
+        }
+    }
+
+    /// Length of the successful response frame to this request, including unit ID and CRC
+    ///
+    /// Exception responses are always 5 bytes long. Together with the frame length, this allows
+    /// the application to detect the end of a response without waiting for the bus to go idle.
+    pub fn response_len(&self) -> usize {
+        let pdu_len = match *self {
+            Request::ReadCoils(_, len) | Request::ReadDiscreteInputs(_, len) => {
+                2 + usize::from(len).div_ceil(8)
+            }
+            Request::ReadHoldingRegisters(_, len) | Request::ReadInputRegisters(_, len) => {
+                2 + 2 * usize::from(len)
+            }
+            Request::WriteSingleCoil(_, _)
+            | Request::WriteSingleRegister(_, _)
+            | Request::WriteMultipleRegisters(_, _) => 5,
+        };
+        pdu_len + 3
+    }
+
+    /// The two 16 bit fields of the request PDU
+    fn fields(&self) -> (u16, u16) {
+        match *self {
+            Request::ReadCoils(addr, len)
+            | Request::ReadDiscreteInputs(addr, len)
+            | Request::ReadHoldingRegisters(addr, len)
+            | Request::ReadInputRegisters(addr, len) => (addr, len),
+            Request::WriteSingleCoil(addr, value) => (addr, if value { 0xFF00 } else { 0x0000 }),
+            Request::WriteSingleRegister(addr, value) => (addr, value),
+            Request::WriteMultipleRegisters(addr, values) => (addr, values.len() as u16),
+        }
+    }
+}
+
+/// Decoded response of a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response<'a> {
+    /// Coil values, one per requested coil
+    ReadCoils(Bits<'a>),
+    /// Discrete input values, one per requested input
+    ReadDiscreteInputs(Bits<'a>),
+    /// Holding register values, one per requested register
+    ReadHoldingRegisters(Registers<'a>),
+    /// Input register values, one per requested register
+    ReadInputRegisters(Registers<'a>),
+    /// Echo of a written coil: address, value
+    WriteSingleCoil(u16, bool),
+    /// Echo of a written register: address, value
+    WriteSingleRegister(u16, u16),
+    /// Echo of written registers: address, quantity
+    WriteMultipleRegisters(u16, u16),
+}
+
+/// Read only view on big endian register values in a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Registers<'a> {
+    /// Number of registers
+    pub fn len(&self) -> usize {
+        self.data.len() / 2
+    }
+
+    /// Returns `true` if the view holds no registers
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Get the register at `idx`, `None` if out of range
+    pub fn get(&self, idx: usize) -> Option<u16> {
+        let bytes = self.data.get(2 * idx..2 * idx + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Iterate over all registers
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = u16> + 'a {
+        self.data
+            .chunks_exact(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Copy the registers into a slice, which must hold at least `len` items
+    pub fn copy_to(&self, out: &mut [u16]) {
+        for (slot, value) in out[..self.len()].iter_mut().zip(self.iter()) {
+            *slot = value;
+        }
+    }
+
+    /// Raw big endian bytes of the registers
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Build the RTU frame of a request to `unit_id`
+///
+/// Returns the length of the frame, see [`Request::frame_len`].
+///
+/// # Errors
+///
+/// * [`Error::InvalidValue`] - `unit_id` is neither in [`crate::UNIT_ID_RANGE`] nor a broadcast
+///   of a write request, or a read or write quantity is 0 or exceeds the Modbus limits.
+/// * [`Error::InvalidAddress`] - The request exceeds the 16 bit address space.
+/// * [`Error::BufferTooSmall`] - `buf` is shorter than [`Request::frame_len`].
+pub fn encode_request(unit_id: u8, request: Request, buf: &mut [u8]) -> Result<usize, Error> {
+    let broadcast = unit_id == BROADCAST_ID && request.is_write();
+    if !broadcast && !UNIT_ID_RANGE.contains(&unit_id) {
+        return Err(Error::InvalidValue);
+    }
+    match request {
+        Request::ReadCoils(addr, len) | Request::ReadDiscreteInputs(addr, len) => {
+            check_range(addr, len, MAX_READ_BITS)?
+        }
+        Request::ReadHoldingRegisters(addr, len) | Request::ReadInputRegisters(addr, len) => {
+            check_range(addr, len, MAX_READ_REGISTERS)?
+        }
+        Request::WriteMultipleRegisters(addr, values) => {
+            let len = u16::try_from(values.len()).map_err(|_| Error::InvalidValue)?;
+            check_range(addr, len, MAX_WRITE_REGISTERS)?
+        }
+        Request::WriteSingleCoil(_, _) | Request::WriteSingleRegister(_, _) => {}
+    }
+    if buf.len() < request.frame_len() {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let (first, second) = request.fields();
+    buf[1] = request.function_code().value();
+    buf[2..4].copy_from_slice(&first.to_be_bytes());
+    buf[4..6].copy_from_slice(&second.to_be_bytes());
+    let mut pdu_len = 5;
+    if let Request::WriteMultipleRegisters(_, values) = request {
+        buf[6] = (2 * values.len()) as u8;
+        for (bytes, value) in buf[7..].chunks_exact_mut(2).zip(values) {
+            bytes.copy_from_slice(&value.to_be_bytes());
+        }
+        pdu_len += 1 + 2 * values.len();
+    }
+    Ok(finish_frame(buf, unit_id, pdu_len))
+}
+
+/// Check the response frame of a server against its request and decode it
+///
+/// # Errors
+///
+/// * [`Error::Crc`] - The CRC of the frame does not match.
+/// * [`Error::Exception`] - The server answered with an exception response.
+/// * [`Error::InvalidResponse`] - The frame is not a valid response to `request` from
+///   `unit_id`: wrong length, unit ID, function code, byte count or echoed values.
+pub fn decode_response<'a>(
+    unit_id: u8,
+    request: Request,
+    frame: &'a [u8],
+) -> Result<Response<'a>, Error> {
+    if frame.len() < MIN_FRAME_LEN {
+        return Err(Error::InvalidResponse);
+    }
+    let (adu, crc) = frame.split_at(frame.len() - 2);
+    if crc16(adu) != u16::from_be_bytes([crc[0], crc[1]]) {
+        return Err(Error::Crc);
+    }
+    if adu[0] != unit_id {
+        return Err(Error::InvalidResponse);
+    }
+
+    let pdu = &adu[1..];
+    let function_code = request.function_code().value();
+    if pdu[0] == function_code | 0x80 && pdu.len() == EXCEPTION_PDU_LEN {
+        let exception = Exception::try_from(pdu[1]).map_err(|_| Error::InvalidResponse)?;
+        return Err(Error::Exception(exception));
+    }
+    if pdu[0] != function_code || frame.len() != request.response_len() {
+        return Err(Error::InvalidResponse);
+    }
+
+    let response = match request {
+        Request::ReadCoils(_, len) | Request::ReadDiscreteInputs(_, len) => {
+            let bits = read_data(pdu)
+                .and_then(|data| Bits::new(data, len.into()))
+                .ok_or(Error::InvalidResponse)?;
+            match request {
+                Request::ReadCoils(_, _) => Response::ReadCoils(bits),
+                _ => Response::ReadDiscreteInputs(bits),
+            }
+        }
+        Request::ReadHoldingRegisters(_, _) | Request::ReadInputRegisters(_, _) => {
+            let registers = Registers {
+                data: read_data(pdu).ok_or(Error::InvalidResponse)?,
+            };
+            match request {
+                Request::ReadHoldingRegisters(_, _) => Response::ReadHoldingRegisters(registers),
+                _ => Response::ReadInputRegisters(registers),
+            }
+        }
+        Request::WriteSingleCoil(addr, value) => {
+            check_echo(request, pdu)?;
+            Response::WriteSingleCoil(addr, value)
+        }
+        Request::WriteSingleRegister(addr, value) => {
+            check_echo(request, pdu)?;
+            Response::WriteSingleRegister(addr, value)
+        }
+        Request::WriteMultipleRegisters(addr, values) => {
+            check_echo(request, pdu)?;
+            Response::WriteMultipleRegisters(addr, values.len() as u16)
+        }
+    };
+    Ok(response)
+}
+
+/// Data of a read response PDU, `None` if the byte count does not match the PDU length
+fn read_data(pdu: &[u8]) -> Option<&[u8]> {
+    let data = &pdu[2..];
+    (usize::from(pdu[1]) == data.len()).then_some(data)
+}
+
+/// Check the echoed fields of a write response
+fn check_echo(request: Request, pdu: &[u8]) -> Result<(), Error> {
+    let (first, second) = request.fields();
+    if pdu[1..3] != first.to_be_bytes() || pdu[3..5] != second.to_be_bytes() {
+        return Err(Error::InvalidResponse);
+    }
+    Ok(())
+}
+
+#[cfg(all(
+    test,
+    feature = "coils",
+    feature = "discrete-inputs",
+    feature = "holding-registers",
+    feature = "input-registers"
+))]
+mod tests {
+    use super::*;
+    use crate::{ModbusServer, Outcome, handler::ModbusHandler, testing::set_crc};
+    use modbus_core::rtu::MAX_FRAME_LEN;
+
+    struct Counter;
+
+    impl ModbusHandler for Counter {
+        fn read_coils(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [bool],
+        ) -> Result<usize, Error> {
+            for (i, coil) in out.iter_mut().enumerate() {
+                *coil = (addr + i).is_multiple_of(3);
+            }
+            Ok(len)
+        }
+
+        fn read_input_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            for (i, value) in out.iter_mut().enumerate() {
+                *value = (addr + i) as u16;
+            }
+            Ok(len)
+        }
+
+        fn write_registers(
+            &mut self,
+            _addr: usize,
+            len: usize,
+            buf: &[u16],
+        ) -> Result<usize, Error> {
+            if buf.contains(&0) {
+                return Err(Error::InvalidValue);
+            }
+            Ok(len)
+        }
+    }
+
+    /// Send a request to a server and decode its response
+    fn transfer(request: Request) -> Result<(usize, [u8; 256]), Error> {
+        let mut server = ModbusServer::new(3, Counter);
+        let mut tx = [0u8; MAX_FRAME_LEN];
+        let mut rx = [0u8; 256];
+        let len = encode_request(3, request, &mut tx)?;
+        match server.process_frame(&tx[..len], &mut rx)? {
+            Outcome::Responded(len) => Ok((len, rx)),
+            outcome => panic!("no response: {outcome:?}"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let request = Request::ReadCoils(1, 10);
+        let (len, rx) = transfer(request).unwrap();
+        assert_eq!(len, request.response_len());
+        let Response::ReadCoils(bits) = decode_response(3, request, &rx[..len]).unwrap() else {
+            panic!("wrong response type");
+        };
+        assert!(
+            bits.iter()
+                .eq((1usize..11).map(|addr| addr.is_multiple_of(3)))
+        );
+
+        let request = Request::ReadInputRegisters(100, 125);
+        let (len, rx) = transfer(request).unwrap();
+        let Response::ReadInputRegisters(registers) =
+            decode_response(3, request, &rx[..len]).unwrap()
+        else {
+            panic!("wrong response type");
+        };
+        assert_eq!(registers.len(), 125);
+        assert!(registers.iter().eq(100..225));
+
+        let request = Request::WriteSingleRegister(7, 0xABCD);
+        let (len, rx) = transfer(request).unwrap();
+        assert_eq!(
+            decode_response(3, request, &rx[..len]),
+            Ok(Response::WriteSingleRegister(7, 0xABCD))
+        );
+
+        let values: [u16; 123] = core::array::from_fn(|i| 0xFF85 + i as u16);
+        let request = Request::WriteMultipleRegisters(0xFF85, &values);
+        assert_eq!(request.frame_len(), 255);
+        let (len, rx) = transfer(request).unwrap();
+        assert_eq!(
+            decode_response(3, request, &rx[..len]),
+            Ok(Response::WriteMultipleRegisters(0xFF85, 123))
+        );
+        let request = Request::WriteMultipleRegisters(7, &[7, 0]);
+        let (len, rx) = transfer(request).unwrap();
+        assert_eq!(
+            decode_response(3, request, &rx[..len]),
+            Err(Error::Exception(Exception::IllegalDataValue))
+        );
+
+        // discrete inputs are not implemented by the handler
+        let request = Request::ReadDiscreteInputs(0, 1);
+        let (len, rx) = transfer(request).unwrap();
+        assert_eq!(len, 5);
+        assert_eq!(
+            decode_response(3, request, &rx[..len]),
+            Err(Error::Exception(Exception::IllegalFunction))
+        );
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let mut buf = [0u8; REQUEST_FRAME_LEN];
+        assert_eq!(
+            encode_request(1, Request::ReadCoils(0, 2001), &mut buf),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            encode_request(1, Request::ReadHoldingRegisters(0xFFFF, 2), &mut buf),
+            Err(Error::InvalidAddress)
+        );
+        assert_eq!(
+            encode_request(0, Request::ReadHoldingRegisters(0, 1), &mut buf),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            encode_request(248, Request::WriteSingleCoil(0, true), &mut buf),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            encode_request(1, Request::WriteSingleCoil(0, true), &mut buf[..7]),
+            Err(Error::BufferTooSmall)
+        );
+        assert_eq!(
+            encode_request(0, Request::WriteSingleCoil(0x0102, true), &mut buf),
+            Ok(8)
+        );
+        assert_eq!(buf[..6], [0x00, 0x05, 0x01, 0x02, 0xFF, 0x00]);
+
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        assert_eq!(
+            encode_request(1, Request::WriteMultipleRegisters(0, &[]), &mut buf),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            encode_request(1, Request::WriteMultipleRegisters(0, &[0; 124]), &mut buf),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            encode_request(
+                1,
+                Request::WriteMultipleRegisters(0xFFFF, &[0; 2]),
+                &mut buf
+            ),
+            Err(Error::InvalidAddress)
+        );
+        let request = Request::WriteMultipleRegisters(0x0102, &[0x0304, 0x0506]);
+        assert_eq!(
+            encode_request(1, request, &mut buf[..12]),
+            Err(Error::BufferTooSmall)
+        );
+        assert_eq!(encode_request(0, request, &mut buf), Ok(13));
+        assert_eq!(
+            buf[..11],
+            [
+                0x00, 0x10, 0x01, 0x02, 0x00, 0x02, 0x04, 0x03, 0x04, 0x05, 0x06
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_responses() {
+        let request = Request::ReadHoldingRegisters(0, 1);
+        let mut frame = [0x03, 0x03, 0x02, 0x12, 0x34, 0x00, 0x00];
+        set_crc(&mut frame);
+        assert!(decode_response(3, request, &frame).is_ok());
+
+        assert_eq!(
+            decode_response(4, request, &frame),
+            Err(Error::InvalidResponse)
+        );
+        assert_eq!(
+            decode_response(3, Request::ReadInputRegisters(0, 1), &frame),
+            Err(Error::InvalidResponse)
+        );
+        assert_eq!(
+            decode_response(3, Request::ReadHoldingRegisters(0, 2), &frame),
+            Err(Error::InvalidResponse)
+        );
+        assert_eq!(decode_response(3, request, &frame[..6]), Err(Error::Crc));
+
+        // byte count does not match the frame length
+        let mut frame = [0x03, 0x03, 0x03, 0x12, 0x34, 0x00, 0x00];
+        set_crc(&mut frame);
+        assert_eq!(
+            decode_response(3, request, &frame),
+            Err(Error::InvalidResponse)
+        );
+
+        // echo of a different value
+        let request = Request::WriteSingleCoil(1, false);
+        let mut frame = [0x03, 0x05, 0x00, 0x01, 0xFF, 0x00, 0x00, 0x00];
+        set_crc(&mut frame);
+        assert_eq!(
+            decode_response(3, request, &frame),
+            Err(Error::InvalidResponse)
+        );
+    }
+}
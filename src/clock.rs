@@ -0,0 +1,23 @@
+//! Time source for timeouts and measurements
+//!
+//! The crate has no notion of time on its own. Components which need one take a [`Clock`]
+//! provided by the application, e.g. backed by a hardware timer.
+
+use core::time::Duration;
+
+/// Monotonic time source supplied by the application
+pub trait Clock {
+    /// Time elapsed since an arbitrary but fixed point in time
+    ///
+    /// The value must never decrease.
+    fn now(&self) -> Duration;
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> Duration,
+{
+    fn now(&self) -> Duration {
+        self()
+    }
+}
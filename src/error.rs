@@ -1,4 +1,4 @@
-
+#[cfg_attr(all(feature = "defmt", target_os = "none"), derive(defmt::Format))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
     /// Provided buffer is too small
@@ -11,6 +11,14 @@ pub enum Error {
     NotSupported,
     /// Application Error
     Application,
+    /// Handler reported a different number of processed items than requested
+    LengthMismatch,
+    /// CRC of a received frame does not match its content
+    Crc,
+    /// Response does not match its request (unit ID, function code, quantity or echoed values)
+    InvalidResponse,
+    /// The server answered with an exception response
+    Exception(modbus_core::Exception),
 }
 
 /// Map crate error codes to modbus exceptions (if applicable)
@@ -19,8 +27,11 @@ pub fn map_exception(err: Error) -> modbus_core::Exception {
         Error::InvalidAddress => modbus_core::Exception::IllegalDataAddress,
         Error::InvalidValue => modbus_core::Exception::IllegalDataValue,
         Error::NotSupported => modbus_core::Exception::IllegalFunction,
-        Error::Application | Error::BufferTooSmall => {
-            modbus_core::Exception::ServerDeviceFailure
-        }
+        Error::Exception(exception) => exception,
+        Error::Application
+        | Error::BufferTooSmall
+        | Error::LengthMismatch
+        | Error::Crc
+        | Error::InvalidResponse => modbus_core::Exception::ServerDeviceFailure,
     }
 }
@@ -0,0 +1,103 @@
+//! Protocol tracing
+//!
+//! The macros forward to `log` and / or `defmt`, depending on the enabled features. Without
+//! either feature they expand to nothing. `defmt` is only used on bare-metal targets
+//! (`target_os = "none"`), like in `modbus-core`.
+//!
+//! Format strings are limited to the common subset of both crates: `{}` and `{:?}` without
+//! further formatting options.
+
+macro_rules! trace {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "log")]
+        ::log::trace!($s $(, $x)*);
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        ::defmt::trace!($s $(, $x)*);
+        #[cfg(not(any(feature = "log", all(feature = "defmt", target_os = "none"))))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+macro_rules! debug {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "log")]
+        ::log::debug!($s $(, $x)*);
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        ::defmt::debug!($s $(, $x)*);
+        #[cfg(not(any(feature = "log", all(feature = "defmt", target_os = "none"))))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+macro_rules! warn {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!($s $(, $x)*);
+        #[cfg(all(feature = "defmt", target_os = "none"))]
+        ::defmt::warn!($s $(, $x)*);
+        #[cfg(not(any(feature = "log", all(feature = "defmt", target_os = "none"))))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+#[cfg(all(test, feature = "log", feature = "holding-registers"))]
+mod tests {
+    extern crate std;
+
+    use std::{format, string::String, sync::Mutex, vec::Vec};
+
+    use crate::{ModbusServer, Outcome, handler::ModbusHandler};
+
+    /// Messages of all tests, which run in parallel
+    static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Logger;
+
+    impl log::Log for Logger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let message = format!("{} {}", record.level(), record.args());
+            MESSAGES.lock().unwrap().push(message);
+        }
+
+        fn flush(&self) {}
+    }
+
+    struct NoRegisters;
+
+    impl ModbusHandler for NoRegisters {}
+
+    #[test]
+    fn trace_frames() {
+        log::set_logger(&Logger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut server = ModbusServer::new(0x11, NoRegisters);
+        let mut tx = [0u8; 256];
+        // CRC error, and a read of unsupported registers
+        let frames: [&[u8]; 2] = [
+            &[0x11, 0x03, 0x00, 0x6B, 0x00, 0x03, 0x00, 0x00],
+            &[0x11, 0x03, 0x00, 0x6B, 0x00, 0x03, 0x76, 0x87],
+        ];
+        assert_eq!(
+            server.process_frame(frames[0], &mut tx),
+            Ok(Outcome::CrcError)
+        );
+        assert_eq!(
+            server.process_frame(frames[1], &mut tx),
+            Ok(Outcome::Responded(5))
+        );
+
+        let messages = MESSAGES.lock().unwrap();
+        for expected in [
+            "DEBUG CRC error in frame for unit 17",
+            "DEBUG dispatching ReadHoldingRegisters",
+            "DEBUG ReadHoldingRegisters failed: NotSupported, responding with IllegalFunction",
+        ] {
+            assert!(messages.iter().any(|m| m == expected), "{messages:?}");
+        }
+    }
+}
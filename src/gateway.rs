@@ -0,0 +1,352 @@
+//! Modbus TCP to RTU gateway
+//!
+//! The [`Gateway`] forwards Modbus TCP requests as RTU frames to slaves on a downstream serial
+//! bus and translates their responses back. The PDUs are passed through unchanged, so the
+//! gateway supports every function code the downstream slaves do.
+//!
+//! The gateway does not do any I/O. The application passes the received TCP and RTU frames in
+//! and sends the frames described by the returned [`GatewayAction`]. RTU is half duplex, so only
+//! one request is forwarded at a time.
+
+use core::{ops::RangeInclusive, time::Duration};
+
+use modbus_core::{
+    Exception,
+    rtu::{MAX_FRAME_LEN, crc16},
+};
+
+use crate::{
+    BROADCAST_ID, EXCEPTION_PDU_LEN, MIN_FRAME_LEN,
+    clock::Clock,
+    error::Error,
+    tcp::{self, MBAP_HEADER_LEN, MbapHeader},
+};
+
+/// What the application has to do after a call to the [`Gateway`]
+///
+/// All frames are written to the `out` buffer passed to the call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayAction {
+    /// Send the RTU request frame of the given length to the downstream bus and pass the
+    /// response to [`Gateway::handle_response`]
+    SendRtu(usize),
+    /// Send the TCP response frame of the given length to the master
+    SendTcp(usize),
+    /// The TCP frame has no valid MBAP header, the application should close the connection
+    Malformed,
+    /// Nothing to send
+    Idle,
+}
+
+/// Request forwarded to the downstream bus, waiting for its response
+struct Pending {
+    /// Header of the TCP request, restored in the response
+    header: MbapHeader,
+    /// Function code of the request
+    function_code: u8,
+    /// When the request was handed out
+    sent_at: Duration,
+}
+
+/// Modbus TCP to RTU gateway
+///
+/// Requests are routed by the unit ID in the MBAP header:
+///
+/// * Unit IDs within one of the routes are forwarded to the downstream bus.
+/// * All other unit IDs, including 0, are answered with a GatewayPathUnavailable exception.
+///
+/// Like [`crate::ModbusServer::process_tcp_frame`], the gateway treats unit ID 0 as no broadcast:
+/// a master waiting for a response would not get any to a forwarded RTU broadcast. There is no
+/// downstream slave with unit ID 0, so the request cannot be routed.
+///
+/// If no matching response arrives within the timeout, the master receives a
+/// GatewayTargetDeviceFailedToRespond exception. Time is measured with the [`Clock`] supplied
+/// by the application, so [`Gateway::poll`] has to be called regularly while a request is
+/// pending.
+pub struct Gateway<C, const N: usize> {
+    /// Time source for the response timeout
+    clock: C,
+    /// Unit IDs reachable on the downstream bus
+    routes: [RangeInclusive<u8>; N],
+    /// Time to wait for a downstream response
+    timeout: Duration,
+    /// Forwarded request waiting for its response
+    pending: Option<Pending>,
+}
+
+impl<C, const N: usize> Gateway<C, N>
+where
+    C: Clock,
+{
+    /// Create a new gateway
+    ///
+    /// # Parameters
+    ///
+    /// * `clock` - Time source for the response timeout.
+    /// * `routes` - Unit IDs reachable on the downstream bus, e.g. `[1..=247]` to forward all.
+    /// * `timeout` - Time to wait for a downstream response, starting when the request is
+    ///   handed out by [`Gateway::handle_request`].
+    pub fn new(clock: C, routes: [RangeInclusive<u8>; N], timeout: Duration) -> Self {
+        Self {
+            clock,
+            routes,
+            timeout,
+            pending: None,
+        }
+    }
+
+    /// Returns `true` if a forwarded request is waiting for its response
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Process a complete Modbus TCP request frame
+    ///
+    /// Translates the request into an RTU frame for the downstream bus
+    /// ([`GatewayAction::SendRtu`]). Requests for unknown unit IDs, and requests received while another one is pending, are answered right away
+    /// with a GatewayPathUnavailable or ServerDeviceBusy exception ([`GatewayAction::SendTcp`]).
+    ///
+    /// Returns [`Error::BufferTooSmall`] if the frame does not fit into `out`, the request is
+    /// dropped in this case.
+    pub fn handle_request(
+        &mut self,
+        tcp_rx: &[u8],
+        out: &mut [u8],
+    ) -> Result<GatewayAction, Error> {
+        let Some((header, pdu)) = tcp::decode(tcp_rx) else {
+            return Ok(GatewayAction::Malformed);
+        };
+        let function_code = pdu[0];
+
+        let routed = self.routes.iter().any(|r| r.contains(&header.unit_id));
+        if header.unit_id == BROADCAST_ID || !routed {
+            return exception_response(
+                header,
+                function_code,
+                Exception::GatewayPathUnavailable,
+                out,
+            );
+        }
+        if self.pending.is_some() {
+            return exception_response(header, function_code, Exception::ServerDeviceBusy, out);
+        }
+
+        let len = pdu.len() + 3;
+        if out.len() < len {
+            return Err(Error::BufferTooSmall);
+        }
+        out[0] = header.unit_id;
+        out[1..len - 2].copy_from_slice(pdu);
+        let crc = crc16(&out[..len - 2]);
+        out[len - 2..len].copy_from_slice(&crc.to_be_bytes());
+
+        self.pending = Some(Pending {
+            header,
+            function_code,
+            sent_at: self.clock.now(),
+        });
+        Ok(GatewayAction::SendRtu(len))
+    }
+
+    /// Process a complete RTU frame received from the downstream bus
+    ///
+    /// If the frame is the response to the pending request (valid CRC, same unit ID and function
+    /// code), it is translated into a TCP response with the transaction ID of the request
+    /// ([`GatewayAction::SendTcp`]). Any other frame is ignored ([`GatewayAction::Idle`]) and the
+    /// gateway keeps waiting.
+    pub fn handle_response(
+        &mut self,
+        rtu_rx: &[u8],
+        out: &mut [u8],
+    ) -> Result<GatewayAction, Error> {
+        let Some(pending) = &self.pending else {
+            return Ok(GatewayAction::Idle);
+        };
+        if rtu_rx.len() < MIN_FRAME_LEN || rtu_rx.len() > MAX_FRAME_LEN {
+            return Ok(GatewayAction::Idle);
+        }
+        let (adu, crc) = rtu_rx.split_at(rtu_rx.len() - 2);
+        if crc16(adu) != u16::from_be_bytes([crc[0], crc[1]]) {
+            return Ok(GatewayAction::Idle);
+        }
+        let (unit_id, pdu) = (adu[0], &adu[1..]);
+        if unit_id != pending.header.unit_id || pdu[0] & 0x7F != pending.function_code {
+            return Ok(GatewayAction::Idle);
+        }
+
+        let len = MBAP_HEADER_LEN + pdu.len();
+        if out.len() < len {
+            return Err(Error::BufferTooSmall);
+        }
+        out[MBAP_HEADER_LEN..len].copy_from_slice(pdu);
+        let len = tcp::encode_header(out, pending.header, pdu.len());
+        self.pending = None;
+        Ok(GatewayAction::SendTcp(len))
+    }
+
+    /// Check the response timeout of the pending request
+    ///
+    /// Once the timeout has expired, the pending request is dropped and the master is answered
+    /// with a GatewayTargetDeviceFailedToRespond exception ([`GatewayAction::SendTcp`]).
+    pub fn poll(&mut self, out: &mut [u8]) -> Result<GatewayAction, Error> {
+        let Some(pending) = &self.pending else {
+            return Ok(GatewayAction::Idle);
+        };
+        if self.clock.now().saturating_sub(pending.sent_at) < self.timeout {
+            return Ok(GatewayAction::Idle);
+        }
+
+        let action = exception_response(
+            pending.header,
+            pending.function_code,
+            Exception::GatewayTargetDevice,
+            out,
+        )?;
+        self.pending = None;
+        Ok(action)
+    }
+}
+
+/// Write a TCP exception response for a request
+fn exception_response(
+    header: MbapHeader,
+    function_code: u8,
+    exception: Exception,
+    out: &mut [u8],
+) -> Result<GatewayAction, Error> {
+    if out.len() < MBAP_HEADER_LEN + EXCEPTION_PDU_LEN {
+        return Err(Error::BufferTooSmall);
+    }
+    out[MBAP_HEADER_LEN] = function_code | 0x80;
+    out[MBAP_HEADER_LEN + 1] = exception as u8;
+    let len = tcp::encode_header(out, header, EXCEPTION_PDU_LEN);
+    Ok(GatewayAction::SendTcp(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::set_crc;
+    use core::cell::Cell;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Read 2 holding registers at 0x0010 from `unit_id`
+    fn read_request(transaction_id: u16, unit_id: u8) -> [u8; 12] {
+        let [hi, lo] = transaction_id.to_be_bytes();
+        [
+            hi, lo, 0x00, 0x00, 0x00, 0x06, unit_id, 0x03, 0x00, 0x10, 0x00, 0x02,
+        ]
+    }
+
+    #[test]
+    fn forward_request() {
+        let now = Cell::new(Duration::ZERO);
+        let mut gateway = Gateway::new(|| now.get(), [5..=6], TIMEOUT);
+        let mut out = [0u8; 260];
+
+        let action = gateway
+            .handle_request(&read_request(0xBEEF, 5), &mut out)
+            .unwrap();
+        assert_eq!(action, GatewayAction::SendRtu(8));
+        let mut expected = [0x05, 0x03, 0x00, 0x10, 0x00, 0x02, 0, 0];
+        set_crc(&mut expected);
+        assert_eq!(out[..8], expected);
+        assert!(gateway.is_pending());
+
+        // responses with a bad CRC or from another unit are ignored
+        let mut response = [0x05, 0x03, 0x04, 0x12, 0x34, 0x56, 0x78, 0, 0];
+        assert_eq!(
+            gateway.handle_response(&response, &mut out),
+            Ok(GatewayAction::Idle)
+        );
+        response[0] = 6;
+        set_crc(&mut response);
+        assert_eq!(
+            gateway.handle_response(&response, &mut out),
+            Ok(GatewayAction::Idle)
+        );
+
+        response[0] = 5;
+        set_crc(&mut response);
+        let action = gateway.handle_response(&response, &mut out).unwrap();
+        assert_eq!(action, GatewayAction::SendTcp(13));
+        assert_eq!(
+            out[..13],
+            [
+                0xBE, 0xEF, 0x00, 0x00, 0x00, 0x07, 0x05, 0x03, 0x04, 0x12, 0x34, 0x56, 0x78
+            ]
+        );
+        assert!(!gateway.is_pending());
+    }
+
+    #[test]
+    fn routing_failures() {
+        let now = Cell::new(Duration::ZERO);
+        let mut gateway = Gateway::new(|| now.get(), [5..=6], TIMEOUT);
+        let mut out = [0u8; 260];
+
+        // unit 7 is not routed
+        let action = gateway
+            .handle_request(&read_request(1, 7), &mut out)
+            .unwrap();
+        assert_eq!(action, GatewayAction::SendTcp(9));
+        assert_eq!(
+            out[..9],
+            [0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x07, 0x83, 0x0A]
+        );
+
+        // a second request while the first one is pending
+        gateway
+            .handle_request(&read_request(2, 5), &mut out)
+            .unwrap();
+        gateway
+            .handle_request(&read_request(3, 6), &mut out)
+            .unwrap();
+        assert_eq!(
+            out[..9],
+            [0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x06, 0x83, 0x06]
+        );
+
+        // timeout of the first request
+        now.set(TIMEOUT - Duration::from_millis(1));
+        assert_eq!(gateway.poll(&mut out), Ok(GatewayAction::Idle));
+        now.set(TIMEOUT);
+        assert_eq!(gateway.poll(&mut out), Ok(GatewayAction::SendTcp(9)));
+        assert_eq!(
+            out[..9],
+            [0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0x05, 0x83, 0x0B]
+        );
+        assert!(!gateway.is_pending());
+        assert_eq!(gateway.poll(&mut out), Ok(GatewayAction::Idle));
+    }
+
+    #[test]
+    fn no_broadcast_and_malformed() {
+        let mut gateway = Gateway::new(|| Duration::ZERO, [0..=247], TIMEOUT);
+        let mut out = [0u8; 260];
+
+        // unit ID 0 is no broadcast over TCP, also when the route includes it
+        let action = gateway
+            .handle_request(&read_request(1, 0), &mut out)
+            .unwrap();
+        assert_eq!(action, GatewayAction::SendTcp(9));
+        assert_eq!(
+            out[..9],
+            [0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x00, 0x83, 0x0A]
+        );
+        assert!(!gateway.is_pending());
+
+        let mut request = read_request(1, 1);
+        request[5] = 0x07;
+        assert_eq!(
+            gateway.handle_request(&request, &mut out),
+            Ok(GatewayAction::Malformed)
+        );
+        assert_eq!(
+            gateway.handle_request(&read_request(1, 1), &mut out[..7]),
+            Err(Error::BufferTooSmall)
+        );
+        assert!(!gateway.is_pending());
+    }
+}
@@ -1,25 +1,71 @@
+#[cfg(feature = "coils")]
+use crate::bits::Bits;
+#[cfg(any(
+    feature = "coils",
+    feature = "discrete-inputs",
+    feature = "holding-registers",
+    feature = "input-registers"
+))]
 use crate::error::Error;
+#[cfg(any(feature = "coils", feature = "discrete-inputs"))]
+use crate::{bits::BitsMut, check_count};
+
+/// Maximum number of coils or discrete inputs passed to a single call of a `bool` slice method
+///
+/// The default implementations of the `*_packed` methods split larger requests into several calls
+/// of [`ModbusHandler::read_coils`], [`ModbusHandler::read_discrete_input`] or
+/// [`ModbusHandler::write_coils`], so the server only needs a small buffer on the stack.
+pub const MAX_CHUNK_LEN: usize = 32;
 
 /// Trait for defining handlers for access to the Modbus data
 ///
 /// The user application holds all data and defines the access including application side effects.
 /// This trait implements defaults (not supported) so the user can choose to only implement the ones
 /// actually needed
+///
+/// On success, every method returns the number of processed items, which has to match the
+/// requested `len`. The server answers any other count with a ServerDeviceFailure exception
+/// (see [`Error::LengthMismatch`]).
+///
+/// Quantities and address ranges are checked against the limits of the Modbus specification
+/// before a handler is called: `len` is never 0 or larger than a single request allows, and
+/// `addr + len` never exceeds the 16 bit address space.
+///
+/// Register reads and writes are passed to the handler in a single call with the whole range of
+/// the request, so values spanning several registers are read and written consistently. The
+/// registers of a read are written directly into the response in the tx buffer.
+///
+/// Coils and discrete inputs are transferred packed into bytes. The server calls the `*_packed`
+/// methods with the whole range of the request, which hand out views on the packed data (see
+/// [`crate::bits`]). Their default implementations are a compatibility layer calling the `bool`
+/// slice methods in chunks of at most [`MAX_CHUNK_LEN`] items, so a handler implements either of
+/// them. Implement the `*_packed` methods to see the whole request at once.
+///
+/// The methods of each data type are only available if the corresponding cargo feature
+/// (`coils`, `discrete-inputs`, `holding-registers`, `input-registers`) is enabled.
 pub trait ModbusHandler {
     /// Read Coils
+    ///
+    /// Called by the default [`ModbusHandler::read_coils_packed`] once per chunk of at most
+    /// [`MAX_CHUNK_LEN`] coils, until the request is complete or an error is returned.
     /// # Arguments
-    /// - `addr`: Data adress from Modbus request
-    /// - `len`: Number of Data from Modbus request
-    /// - `out`: output of the requested Coil values. The output buffer is guaranteed to hold the maximum number of coils in one Modbus request (2000)
+    /// - `addr`: Data adress of the chunk
+    /// - `len`: Number of Data of the chunk
+    /// - `out`: output of the requested Coil values. The output buffer holds exactly `len` items
+    #[cfg(feature = "coils")]
     fn read_coils(&mut self, _addr: usize, _len: usize, _out: &mut [bool]) -> Result<usize, Error> {
         Err(Error::NotSupported)
     }
 
     /// Read Discrete Inputs
+    ///
+    /// Called by the default [`ModbusHandler::read_discrete_input_packed`] once per chunk of at
+    /// most [`MAX_CHUNK_LEN`] inputs, until the request is complete or an error is returned.
     /// # Arguments
-    /// - `addr`: Data adress from Modbus request
-    /// - `len`: Number of Data from Modbus request
-    /// - `out`: output of the requested Coil values. The output buffer is guaranteed to hold the maximum number of coils in one Modbus request (2000)
+    /// - `addr`: Data adress of the chunk
+    /// - `len`: Number of Data of the chunk
+    /// - `out`: output of the requested Coil values. The output buffer holds exactly `len` items
+    #[cfg(feature = "discrete-inputs")]
     fn read_discrete_input(
         &mut self,
         _addr: usize,
@@ -29,11 +75,42 @@ pub trait ModbusHandler {
         Err(Error::NotSupported)
     }
 
+    /// Read Coils into a packed bit buffer
+    ///
+    /// Called once with the whole request, `out` is a view on the response in the tx buffer.
+    /// # Arguments
+    /// - `addr`: Data adress from Modbus request
+    /// - `out`: output of the requested Coil values, `out.len()` is the number of Coils from the Modbus request. All bits are cleared before the call
+    #[cfg(feature = "coils")]
+    fn read_coils_packed(&mut self, addr: usize, out: &mut BitsMut) -> Result<usize, Error> {
+        read_bits_chunked(addr, out, |addr, len, buf| self.read_coils(addr, len, buf))
+    }
+
+    /// Read Discrete Inputs into a packed bit buffer
+    ///
+    /// Called once with the whole request, `out` is a view on the response in the tx buffer.
+    /// # Arguments
+    /// - `addr`: Data adress from Modbus request
+    /// - `out`: output of the requested input values, `out.len()` is the number of inputs from the Modbus request. All bits are cleared before the call
+    #[cfg(feature = "discrete-inputs")]
+    fn read_discrete_input_packed(
+        &mut self,
+        addr: usize,
+        out: &mut BitsMut,
+    ) -> Result<usize, Error> {
+        read_bits_chunked(addr, out, |addr, len, buf| {
+            self.read_discrete_input(addr, len, buf)
+        })
+    }
+
     /// Read Holding Registers
+    ///
+    /// Called once with the whole request, `out` lies in the tx buffer.
     /// # Arguments
     /// - `addr`: Data adress from Modbus request
     /// - `len`: Number of Data from Modbus request
-    /// - `out`: output of the requested register values. The output buffer is guaranteed to hold the maximum number of Registers in one Modbus request (125)
+    /// - `out`: output of the requested register values. The output buffer holds exactly `len` items
+    #[cfg(feature = "holding-registers")]
     fn read_holding_registers(
         &mut self,
         _addr: usize,
@@ -44,10 +121,13 @@ pub trait ModbusHandler {
     }
 
     /// Read Input Registers
+    ///
+    /// Called once with the whole request, `out` lies in the tx buffer.
     /// # Arguments
     /// - `addr`: Data adress from Modbus request
     /// - `len`: Number of Data from Modbus request
-    /// - `out`: output of the requested register values. The output buffer is guaranteed to hold the maximum number of Registers in one Modbus request (125)
+    /// - `out`: output of the requested register values. The output buffer holds exactly `len` items
+    #[cfg(feature = "input-registers")]
     fn read_input_registers(
         &mut self,
         _addr: usize,
@@ -58,20 +138,167 @@ pub trait ModbusHandler {
     }
 
     /// Write Coils
+    ///
+    /// Called by the default [`ModbusHandler::write_coils_packed`] once per chunk of at most
+    /// [`MAX_CHUNK_LEN`] coils.
     /// # Arguments
-    /// - `addr`: Data adress (from Modbus request)
-    /// - `len`: Number of Coils to write (from Modbus request)
+    /// - `addr`: Data adress of the chunk
+    /// - `len`: Number of Coils of the chunk
     /// - `buf`: Slice holding the coils to be written
+    #[cfg(feature = "coils")]
     fn write_coils(&mut self, _addr: usize, _len: usize, _buf: &[bool]) -> Result<usize, Error> {
         Err(Error::NotSupported)
     }
 
+    /// Write Coils from a packed bit buffer
+    /// # Arguments
+    /// - `addr`: Data adress (from Modbus request)
+    /// - `bits`: the Coils to be written, `bits.len()` is the number of Coils from the Modbus request
+    #[cfg(feature = "coils")]
+    fn write_coils_packed(&mut self, addr: usize, bits: Bits) -> Result<usize, Error> {
+        write_bits_chunked(addr, bits, |addr, len, buf| {
+            self.write_coils(addr, len, buf)
+        })
+    }
+
     /// Write Registers
     /// # Arguments
     /// - `addr`: Data adress (from Modbus request)
     /// - `len`: Number of Registers to write (from Modbus request)
     /// - `buf`: Slice holding the registers to be written
+    #[cfg(feature = "holding-registers")]
     fn write_registers(&mut self, _addr: usize, _len: usize, _buf: &[u16]) -> Result<usize, Error> {
         Err(Error::NotSupported)
     }
+
+    /// Unit ID change requested by the last request
+    ///
+    /// Called by [`crate::ModbusServer::process_frame`] after every executed request. Return the
+    /// new unit ID once, e.g. after a master wrote it to a holding register, and the server applies
+    /// it after the response has been transmitted (see [`crate::ModbusServer::transmit_complete`]).
+    /// Unit IDs outside of [`crate::UNIT_ID_RANGE`] are not applied but reported to the observer
+    /// (see [`crate::observer::Observer::unit_id_rejected`]), so reject them in the write handler
+    /// with [`Error::InvalidValue`].
+    fn take_unit_id_change(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// Forward all accesses to the borrowed handler
+///
+/// This allows handlers of different types behind `&mut dyn ModbusHandler`, e.g. for the units
+/// of a [`crate::multi::MultiUnitServer`].
+impl<H: ModbusHandler + ?Sized> ModbusHandler for &mut H {
+    #[cfg(feature = "coils")]
+    fn read_coils(&mut self, addr: usize, len: usize, out: &mut [bool]) -> Result<usize, Error> {
+        (**self).read_coils(addr, len, out)
+    }
+
+    #[cfg(feature = "discrete-inputs")]
+    fn read_discrete_input(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [bool],
+    ) -> Result<usize, Error> {
+        (**self).read_discrete_input(addr, len, out)
+    }
+
+    #[cfg(feature = "coils")]
+    fn read_coils_packed(&mut self, addr: usize, out: &mut BitsMut) -> Result<usize, Error> {
+        (**self).read_coils_packed(addr, out)
+    }
+
+    #[cfg(feature = "discrete-inputs")]
+    fn read_discrete_input_packed(
+        &mut self,
+        addr: usize,
+        out: &mut BitsMut,
+    ) -> Result<usize, Error> {
+        (**self).read_discrete_input_packed(addr, out)
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn read_holding_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        (**self).read_holding_registers(addr, len, out)
+    }
+
+    #[cfg(feature = "input-registers")]
+    fn read_input_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        (**self).read_input_registers(addr, len, out)
+    }
+
+    #[cfg(feature = "coils")]
+    fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+        (**self).write_coils(addr, len, buf)
+    }
+
+    #[cfg(feature = "coils")]
+    fn write_coils_packed(&mut self, addr: usize, bits: Bits) -> Result<usize, Error> {
+        (**self).write_coils_packed(addr, bits)
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn write_registers(&mut self, addr: usize, len: usize, buf: &[u16]) -> Result<usize, Error> {
+        (**self).write_registers(addr, len, buf)
+    }
+
+    fn take_unit_id_change(&mut self) -> Option<u8> {
+        (**self).take_unit_id_change()
+    }
+}
+
+/// Fill a packed bit buffer through a `bool` slice read method, chunk by chunk
+#[cfg(any(feature = "coils", feature = "discrete-inputs"))]
+fn read_bits_chunked(
+    addr: usize,
+    out: &mut BitsMut,
+    mut read: impl FnMut(usize, usize, &mut [bool]) -> Result<usize, Error>,
+) -> Result<usize, Error> {
+    let mut chunk = [false; MAX_CHUNK_LEN];
+
+    let mut offset = 0;
+    while offset < out.len() {
+        let n = (out.len() - offset).min(MAX_CHUNK_LEN);
+        let count = read(addr + offset, n, &mut chunk[..n])?;
+        check_count(n, count)?;
+
+        for (i, bit) in chunk[..n].iter().enumerate() {
+            out.set(offset + i, *bit);
+        }
+        offset += n;
+    }
+    Ok(out.len())
+}
+
+/// Pass a packed bit buffer to a `bool` slice write method, chunk by chunk
+#[cfg(feature = "coils")]
+fn write_bits_chunked(
+    addr: usize,
+    bits: Bits,
+    mut write: impl FnMut(usize, usize, &[bool]) -> Result<usize, Error>,
+) -> Result<usize, Error> {
+    let mut chunk = [false; MAX_CHUNK_LEN];
+
+    let mut offset = 0;
+    while offset < bits.len() {
+        let n = (bits.len() - offset).min(MAX_CHUNK_LEN);
+        for (i, slot) in chunk[..n].iter_mut().enumerate() {
+            *slot = bits.get(offset + i).unwrap_or_default();
+        }
+        let count = write(addr + offset, n, &chunk[..n])?;
+        check_count(n, count)?;
+        offset += n;
+    }
+    Ok(bits.len())
 }
@@ -24,7 +24,7 @@
 //! 3. Transmit the generated response bytes
 //!
 //! ```no_run
-//! use modbus_server::{ModbusServer, handler::ModbusHandler};
+//! use modbus_server::{ModbusServer, Outcome, handler::ModbusHandler};
 //!
 //! let rx_frame = [0u8;4]; // imaginary modbus frame, input from UART
 //! struct MyHandler;
@@ -35,8 +35,8 @@
 //! let mut server = ModbusServer::new(1, handler); // 1 is the device slave ID
 //! let mut tx_buf = [0u8; 256];
 //!
-//! if let Ok(len) = server.process_frame(&rx_frame, &mut tx_buf) {
-//!     // handle your uart transmission 
+//! if let Ok(Outcome::Responded(len)) = server.process_frame(&rx_frame, &mut tx_buf) {
+//!     // handle your uart transmission
 //!     // uart.write(&tx_buf[..len]);
 //! }
 //! ```
@@ -55,28 +55,130 @@
 
 #![no_std]
 
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+#[cfg(not(any(
+    feature = "coils",
+    feature = "discrete-inputs",
+    feature = "holding-registers",
+    feature = "input-registers"
+)))]
+compile_error!(
+    "enable at least one of the features `coils`, `discrete-inputs`, `holding-registers` or `input-registers`"
+);
+
+#[macro_use]
+mod fmt;
+
+pub mod bits;
+pub mod client;
+pub mod clock;
 pub mod error;
+#[cfg(feature = "tcp")]
+pub mod gateway;
 pub mod handler;
-
+pub mod map;
+pub mod multi;
+pub mod observer;
+#[cfg(feature = "holding-registers")]
+pub mod persist;
+pub mod queue;
+pub mod rtu;
+pub mod statistics;
+pub mod store;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+#[cfg(all(feature = "std", feature = "tcp"))]
+pub mod tcp_server;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(any(
+    feature = "embedded-io",
+    feature = "embedded-io-async",
+    feature = "embedded-hal-nb"
+))]
+pub mod transport;
+
+#[cfg(feature = "coils")]
+use bits::Bits;
+#[cfg(any(feature = "coils", feature = "discrete-inputs"))]
+use bits::BitsMut;
+use core::ops::RangeInclusive;
 use error::Error;
 use handler::ModbusHandler;
 use modbus_core::{
-    Coils, Data, ExceptionResponse, FunctionCode, Request, Response, ResponsePdu,
-    rtu::{
-        Header, ResponseAdu,
-        server::{decode_request, encode_response},
-    },
+    Exception, FunctionCode, Request,
+    rtu::{MAX_FRAME_LEN, crc16},
 };
+use observer::{NoObserver, Observer, Reply, RequestEvent, Transport};
 
 use crate::error::map_exception;
 
-pub struct ModbusServer<H> {
+/// Unit ID used by a master to address all servers on the bus at once
+pub const BROADCAST_ID: u8 = 0;
+
+/// Unit IDs a server may use, 248 to 255 are reserved by the Modbus specification
+pub const UNIT_ID_RANGE: RangeInclusive<u8> = 1..=247;
+
+/// Smallest possible RTU frame: unit ID, function code and CRC
+const MIN_FRAME_LEN: usize = 4;
+
+/// Length of an exception response PDU: function code and exception code
+const EXCEPTION_PDU_LEN: usize = 2;
+
+/// Result of processing a single frame with [`ModbusServer::process_frame`]
+///
+/// Only [`Outcome::Responded`] produces bytes which have to be transmitted, all other variants
+/// describe why the frame was not answered.
+#[cfg_attr(all(feature = "defmt", target_os = "none"), derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// A response frame of the given length was written to the tx buffer
+    Responded(usize),
+    /// The frame is valid but addressed to a different unit
+    NotForUs,
+    /// The frame was a broadcast (unit ID 0). Write requests are executed, but never answered
+    Broadcast,
+    /// The CRC of the frame does not match its content
+    CrcError,
+    /// The frame is too short, too long or its PDU could not be decoded
+    Malformed,
+    /// The server is in listen only mode, the request was not executed
+    ListenOnly,
+}
+
+impl Outcome {
+    /// Number of response bytes to transmit, 0 if there is no response
+    pub fn response_len(&self) -> usize {
+        match self {
+            Outcome::Responded(len) => *len,
+            _ => 0,
+        }
+    }
+}
+
+/// Modbus RTU server
+///
+/// The server does not hold any buffers, responses are written directly into the tx buffer
+/// passed to [`ModbusServer::process_frame`]. Packed coils and registers are read by the handler
+/// directly into the tx buffer, only the `bool` slice methods of coils and discrete inputs are
+/// called in chunks of at most [`handler::MAX_CHUNK_LEN`] items. So the worst-case stack usage of
+/// a request is bounded by a 32 byte chunk buffer plus the call frames, independent of the
+/// requested quantity.
+///
+/// An [`Observer`] can be attached with [`ModbusServer::with_observer`] to follow every request.
+pub struct ModbusServer<H, O = NoObserver> {
     /// Modbus slave ID
     unit_id: u8,
     /// Handler object implementing [`ModbusHandler`] traits
     handler: H,
-    /// buffer for building response data
-    buf: [u8; 250],
+    /// Notified of every decoded request
+    observer: O,
+    /// In listen only mode requests are neither executed nor answered
+    listen_only: bool,
+    /// Unit ID applied once the current response has been transmitted
+    pending_unit_id: Option<u8>,
 }
 
 impl<H> ModbusServer<H>
@@ -87,7 +189,8 @@ where
     ///
     /// # Parameters
     ///
-    /// * `unit_id` - Modbus slave (unit) identifier for this server.
+    /// * `unit_id` - Modbus slave (unit) identifier for this server, should be in
+    ///   [`UNIT_ID_RANGE`]. It is not checked, see [`ModbusServer::try_new`].
     /// * `handler` - Application-defined handler implementing [`ModbusHandler`].
     ///
     /// # Returns
@@ -97,10 +200,116 @@ where
         Self {
             unit_id,
             handler,
-            buf: [0u8; 250],
+            observer: NoObserver,
+            listen_only: false,
+            pending_unit_id: None,
+        }
+    }
+
+    /// Create a new Modbus RTU server instance with a checked unit ID, like [`ModbusServer::new`]
+    ///
+    /// Returns [`Error::InvalidValue`] if `unit_id` is outside of [`UNIT_ID_RANGE`], like
+    /// [`ModbusServer::set_unit_id`]. Use this for unit IDs which are configured at runtime.
+    pub fn try_new(unit_id: u8, handler: H) -> Result<Self, Error> {
+        check_unit_id(unit_id)?;
+        Ok(Self::new(unit_id, handler))
+    }
+}
+
+impl<H, O> ModbusServer<H, O>
+where
+    H: ModbusHandler,
+    O: Observer,
+{
+    /// Attach `observer`, which is notified of every request before and after the handler runs
+    pub fn with_observer<P: Observer>(self, observer: P) -> ModbusServer<H, P> {
+        ModbusServer {
+            unit_id: self.unit_id,
+            handler: self.handler,
+            observer,
+            listen_only: self.listen_only,
+            pending_unit_id: self.pending_unit_id,
         }
     }
 
+    /// Reference to the observer
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Mutable reference to the observer
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Unit ID the server currently answers to
+    pub fn unit_id(&self) -> u8 {
+        self.unit_id
+    }
+
+    /// Change the unit ID immediately
+    ///
+    /// Returns [`Error::InvalidValue`] if `unit_id` is outside of [`UNIT_ID_RANGE`]. A pending
+    /// change (see [`ModbusServer::set_unit_id_deferred`]) is discarded.
+    pub fn set_unit_id(&mut self, unit_id: u8) -> Result<(), Error> {
+        check_unit_id(unit_id)?;
+        self.unit_id = unit_id;
+        self.pending_unit_id = None;
+        Ok(())
+    }
+
+    /// Change the unit ID once the current response has been transmitted
+    ///
+    /// The new unit ID is applied by [`ModbusServer::transmit_complete`], so a response which is
+    /// still being sent goes out under the old unit ID. Returns [`Error::InvalidValue`] if
+    /// `unit_id` is outside of [`UNIT_ID_RANGE`].
+    pub fn set_unit_id_deferred(&mut self, unit_id: u8) -> Result<(), Error> {
+        check_unit_id(unit_id)?;
+        self.pending_unit_id = Some(unit_id);
+        Ok(())
+    }
+
+    /// Unit ID which will be applied by [`ModbusServer::transmit_complete`], if any
+    pub fn pending_unit_id(&self) -> Option<u8> {
+        self.pending_unit_id
+    }
+
+    /// Notify the server that the last response has been transmitted completely
+    ///
+    /// Applies a pending unit ID change, requested by [`ModbusServer::set_unit_id_deferred`] or
+    /// by the handler through [`ModbusHandler::take_unit_id_change`]. Call this after the last
+    /// byte of a response left the UART, or right away if [`ModbusServer::process_frame`] did
+    /// not produce a response.
+    pub fn transmit_complete(&mut self) {
+        if let Some(unit_id) = self.pending_unit_id.take() {
+            self.unit_id = unit_id;
+        }
+    }
+
+    /// Reference to the handler
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Mutable reference to the handler
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Enable or disable listen only mode.
+    ///
+    /// While listen only mode is active, frames addressed to this server are still decoded (and
+    /// reported as [`Outcome::ListenOnly`]), but they are neither executed nor answered. This
+    /// allows the application to keep the bus driver disabled.
+    pub fn set_listen_only(&mut self, listen_only: bool) {
+        self.listen_only = listen_only;
+    }
+
+    /// Returns `true` if listen only mode is active, see [`ModbusServer::set_listen_only`]
+    pub fn is_listen_only(&self) -> bool {
+        self.listen_only
+    }
+
     /// Process a single complete Modbus RTU request frame.
     ///
     /// This function parses and validates the received RTU frame, dispatches
@@ -110,180 +319,502 @@ where
     /// The caller is responsible for providing complete RTU frames, including
     /// slave address and CRC. Frame timing and UART handling are out of scope.
     ///
-    /// The function returns the length of the response frame. In case of a parsing error of the
-    /// Frame, no response is generated, thus the result is 0. This is no error but a 'skip'.
-    /// Errors returned by this function exclusively come from the user handlers, see
-    /// [`ModbusHandler`]
+    /// Frames which must not be answered (CRC errors, malformed frames, frames for other units,
+    /// broadcasts) are reported by the returned [`Outcome`], so the application can count them
+    /// and keep its bus driver disabled. Errors of the user handlers are answered with a Modbus
+    /// exception response, see [`ModbusHandler`].
     ///
     /// # Parameters
     ///
     /// * `rx` - Received Modbus RTU frame (including unit ID and CRC).
-    /// * `tx` - Output buffer where the response frame will be written. Must be large enough to hold the maximum possible Modbus response (typically up to 256 bytes including CRC).
+    /// * `tx` - Output buffer where the response frame will be written. Must be large enough to hold the maximum possible Modbus response (up to 256 bytes including CRC).
+    ///   The registers of Write Multiple Registers requests are decoded in it as well, also of
+    ///   broadcasts.
     ///
     /// # Returns
     ///
-    /// * `Ok(len)` - Number of bytes written to `tx` containing the response frame.
-    /// * `Err(Error)` - If the frame is invalid, addressed to a different unit,
-    ///   or the request cannot be processed.
+    /// * `Ok(Outcome::Responded(len))` - Number of bytes written to `tx` containing the response frame.
+    /// * `Ok(outcome)` - The frame was consumed without generating a response, see [`Outcome`].
+    /// * `Err(Error)` - If the response or the registers of a write do not fit into `tx`. The
+    ///   request is not executed in this case.
     ///
     /// # Notes
     ///
-    /// Broadcast frames (unit ID = 0) may be accepted or ignored depending on
-    /// the handler implementation and application requirements.
-    /// ```
-    pub fn process_frame(&mut self, rx: &[u8], tx: &mut [u8]) -> Result<usize, Error> {
-        let request = decode_request(rx).unwrap_or_default();
-
-        if let Some(adu) = request {
-            let response: Result<Response, Error> = match adu.pdu.0 {
-                Request::ReadCoils(addr, len) => {
-                    let mut coils_buf = [false; 2000];
-
-                    // call user handler for read_coils
-                    match self
-                        .handler
-                        .read_coils(addr as usize, len as usize, &mut coils_buf)
-                    {
-                        Ok(_) => {
-                            let coils =
-                                Coils::from_bools(&coils_buf[..len as usize], &mut self.buf)
-                                    .map_err(|_| Error::BufferTooSmall)?;
-                            Ok(Response::ReadCoils(coils))
-                        }
-                        Err(e) => Err(e),
-                    }
-                }
-                Request::ReadDiscreteInputs(addr, len) => {
-                    let mut coils_buf = [false; 2000];
-
-                    // call user handler for read_discrete_inputs
-                    match self.handler.read_discrete_input(
-                        addr as usize,
-                        len as usize,
-                        &mut coils_buf,
-                    ) {
-                        Ok(_) => {
-                            let coils =
-                                Coils::from_bools(&coils_buf[..len as usize], &mut self.buf)
-                                    .map_err(|_| Error::BufferTooSmall)?;
-                            Ok(Response::ReadDiscreteInputs(coils))
-                        }
-                        Err(e) => Err(e),
-                    }
-                }
-                Request::ReadHoldingRegisters(addr, len) => {
-                    let mut reg_buf = [0u16; 125];
-
-                    // call user handler for read_holding_registers
-                    match self.handler.read_holding_registers(
-                        addr as usize,
-                        len as usize,
-                        &mut reg_buf,
-                    ) {
-                        Ok(_) => {
-                            let data = Data::from_words(&reg_buf[..len as usize], &mut self.buf)
-                                .map_err(|_| Error::BufferTooSmall)?;
-                            Ok(Response::ReadHoldingRegisters(data))
-                        }
-                        Err(e) => Err(e),
-                    }
-                }
-                Request::ReadInputRegisters(addr, len) => {
-                    let mut reg_buf = [0u16; 125];
-
-                    // call user handler for read_holding_registers
-                    match self.handler.read_input_registers(
-                        addr as usize,
-                        len as usize,
-                        &mut reg_buf,
-                    ) {
-                        Ok(_) => {
-                            let data = Data::from_words(&reg_buf[..len as usize], &mut self.buf)
-                                .map_err(|_| Error::BufferTooSmall)?;
-                            Ok(Response::ReadInputRegisters(data))
-                        }
-                        Err(e) => Err(e),
-                    }
-                }
-                Request::WriteSingleCoil(addr, value) => {
-                    let coils_buf = [value];
-
-                    // call user handler for read_coils
-                    match self.handler.write_coils(addr as usize, 1, &coils_buf) {
-                        Ok(num_written_coils) => {
-                            // workaround for bug in modbus-core crate: not encoding a response because the
-                            // Response::WriteSingleCoil enum is not correct. Since the modbus spec states the response is an
-                            // echo of the request, we are just doing that
-                            tx[..rx.len()].copy_from_slice(rx);
-
-                            if num_written_coils == 1 {
-                                // We return here since we can't build a valid Response for now
-                                return Ok(rx.len());
-                            } else {
-                                Err(Error::Application)
-                            }
-                        }
-                        Err(e) => Err(e),
-                    }
-                }
-                Request::WriteSingleRegister(addr, value) => {
-                    let reg_buf = [value];
-
-                    // call user handler for read_coils
-                    match self.handler.write_registers(addr as usize, 1, &reg_buf) {
-                        Ok(_) => Ok(Response::WriteSingleRegister(addr, value)),
-                        Err(e) => Err(e),
-                    }
-                }
-                _ => Err(Error::NotSupported),
-            };
+    /// Broadcast frames (unit ID = 0) are executed if they contain a write request and are never
+    /// answered. Read requests in broadcast frames are ignored.
+    ///
+    /// A unit ID change requested by the handler while executing the request (see
+    /// [`ModbusHandler::take_unit_id_change`]) is deferred until
+    /// [`ModbusServer::transmit_complete`] is called, so the response still carries the old unit
+    /// ID. After a broadcast, which is not answered, it is applied right away.
+    pub fn process_frame(&mut self, rx: &[u8], tx: &mut [u8]) -> Result<Outcome, Error> {
+        let decoded = match decode_frame(rx, |slave| slave == self.unit_id) {
+            Ok(decoded) => decoded,
+            Err(outcome) => return Ok(outcome),
+        };
+        let event = RequestEvent {
+            unit_id: decoded.slave,
+            transport: Transport::Rtu,
+            function_code: decoded.function_code,
+            request: decoded.request.ok(),
+        };
+        self.observer.before_request(&event);
 
-            let function_code = match response {
-                Ok(ref r) => match r {
-                    Response::ReadCoils(_) => FunctionCode::ReadCoils,
-                    Response::ReadDiscreteInputs(_) => FunctionCode::ReadDiscreteInputs,
-                    Response::ReadHoldingRegisters(_) => FunctionCode::ReadHoldingRegisters,
-                    Response::ReadInputRegisters(_) => FunctionCode::ReadInputRegisters,
-                    Response::WriteSingleCoil(_) => FunctionCode::WriteSingleCoil,
-                    Response::WriteSingleRegister(_, _) => FunctionCode::WriteSingleRegister,
-                    _ => FunctionCode::ReadCoils, // Fallback, though should match request
-                },
-                Err(_) => match adu.pdu.0 {
-                    Request::ReadCoils(_, _) => FunctionCode::ReadCoils,
-                    Request::ReadDiscreteInputs(_, _) => FunctionCode::ReadDiscreteInputs,
-                    Request::ReadHoldingRegisters(_, _) => FunctionCode::ReadHoldingRegisters,
-                    Request::ReadInputRegisters(_, _) => FunctionCode::ReadInputRegisters,
-                    Request::WriteSingleCoil(_, _) => FunctionCode::WriteSingleCoil,
-                    Request::WriteSingleRegister(_, _) => FunctionCode::WriteSingleRegister,
-                    _ => FunctionCode::ReadCoils, // Fallback
-                },
-            };
+        if self.listen_only {
+            trace!("listen only, ignoring {:?}", decoded.function_code);
+            self.observer.after_request(&event, Reply::Dropped);
+            return Ok(Outcome::ListenOnly);
+        }
 
-            let response_pdu = match response {
-                Ok(r) => ResponsePdu(Ok(r)),
-                Err(e) => ResponsePdu(Err(ExceptionResponse {
-                    function: function_code,
-                    exception: map_exception(e),
-                })),
+        if decoded.slave == BROADCAST_ID {
+            let result = execute_broadcast(&mut self.handler, &decoded.request, tx);
+            let reply = match result {
+                Ok(Some(exception)) => Reply::BroadcastFailed(exception),
+                _ => Reply::Dropped,
             };
+            self.observer.after_request(&event, reply);
+            self.take_unit_id_change();
+            self.transmit_complete();
+            return result.map(|_| Outcome::Broadcast);
+        }
 
-            let response_adu = ResponseAdu {
-                hdr: Header {
-                    slave: self.unit_id,
-                },
-                pdu: response_pdu,
-            };
+        let outcome = respond(&mut self.handler, decoded, tx);
+        let reply = match outcome {
+            Ok(Outcome::Responded(_)) => Reply::from_pdu(&tx[1..]),
+            _ => Reply::Dropped,
+        };
+        self.observer.after_request(&event, reply);
+        self.take_unit_id_change();
+        outcome
+    }
+
+    /// Fetch a unit ID change requested by the handler, invalid unit IDs are reported to the
+    /// observer instead of being applied
+    fn take_unit_id_change(&mut self) {
+        if let Some(unit_id) = self.handler.take_unit_id_change()
+            && self.set_unit_id_deferred(unit_id).is_err()
+        {
+            warn!("rejected unit ID change to {}", unit_id);
+            self.observer.unit_id_rejected(unit_id);
+        }
+    }
+}
+
+/// Request decoded from a frame which has to be processed
+struct Decoded<'a> {
+    /// Unit ID the frame is addressed to
+    slave: u8,
+    /// Function code of the request, also known if the request failed validation
+    function_code: FunctionCode,
+    /// The validated request, or the error it has to be answered with
+    request: Result<Request<'a>, Error>,
+}
+
+/// Check the CRC and decode the request of an RTU frame
+///
+/// `serves` decides whether a unit ID is served by the caller, broadcasts are always decoded.
+/// Frames which are not processed any further are returned as `Err` with their [`Outcome`].
+fn decode_frame(rx: &[u8], serves: impl Fn(u8) -> bool) -> Result<Decoded<'_>, Outcome> {
+    if rx.len() < MIN_FRAME_LEN || rx.len() > MAX_FRAME_LEN {
+        debug!("malformed frame of {} bytes", rx.len());
+        return Err(Outcome::Malformed);
+    }
+
+    let (adu, crc) = rx.split_at(rx.len() - 2);
+    if crc16(adu) != u16::from_be_bytes([crc[0], crc[1]]) {
+        debug!("CRC error in frame for unit {}", adu[0]);
+        return Err(Outcome::CrcError);
+    }
+
+    let slave = adu[0];
+    if slave != BROADCAST_ID && !serves(slave) {
+        trace!("frame for unit {}", slave);
+        return Err(Outcome::NotForUs);
+    }
+
+    let Some((function_code, request)) = decode_pdu(&adu[1..]) else {
+        debug!("malformed PDU with function code {}", adu[1]);
+        return Err(Outcome::Malformed);
+    };
+    Ok(Decoded {
+        slave,
+        function_code,
+        request,
+    })
+}
 
-            let tx_len = encode_response(response_adu, tx).map_err(|_| Error::BufferTooSmall)?;
-            return Ok(tx_len as usize);
+/// Decode and validate a request PDU
+///
+/// Returns the function code together with the validated request or the error it has to be
+/// answered with, `None` if the PDU can't be decoded at all.
+fn decode_pdu(pdu: &[u8]) -> Option<(FunctionCode, Result<Request<'_>, Error>)> {
+    match Request::try_from(pdu) {
+        Ok(request) => Some((FunctionCode::from(request), validate(request))),
+        // the PDU is intact, but the coil value is neither ON nor OFF
+        Err(modbus_core::Error::CoilValue(_)) => {
+            Some((FunctionCode::WriteSingleCoil, Err(Error::InvalidValue)))
+        }
+        Err(_) => None,
+    }
+}
+
+/// Execute a broadcast request, only write requests are executed
+///
+/// `pdu` is used to decode the request, like the response PDU of other requests. Returns the
+/// exception the handler rejected the request with. There is no response to a broadcast, so it
+/// can only be reported to the observer.
+///
+/// Returns [`Error::BufferTooSmall`] without executing the request if `pdu` is too small.
+fn execute_broadcast<H: ModbusHandler + ?Sized>(
+    handler: &mut H,
+    request: &Result<Request, Error>,
+    pdu: &mut [u8],
+) -> Result<Option<Exception>, Error> {
+    if let Ok(
+        request @ (Request::WriteSingleCoil(_, _)
+        | Request::WriteSingleRegister(_, _)
+        | Request::WriteMultipleRegisters(_, _)),
+    ) = request
+    {
+        debug!("broadcast {:?}", FunctionCode::from(*request));
+        if pdu.len() < required_pdu_len(&Ok(*request)) {
+            warn!("tx buffer of {} bytes too small for broadcast", pdu.len());
+            return Err(Error::BufferTooSmall);
+        }
+        if let Err(e) = execute(handler, *request, pdu) {
+            warn!("broadcast failed: {:?}", e);
+            return Ok(Some(map_exception(e)));
+        }
+    }
+    Ok(None)
+}
+
+/// Execute a decoded request and write the response frame (or exception response) into `tx`
+fn respond<H: ModbusHandler + ?Sized>(
+    handler: &mut H,
+    decoded: Decoded,
+    tx: &mut [u8],
+) -> Result<Outcome, Error> {
+    // unit ID and CRC around the PDU
+    if tx.len() < 3 {
+        return Err(Error::BufferTooSmall);
+    }
+    let pdu_end = tx.len() - 2;
+    let pdu_len = respond_pdu(
+        handler,
+        decoded.function_code,
+        decoded.request,
+        &mut tx[1..pdu_end],
+    )?;
+
+    Ok(Outcome::Responded(finish_frame(tx, decoded.slave, pdu_len)))
+}
+
+/// Execute a decoded request and write the response PDU (or exception PDU) into `pdu`
+///
+/// Returns the length of the response PDU, or [`Error::BufferTooSmall`] without executing the
+/// request if the response may not fit.
+fn respond_pdu<H: ModbusHandler + ?Sized>(
+    handler: &mut H,
+    function_code: FunctionCode,
+    request: Result<Request, Error>,
+    pdu: &mut [u8],
+) -> Result<usize, Error> {
+    // make sure the response fits before executing the request
+    if pdu.len() < required_pdu_len(&request).max(EXCEPTION_PDU_LEN) {
+        warn!(
+            "tx buffer of {} bytes too small for {:?}",
+            pdu.len(),
+            function_code
+        );
+        return Err(Error::BufferTooSmall);
+    }
+
+    debug!("dispatching {:?}", function_code);
+    pdu[0] = function_code.value();
+    match request.and_then(|request| execute(handler, request, pdu)) {
+        Ok(len) => Ok(len),
+        Err(e) => {
+            let exception = map_exception(e);
+            debug!(
+                "{:?} failed: {:?}, responding with {:?}",
+                function_code, e, exception
+            );
+            pdu[0] |= 0x80;
+            pdu[1] = exception as u8;
+            Ok(EXCEPTION_PDU_LEN)
+        }
+    }
+}
+
+/// Dispatch a decoded request to the user handler and write the response data
+///
+/// `pdu` already holds the function code and is large enough for the response, see
+/// [`required_pdu_len`]. Returns the length of the response PDU.
+#[cfg_attr(
+    not(any(
+        feature = "coils",
+        feature = "discrete-inputs",
+        feature = "holding-registers",
+        feature = "input-registers"
+    )),
+    allow(unused_variables)
+)]
+fn execute<H: ModbusHandler + ?Sized>(
+    handler: &mut H,
+    request: Request,
+    pdu: &mut [u8],
+) -> Result<usize, Error> {
+    match request {
+        #[cfg(feature = "coils")]
+        Request::ReadCoils(addr, len) => {
+            let byte_count = (len as usize).div_ceil(8);
+            pdu[1] = byte_count as u8;
+
+            let mut bits = packed_bits(&mut pdu[2..2 + byte_count], len)?;
+
+            // call user handler for read_coils
+            let count = handler.read_coils_packed(addr as usize, &mut bits)?;
+            check_count(len as usize, count)?;
+            bits.clear_padding();
+            Ok(2 + byte_count)
+        }
+        #[cfg(feature = "discrete-inputs")]
+        Request::ReadDiscreteInputs(addr, len) => {
+            let byte_count = (len as usize).div_ceil(8);
+            pdu[1] = byte_count as u8;
+
+            let mut bits = packed_bits(&mut pdu[2..2 + byte_count], len)?;
+
+            // call user handler for read_discrete_inputs
+            let count = handler.read_discrete_input_packed(addr as usize, &mut bits)?;
+            check_count(len as usize, count)?;
+            bits.clear_padding();
+            Ok(2 + byte_count)
+        }
+        #[cfg(feature = "holding-registers")]
+        Request::ReadHoldingRegisters(addr, len) => {
+            // call user handler for read_holding_registers
+            read_words(addr, len, pdu, |addr, len, out| {
+                handler.read_holding_registers(addr, len, out)
+            })
+        }
+        #[cfg(feature = "input-registers")]
+        Request::ReadInputRegisters(addr, len) => {
+            // call user handler for read_input_registers
+            read_words(addr, len, pdu, |addr, len, out| {
+                handler.read_input_registers(addr, len, out)
+            })
+        }
+        #[cfg(feature = "coils")]
+        Request::WriteSingleCoil(addr, value) => {
+            let coils_buf = [u8::from(value)];
+            let bits = Bits::new(&coils_buf, 1).ok_or(Error::BufferTooSmall)?;
+
+            // call user handler for write_coils
+            let count = handler.write_coils_packed(addr as usize, bits)?;
+            check_count(1, count)?;
+
+            // the response is an echo of the request
+            let value: u16 = if value { 0xFF00 } else { 0x0000 };
+            pdu[1..3].copy_from_slice(&addr.to_be_bytes());
+            pdu[3..5].copy_from_slice(&value.to_be_bytes());
+            Ok(5)
+        }
+        #[cfg(feature = "holding-registers")]
+        Request::WriteSingleRegister(addr, value) => {
+            let reg_buf = [value];
+
+            // call user handler for write_registers
+            let count = handler.write_registers(addr as usize, 1, &reg_buf)?;
+            check_count(1, count)?;
+
+            // the response is an echo of the request
+            pdu[1..3].copy_from_slice(&addr.to_be_bytes());
+            pdu[3..5].copy_from_slice(&value.to_be_bytes());
+            Ok(5)
+        }
+        #[cfg(feature = "holding-registers")]
+        Request::WriteMultipleRegisters(addr, data) => {
+            // all registers are passed at once, so the handler can check them as a whole. They
+            // are decoded where the response will be written
+            let len = data.len();
+            let (_, regs) = align_words(&mut pdu[1..2 + 2 * len], len);
+            for (i, reg) in regs.iter_mut().enumerate() {
+                *reg = data.get(i).ok_or(Error::InvalidValue)?;
+            }
+
+            // call user handler for write_registers
+            let count = handler.write_registers(addr as usize, len, regs)?;
+            check_count(len, count)?;
+
+            // the response echoes address and quantity
+            pdu[1..3].copy_from_slice(&addr.to_be_bytes());
+            pdu[3..5].copy_from_slice(&(len as u16).to_be_bytes());
+            Ok(5)
+        }
+        _ => Err(Error::NotSupported),
+    }
+}
+
+/// Length of the PDU buffer needed to execute a request and hold its response
+///
+/// This is the length of the response PDU, except for Write Multiple Registers, whose registers
+/// are decoded in the PDU buffer before the response is written.
+fn required_pdu_len(request: &Result<Request, Error>) -> usize {
+    match request {
+        #[cfg(feature = "coils")]
+        Ok(Request::ReadCoils(_, len)) => 2 + (*len as usize).div_ceil(8),
+        #[cfg(feature = "discrete-inputs")]
+        Ok(Request::ReadDiscreteInputs(_, len)) => 2 + (*len as usize).div_ceil(8),
+        #[cfg(feature = "holding-registers")]
+        Ok(Request::ReadHoldingRegisters(_, len)) => 2 + *len as usize * 2,
+        #[cfg(feature = "input-registers")]
+        Ok(Request::ReadInputRegisters(_, len)) => 2 + *len as usize * 2,
+        #[cfg(feature = "coils")]
+        Ok(Request::WriteSingleCoil(_, _)) => 5,
+        #[cfg(feature = "holding-registers")]
+        Ok(Request::WriteSingleRegister(_, _)) => 5,
+        #[cfg(feature = "holding-registers")]
+        Ok(Request::WriteMultipleRegisters(_, data)) => (2 + 2 * data.len()).max(5),
+        _ => EXCEPTION_PDU_LEN,
+    }
+}
+
+/// Add unit ID and CRC to the response PDU in `tx`, returns the length of the frame
+fn finish_frame(tx: &mut [u8], unit_id: u8, pdu_len: usize) -> usize {
+    tx[0] = unit_id;
+    let crc = crc16(&tx[..1 + pdu_len]);
+    tx[1 + pdu_len..3 + pdu_len].copy_from_slice(&crc.to_be_bytes());
+    3 + pdu_len
+}
+
+/// Clear the response data of a bit read and create a view on it
+#[cfg(any(feature = "coils", feature = "discrete-inputs"))]
+fn packed_bits(data: &mut [u8], len: u16) -> Result<BitsMut<'_>, Error> {
+    data.fill(0);
+    BitsMut::new(data, len as usize).ok_or(Error::BufferTooSmall)
+}
+
+/// Read `len` registers starting at `addr` with a single handler call and write the response
+/// data into `pdu`, returns the length of the response PDU
+///
+/// The handler writes the registers directly into the response, which is converted to big
+/// endian and moved behind the byte count afterwards.
+#[cfg(any(feature = "holding-registers", feature = "input-registers"))]
+fn read_words(
+    addr: u16,
+    len: u16,
+    pdu: &mut [u8],
+    read: impl FnOnce(usize, usize, &mut [u16]) -> Result<usize, Error>,
+) -> Result<usize, Error> {
+    let len = len as usize;
+    // the byte count and the registers
+    let data = &mut pdu[1..2 + 2 * len];
+    let (offset, words) = align_words(data, len);
+    words.fill(0);
+    let count = read(addr as usize, len, words)?;
+    check_count(len, count)?;
+
+    let words = &mut data[offset..offset + 2 * len];
+    for word in words.chunks_exact_mut(2) {
+        let value = u16::from_ne_bytes([word[0], word[1]]);
+        word.copy_from_slice(&value.to_be_bytes());
+    }
+    data.copy_within(offset..offset + 2 * len, 1);
+    data[0] = (2 * len) as u8;
+    Ok(2 + 2 * len)
+}
+
+/// View `len` registers in `buf`, which holds at least `2 * len + 1` bytes
+///
+/// The registers start at the first or the second byte of `buf`, whichever is aligned for `u16`.
+/// Returns this offset together with the registers.
+#[cfg(any(feature = "holding-registers", feature = "input-registers"))]
+fn align_words(buf: &mut [u8], len: usize) -> (usize, &mut [u16]) {
+    let offset = buf.as_ptr().addr() % align_of::<u16>();
+    let bytes = &mut buf[offset..offset + 2 * len];
+    // Safety: `bytes` is aligned for `u16` and holds `len` of them, every bit pattern is a valid
+    // `u16`, and the registers borrow `buf` mutably
+    let words = unsafe { core::slice::from_raw_parts_mut(bytes.as_mut_ptr().cast(), len) };
+    (offset, words)
+}
+
+/// Maximum quantity of coils or discrete inputs in a single read request
+const MAX_READ_BITS: u16 = 2000;
+
+/// Maximum quantity of registers in a single read request
+const MAX_READ_REGISTERS: u16 = 125;
+
+/// Maximum quantity of registers in a single write request
+const MAX_WRITE_REGISTERS: u16 = 123;
+
+/// Check the quantity and address range of a request before it is passed to the handler
+///
+/// A quantity outside the limits of the Modbus specification is an illegal data value, a range
+/// exceeding the 16 bit address space is an illegal data address.
+fn validate(request: Request) -> Result<Request, Error> {
+    match request {
+        #[cfg(feature = "coils")]
+        Request::ReadCoils(addr, len) => check_range(addr, len, MAX_READ_BITS)?,
+        #[cfg(feature = "discrete-inputs")]
+        Request::ReadDiscreteInputs(addr, len) => check_range(addr, len, MAX_READ_BITS)?,
+        #[cfg(feature = "holding-registers")]
+        Request::ReadHoldingRegisters(addr, len) => check_range(addr, len, MAX_READ_REGISTERS)?,
+        #[cfg(feature = "input-registers")]
+        Request::ReadInputRegisters(addr, len) => check_range(addr, len, MAX_READ_REGISTERS)?,
+        #[cfg(feature = "holding-registers")]
+        Request::WriteMultipleRegisters(addr, data) => {
+            // the byte count has to match the quantity
+            if data.payload().len() != 2 * data.len() {
+                return Err(Error::InvalidValue);
+            }
+            check_range(addr, data.len() as u16, MAX_WRITE_REGISTERS)?;
         }
-        Ok(0)
+        _ => {}
     }
+    Ok(request)
 }
 
-#[cfg(test)]
+/// Check a quantity against its limit and the address range against the address space
+fn check_range(addr: u16, len: u16, max: u16) -> Result<(), Error> {
+    if len == 0 || len > max {
+        return Err(Error::InvalidValue);
+    }
+    if addr as usize + len as usize > u16::MAX as usize + 1 {
+        return Err(Error::InvalidAddress);
+    }
+    Ok(())
+}
+
+/// Check that `unit_id` is in [`UNIT_ID_RANGE`]
+fn check_unit_id(unit_id: u8) -> Result<(), Error> {
+    if UNIT_ID_RANGE.contains(&unit_id) {
+        Ok(())
+    } else {
+        Err(Error::InvalidValue)
+    }
+}
+
+/// Check the number of items a handler reported as processed against the requested quantity
+pub(crate) fn check_count(requested: usize, count: usize) -> Result<(), Error> {
+    if count == requested {
+        Ok(())
+    } else {
+        Err(Error::LengthMismatch)
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "coils",
+    feature = "discrete-inputs",
+    feature = "holding-registers",
+    feature = "input-registers"
+))]
 mod tests {
     use super::*;
+    use crate::testing::set_crc;
     struct TestData {
         test_coils: [bool; 12],
         test_registers: [u16; 12],
@@ -399,7 +930,10 @@ mod tests {
         let expected_response: [u8; 6] = [0x01, 0x01, 0x01, 0x00, 0x51, 0x88];
         let mut tx_buf = [0u8; 32];
 
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
         let response = &tx_buf[..len];
         assert_eq!(len, expected_response.len());
         assert_eq!(response, expected_response);
@@ -423,7 +957,10 @@ mod tests {
         let expected_response: [u8; 6] = [0x01, 0x02, 0x01, 0xD5, 0x60, 0x17];
         let mut tx_buf = [0u8; 32];
 
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
         let response = &tx_buf[..len];
         assert_eq!(len, expected_response.len());
         assert_eq!(response, expected_response);
@@ -447,7 +984,10 @@ mod tests {
         let expected_response: [u8; 6] = [0x01, 0x01, 0x01, 0x0A, 0xD1, 0x8F]; // Data byte: 0x0A (0:0 1:1 2:0 3:1)
         let mut tx_buf = [0u8; 32];
 
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
         let response = &tx_buf[..len];
         assert_eq!(len, expected_response.len());
         assert_eq!(response, expected_response);
@@ -480,7 +1020,10 @@ mod tests {
         ];
         let mut tx_buf = [0u8; 32];
 
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
         let response = &tx_buf[..len];
         assert_eq!(len, expected_response.len());
         assert_eq!(response, expected_response);
@@ -508,7 +1051,10 @@ mod tests {
         ];
         let mut tx_buf = [0u8; 32];
 
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
         let response = &tx_buf[..len];
         assert_eq!(len, expected_response.len());
         assert_eq!(response, expected_response);
@@ -532,7 +1078,10 @@ mod tests {
         let expected_response = frame; // repsponse is identical to request frame
         let mut tx_buf = [0u8; 32];
 
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
         let response = &tx_buf[..len];
         assert_eq!(response, expected_response);
         assert_eq!(
@@ -561,7 +1110,10 @@ mod tests {
         let expected_response = frame; // repsponse is identical to request frame
         let mut tx_buf = [0u8; 32];
 
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
         let response = &tx_buf[..len];
         assert_eq!(response, expected_response);
         assert_eq!(
@@ -570,6 +1122,80 @@ mod tests {
         )
     }
 
+    #[test]
+    fn write_multiple_registers() {
+        let testdata = TestData {
+            test_coils: [false; 12],
+            test_registers: [0; 12],
+        };
+        let mut server = ModbusServer::new(1, testdata);
+
+        let mut frame: [u8; 13] = [
+            0x01, // Slave address
+            0x10, // Function code: Write multiple registers
+            0x00, 0x02, // Starting address: 2
+            0x00, 0x02, // Quantity: 2
+            0x04, // Byte count
+            0x12, 0x34, 0x56, 0x78, // Register values
+            0x00, 0x00, // CRC16
+        ];
+        set_crc(&mut frame);
+        let mut tx_buf = [0u8; 32];
+
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
+        // the response echoes address and quantity
+        let mut expected_response = [0x01, 0x10, 0x00, 0x02, 0x00, 0x02, 0x00, 0x00];
+        set_crc(&mut expected_response);
+        assert_eq!(tx_buf[..len], expected_response);
+        assert_eq!(
+            server.handler.test_registers,
+            [0, 0, 0x1234, 0x5678, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // the byte count does not match the quantity
+        let mut frame = [
+            0x01, 0x10, 0x00, 0x00, 0x00, 0x02, 0x02, 0x12, 0x34, 0x00, 0x00,
+        ];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x03);
+        // quantity 0
+        let mut frame = [0x01, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x03);
+        // registers 0xFFFF and 0x10000
+        let mut frame = [
+            0x01, 0x10, 0xFF, 0xFF, 0x00, 0x02, 0x04, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00,
+        ];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x02);
+
+        // broadcast
+        let mut frame = [
+            0x00, 0x10, 0x00, 0x0B, 0x00, 0x01, 0x02, 0xAB, 0xCD, 0x00, 0x00,
+        ];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::Broadcast);
+        assert_eq!(server.handler.test_registers[11], 0xABCD);
+
+        // the response fits, but not the decoded registers
+        let mut frame = [
+            0x01, 0x10, 0x00, 0x00, 0x00, 0x02, 0x04, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00,
+        ];
+        set_crc(&mut frame);
+        assert_eq!(
+            server.process_frame(&frame, &mut tx_buf[..8]),
+            Err(Error::BufferTooSmall)
+        );
+        frame[0] = BROADCAST_ID;
+        set_crc(&mut frame);
+        assert_eq!(
+            server.process_frame(&frame, &mut tx_buf[..5]),
+            Err(Error::BufferTooSmall)
+        );
+        assert_eq!(server.handler.test_registers[..2], [0, 0]);
+    }
+
     // Test exception handling
     struct ExceptionHandler;
     impl ModbusHandler for ExceptionHandler {
@@ -604,7 +1230,10 @@ mod tests {
         ];
 
         let mut tx_buf = [0u8; 32];
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
         let response = &tx_buf[..len];
 
         // Expected: [0x01, 0x81, 0x02, CRC_LO, CRC_HI]
@@ -628,9 +1257,9 @@ mod tests {
         ];
 
         let mut tx_buf = [0u8; 32];
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
 
-        assert_eq!(len, 0);
+        assert_eq!(outcome, Outcome::CrcError);
     }
 
     /// Test unsupported function
@@ -647,7 +1276,10 @@ mod tests {
         ];
 
         let mut tx_buf = [0u8; 32];
-        let len = server.process_frame(&frame, &mut tx_buf).unwrap();
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
 
         let response = &tx_buf[..len];
 
@@ -657,4 +1289,815 @@ mod tests {
         assert_eq!(response[1], 0x84); // Exception Function Code (0x01 | 0x80)
         assert_eq!(response[2], 0x01); // Exception Code (IllegalFunctioN)
     }
+
+    #[test]
+    fn frame_for_other_unit() {
+        let testdata = TestData {
+            test_coils: [false; 12],
+            test_registers: [0; 12],
+        };
+        let mut server = ModbusServer::new(1, testdata);
+
+        let mut frame: [u8; 8] = [
+            0x02, // Slave address (not ours)
+            0x06, // Function code: Write single register
+            0x00, 0x01, // Starting address: 1
+            0x12, 0x34, // Register Value: 0x1234
+            0x00, 0x00, // CRC16
+        ];
+        set_crc(&mut frame);
+
+        let mut tx_buf = [0u8; 32];
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+
+        assert_eq!(outcome, Outcome::NotForUs);
+        assert_eq!(outcome.response_len(), 0);
+        assert_eq!(server.handler.test_registers, [0; 12]);
+    }
+
+    #[test]
+    fn broadcast_write() {
+        let testdata = TestData {
+            test_coils: [false; 12],
+            test_registers: [0; 12],
+        };
+        let mut server = ModbusServer::new(1, testdata);
+
+        let mut frame: [u8; 8] = [
+            0x00, // Slave address: broadcast
+            0x06, // Function code: Write single register
+            0x00, 0x01, // Starting address: 1
+            0x12, 0x34, // Register Value: 0x1234
+            0x00, 0x00, // CRC16
+        ];
+        set_crc(&mut frame);
+
+        let mut tx_buf = [0u8; 32];
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+
+        assert_eq!(outcome, Outcome::Broadcast);
+        assert_eq!(server.handler.test_registers[1], 0x1234);
+    }
+
+    #[test]
+    fn broadcast_read_ignored() {
+        let mut server = ModbusServer::new(1, ExceptionHandler);
+
+        let mut frame: [u8; 8] = [
+            0x00, // Slave address: broadcast
+            0x01, // Function code: Read Coils
+            0x00, 0x00, // Starting address: 0
+            0x00, 0x01, // Quantity of coils: 1
+            0x00, 0x00, // CRC16
+        ];
+        set_crc(&mut frame);
+
+        let mut tx_buf = [0u8; 32];
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+
+        assert_eq!(outcome, Outcome::Broadcast);
+    }
+
+    #[test]
+    fn malformed_frames() {
+        let mut server = ModbusServer::new(1, ExceptionHandler);
+        let mut tx_buf = [0u8; 32];
+
+        // too short to hold unit ID, function code and CRC
+        let outcome = server.process_frame(&[0x01, 0x01, 0x00], &mut tx_buf);
+        assert_eq!(outcome, Ok(Outcome::Malformed));
+
+        // longer than the maximum RTU frame
+        let outcome = server.process_frame(&[0u8; 300], &mut tx_buf);
+        assert_eq!(outcome, Ok(Outcome::Malformed));
+
+        // valid CRC, but the PDU is truncated
+        let mut frame: [u8; 6] = [0x01, 0x03, 0x00, 0x00, 0x00, 0x00];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx_buf);
+        assert_eq!(outcome, Ok(Outcome::Malformed));
+    }
+
+    #[test]
+    fn listen_only() {
+        let testdata = TestData {
+            test_coils: [false; 12],
+            test_registers: [0; 12],
+        };
+        let mut server = ModbusServer::new(1, testdata);
+        server.set_listen_only(true);
+        assert!(server.is_listen_only());
+
+        let frame: [u8; 8] = [
+            0x01, // Slave address
+            0x06, // Function code: Write single register
+            0x00, 0x08, // Starting address: 8
+            0x12, 0x34, // Register Value: 0x1234
+            0x05, 0x7F, // CRC16 (low byte first)
+        ];
+        let mut tx_buf = [0u8; 32];
+
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::ListenOnly);
+        assert_eq!(server.handler.test_registers, [0; 12]);
+
+        server.set_listen_only(false);
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::Responded(frame.len()));
+        assert_eq!(server.handler.test_registers[8], 0x1234);
+    }
+
+    /// Handler which reports more or less processed items than requested
+    struct MiscountHandler {
+        over: bool,
+    }
+
+    impl MiscountHandler {
+        fn count(&self, len: usize) -> usize {
+            if self.over { len + 1 } else { len - 1 }
+        }
+    }
+
+    impl ModbusHandler for MiscountHandler {
+        fn read_coils(
+            &mut self,
+            _addr: usize,
+            len: usize,
+            _out: &mut [bool],
+        ) -> Result<usize, Error> {
+            Ok(self.count(len))
+        }
+
+        fn read_discrete_input(
+            &mut self,
+            _addr: usize,
+            len: usize,
+            _out: &mut [bool],
+        ) -> Result<usize, Error> {
+            Ok(self.count(len))
+        }
+
+        fn read_holding_registers(
+            &mut self,
+            _addr: usize,
+            len: usize,
+            _out: &mut [u16],
+        ) -> Result<usize, Error> {
+            Ok(self.count(len))
+        }
+
+        fn read_input_registers(
+            &mut self,
+            _addr: usize,
+            len: usize,
+            _out: &mut [u16],
+        ) -> Result<usize, Error> {
+            Ok(self.count(len))
+        }
+
+        fn write_coils(&mut self, _addr: usize, len: usize, _buf: &[bool]) -> Result<usize, Error> {
+            Ok(self.count(len))
+        }
+
+        fn write_registers(
+            &mut self,
+            _addr: usize,
+            len: usize,
+            _buf: &[u16],
+        ) -> Result<usize, Error> {
+            Ok(self.count(len))
+        }
+    }
+
+    /// Requests for every supported function code, CRC is filled in by the test
+    const ALL_FUNCTIONS: [[u8; 8]; 6] = [
+        [0x01, 0x01, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00], // Read Coils
+        [0x01, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00], // Read Discrete Inputs
+        [0x01, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00], // Read Holding Registers
+        [0x01, 0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00], // Read Input Registers
+        [0x01, 0x05, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00], // Write Single Coil
+        [0x01, 0x06, 0x00, 0x00, 0x12, 0x34, 0x00, 0x00], // Write Single Register
+    ];
+
+    fn assert_length_mismatch(over: bool) {
+        let mut server = ModbusServer::new(1, MiscountHandler { over });
+
+        for mut frame in ALL_FUNCTIONS {
+            set_crc(&mut frame);
+            let mut tx_buf = [0u8; 32];
+            let len = server
+                .process_frame(&frame, &mut tx_buf)
+                .unwrap()
+                .response_len();
+            let response = &tx_buf[..len];
+
+            assert_eq!(len, 5);
+            assert_eq!(response[1], frame[1] | 0x80); // Exception Function Code
+            assert_eq!(response[2], 0x04); // Exception Code (ServerDeviceFailure)
+        }
+    }
+
+    #[test]
+    fn handler_short_count() {
+        assert_length_mismatch(false);
+    }
+
+    #[test]
+    fn handler_over_long_count() {
+        assert_length_mismatch(true);
+    }
+
+    /// Send a request with the CRC filled in and return the exception code of the response
+    fn exception_code<H: ModbusHandler>(server: &mut ModbusServer<H>, frame: &mut [u8]) -> u8 {
+        set_crc(frame);
+        let mut tx_buf = [0u8; 32];
+        let len = server
+            .process_frame(frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
+
+        assert_eq!(len, 5);
+        assert_eq!(tx_buf[1], frame[1] | 0x80);
+        tx_buf[2]
+    }
+
+    #[test]
+    fn illegal_quantity() {
+        let mut server = ModbusServer::new(1, FuzzHandler);
+
+        for function in [0x01, 0x02, 0x03, 0x04] {
+            // quantity 0
+            let mut frame = [0x01, function, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+            assert_eq!(exception_code(&mut server, &mut frame), 0x03);
+
+            // quantity 0xFFFF
+            let mut frame = [0x01, function, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00];
+            assert_eq!(exception_code(&mut server, &mut frame), 0x03);
+        }
+
+        // 2001 coils
+        let mut frame = [0x01, 0x01, 0x00, 0x00, 0x07, 0xD1, 0x00, 0x00];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x03);
+
+        // 126 registers
+        let mut frame = [0x01, 0x03, 0x00, 0x00, 0x00, 0x7E, 0x00, 0x00];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x03);
+    }
+
+    #[test]
+    fn maximum_quantity() {
+        let mut server = ModbusServer::new(1, FuzzHandler);
+        let mut tx_buf = [0u8; 256];
+
+        // 2000 coils
+        let mut frame = [0x01, 0x01, 0x00, 0x00, 0x07, 0xD0, 0x00, 0x00];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::Responded(3 + 250 + 2));
+
+        // 125 registers
+        let mut frame = [0x01, 0x04, 0x00, 0x00, 0x00, 0x7D, 0x00, 0x00];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::Responded(3 + 250 + 2));
+    }
+
+    #[test]
+    fn address_overflow() {
+        let mut server = ModbusServer::new(1, FuzzHandler);
+
+        // coils 0xFFFF and 0x10000
+        let mut frame = [0x01, 0x02, 0xFF, 0xFF, 0x00, 0x02, 0x00, 0x00];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x02);
+
+        // registers 0xFF84..=0x10000
+        let mut frame = [0x01, 0x03, 0xFF, 0x84, 0x00, 0x7D, 0x00, 0x00];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x02);
+
+        // last register is fine
+        let mut frame = [0x01, 0x03, 0xFF, 0xFF, 0x00, 0x01, 0x00, 0x00];
+        set_crc(&mut frame);
+        let mut tx_buf = [0u8; 32];
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::Responded(7));
+    }
+
+    #[test]
+    fn illegal_coil_value() {
+        let mut server = ModbusServer::new(1, FuzzHandler);
+
+        let mut frame = [0x01, 0x05, 0x00, 0x00, 0x12, 0x34, 0x00, 0x00];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x03);
+    }
+
+    /// Handler accepting any request, it touches every item of the buffers it is given
+    struct FuzzHandler;
+
+    impl ModbusHandler for FuzzHandler {
+        fn read_coils(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [bool],
+        ) -> Result<usize, Error> {
+            for (i, slot) in out[..len].iter_mut().enumerate() {
+                *slot = (addr + i).is_multiple_of(3);
+            }
+            Ok(len)
+        }
+
+        fn read_discrete_input(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [bool],
+        ) -> Result<usize, Error> {
+            self.read_coils(addr, len, out)
+        }
+
+        fn read_holding_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            for (i, slot) in out[..len].iter_mut().enumerate() {
+                *slot = (addr + i) as u16;
+            }
+            Ok(len)
+        }
+
+        fn read_input_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            self.read_holding_registers(addr, len, out)
+        }
+
+        fn write_coils(&mut self, _addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+            Ok(buf[..len].len())
+        }
+
+        fn write_registers(
+            &mut self,
+            _addr: usize,
+            len: usize,
+            buf: &[u16],
+        ) -> Result<usize, Error> {
+            Ok(buf[..len].len())
+        }
+    }
+
+    /// Minimal xorshift generator, good enough to produce arbitrary frames
+    struct XorShift(u32);
+
+    impl XorShift {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+    }
+
+    #[test]
+    fn arbitrary_frames_never_panic() {
+        let mut server = ModbusServer::new(1, FuzzHandler);
+        let mut rng = XorShift(0x1234_5678);
+        let mut frame = [0u8; 300];
+        let mut tx_buf = [0u8; 256];
+
+        for i in 0..20_000u32 {
+            let len = rng.next() as usize % frame.len();
+            for byte in frame[..len].iter_mut() {
+                *byte = rng.next() as u8;
+            }
+            // most random frames fail the CRC check, give every other frame a valid one
+            // addressed to us, so the decoder and the handler dispatch get exercised as well
+            if i.is_multiple_of(2) && len >= MIN_FRAME_LEN {
+                frame[0] = (rng.next() % 3) as u8;
+                frame[1] = (rng.next() % 0x18) as u8;
+                set_crc(&mut frame[..len]);
+            }
+
+            match server.process_frame(&frame[..len], &mut tx_buf) {
+                Ok(Outcome::Responded(n)) => {
+                    assert!(n <= tx_buf.len());
+                    assert_eq!(tx_buf[0], 1);
+                    assert_eq!(tx_buf[1] & 0x7F, frame[1]);
+                }
+                Ok(_) => {}
+                Err(e) => panic!("unexpected error {e:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn small_tx_buffer_never_panics() {
+        let mut server = ModbusServer::new(1, FuzzHandler);
+
+        for mut frame in ALL_FUNCTIONS {
+            set_crc(&mut frame);
+            for tx_len in 0..8 {
+                let mut tx_buf = [0u8; 8];
+                match server.process_frame(&frame, &mut tx_buf[..tx_len]) {
+                    Ok(outcome) => assert!(outcome.response_len() <= tx_len),
+                    Err(e) => assert_eq!(e, Error::BufferTooSmall),
+                }
+            }
+        }
+    }
+
+    /// Handler recording the ranges it is called with
+    #[derive(Default)]
+    struct ChunkHandler {
+        calls: [(usize, usize); 8],
+        num_calls: usize,
+    }
+
+    impl ModbusHandler for ChunkHandler {
+        fn read_coils(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [bool],
+        ) -> Result<usize, Error> {
+            assert_eq!(out.len(), len);
+            self.calls[self.num_calls] = (addr, len);
+            self.num_calls += 1;
+            out.fill(true);
+            Ok(len)
+        }
+
+        fn read_holding_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            assert_eq!(out.len(), len);
+            self.calls[self.num_calls] = (addr, len);
+            self.num_calls += 1;
+            for (i, slot) in out.iter_mut().enumerate() {
+                *slot = (addr + i) as u16;
+            }
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn read_registers_at_once() {
+        // 100 registers starting at 10
+        let mut frame = [0x01, 0x03, 0x00, 0x0A, 0x00, 0x64, 0x00, 0x00];
+        set_crc(&mut frame);
+
+        // the registers are written into the tx buffer at both alignments
+        for start in [0, 1] {
+            let mut server = ModbusServer::new(1, ChunkHandler::default());
+            let mut tx_buf = [0u8; 257];
+            let tx_buf = &mut tx_buf[start..start + 256];
+            let len = server.process_frame(&frame, tx_buf).unwrap().response_len();
+
+            assert_eq!(len, 3 + 200 + 2);
+            assert_eq!(
+                server.handler.calls[..server.handler.num_calls],
+                [(10, 100)]
+            );
+            assert_eq!(tx_buf[..3], [0x01, 0x03, 200]);
+            for (i, word) in tx_buf[3..203].chunks(2).enumerate() {
+                assert_eq!(u16::from_be_bytes([word[0], word[1]]), 10 + i as u16);
+            }
+            let crc = crc16(&tx_buf[..203]);
+            assert_eq!(tx_buf[203..205], crc.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn read_value_across_chunk_boundary() {
+        /// 32 bit value in registers 31 and 32, incremented by every read
+        struct Counter(u32);
+
+        impl ModbusHandler for Counter {
+            fn read_holding_registers(
+                &mut self,
+                addr: usize,
+                len: usize,
+                out: &mut [u16],
+            ) -> Result<usize, Error> {
+                self.0 += 1;
+                for (reg, slot) in (addr..).zip(out.iter_mut()) {
+                    *slot = match reg {
+                        31 => (self.0 >> 16) as u16,
+                        32 => self.0 as u16,
+                        _ => 0,
+                    };
+                }
+                Ok(len)
+            }
+        }
+
+        let mut server = ModbusServer::new(1, Counter(0xFFFF));
+        let mut tx_buf = [0u8; 256];
+
+        // 40 registers starting at 0, the value straddles the end of the first 32 registers
+        let mut frame = [0x01, 0x03, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx_buf);
+
+        assert_eq!(outcome, Ok(Outcome::Responded(3 + 80 + 2)));
+        assert_eq!(server.handler.0, 0x10000);
+        assert_eq!(tx_buf[3 + 62..3 + 66], [0x00, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn read_coils_in_chunks() {
+        let mut server = ModbusServer::new(1, ChunkHandler::default());
+        let mut tx_buf = [0u8; 256];
+
+        // 70 coils starting at 3
+        let mut frame = [0x01, 0x01, 0x00, 0x03, 0x00, 0x46, 0x00, 0x00];
+        set_crc(&mut frame);
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
+
+        assert_eq!(len, 3 + 9 + 2);
+        assert_eq!(
+            server.handler.calls[..server.handler.num_calls],
+            [(3, 32), (35, 32), (67, 6)]
+        );
+        assert_eq!(tx_buf[2], 9);
+        assert_eq!(tx_buf[3..11], [0xFF; 8]);
+        assert_eq!(tx_buf[11], 0b0011_1111);
+    }
+
+    /// Handler keeping 32 coils packed in memory, only implementing the packed methods
+    struct PackedHandler {
+        coils: [u8; 4],
+    }
+
+    impl ModbusHandler for PackedHandler {
+        fn read_coils_packed(&mut self, addr: usize, out: &mut BitsMut) -> Result<usize, Error> {
+            let coils = Bits::new(&self.coils, 32).unwrap();
+            if addr + out.len() > coils.len() {
+                return Err(Error::InvalidAddress);
+            }
+            if addr.is_multiple_of(8) {
+                // byte aligned, copy the packed bytes directly
+                let bytes = out.as_bytes_mut();
+                let len = bytes.len();
+                bytes.copy_from_slice(&self.coils[addr / 8..addr / 8 + len]);
+            } else {
+                for (i, bit) in coils.iter().skip(addr).take(out.len()).enumerate() {
+                    out.set(i, bit);
+                }
+            }
+            Ok(out.len())
+        }
+
+        fn write_coils_packed(&mut self, addr: usize, bits: Bits) -> Result<usize, Error> {
+            let mut coils = BitsMut::new(&mut self.coils, 32).unwrap();
+            if addr + bits.len() > coils.len() {
+                return Err(Error::InvalidAddress);
+            }
+            for (i, bit) in bits.iter().enumerate() {
+                coils.set(addr + i, bit);
+            }
+            Ok(bits.len())
+        }
+    }
+
+    #[test]
+    fn packed_handler() {
+        let mut server = ModbusServer::new(
+            1,
+            PackedHandler {
+                coils: [0xA5, 0xFF, 0x00, 0x0F],
+            },
+        );
+        let mut tx_buf = [0u8; 32];
+
+        // byte aligned read of 12 coils, padding bits are cleared
+        let mut frame = [0x01, 0x01, 0x00, 0x00, 0x00, 0x0C, 0x00, 0x00];
+        set_crc(&mut frame);
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
+        assert_eq!(tx_buf[..len - 2], [0x01, 0x01, 0x02, 0xA5, 0x0F]);
+
+        // unaligned read of 8 coils starting at 4
+        let mut frame = [0x01, 0x01, 0x00, 0x04, 0x00, 0x08, 0x00, 0x00];
+        set_crc(&mut frame);
+        let len = server
+            .process_frame(&frame, &mut tx_buf)
+            .unwrap()
+            .response_len();
+        assert_eq!(tx_buf[..len - 2], [0x01, 0x01, 0x01, 0xFA]);
+
+        // write single coil 16
+        let mut frame = [0x01, 0x05, 0x00, 0x10, 0xFF, 0x00, 0x00, 0x00];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::Responded(8));
+        assert_eq!(server.handler.coils, [0xA5, 0xFF, 0x01, 0x0F]);
+
+        // discrete inputs fall back to the (unimplemented) bool slice method
+        let mut frame = [0x01, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x01);
+    }
+
+    /// Holds its unit ID in holding register 0
+    struct AddressHandler {
+        unit_id: Option<u8>,
+    }
+
+    impl ModbusHandler for AddressHandler {
+        fn write_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            buf: &[u16],
+        ) -> Result<usize, Error> {
+            if addr != 0 || len != 1 {
+                return Err(Error::InvalidAddress);
+            }
+            let unit_id = u8::try_from(buf[0]).map_err(|_| Error::InvalidValue)?;
+            if !UNIT_ID_RANGE.contains(&unit_id) {
+                return Err(Error::InvalidValue);
+            }
+            self.unit_id = Some(unit_id);
+            Ok(len)
+        }
+
+        fn take_unit_id_change(&mut self) -> Option<u8> {
+            self.unit_id.take()
+        }
+    }
+
+    #[test]
+    fn unit_id_setters() {
+        let mut server = ModbusServer::new(1, AddressHandler { unit_id: None });
+        assert_eq!(server.unit_id(), 1);
+
+        assert_eq!(server.set_unit_id(0), Err(Error::InvalidValue));
+        assert_eq!(server.set_unit_id(248), Err(Error::InvalidValue));
+        assert_eq!(server.set_unit_id_deferred(255), Err(Error::InvalidValue));
+        assert_eq!(server.unit_id(), 1);
+
+        server.set_unit_id(247).unwrap();
+        assert_eq!(server.unit_id(), 247);
+
+        server.set_unit_id_deferred(5).unwrap();
+        assert_eq!(server.unit_id(), 247);
+        assert_eq!(server.pending_unit_id(), Some(5));
+        server.transmit_complete();
+        assert_eq!(server.unit_id(), 5);
+        assert_eq!(server.pending_unit_id(), None);
+
+        for unit_id in [0, 248] {
+            let server = ModbusServer::try_new(unit_id, AddressHandler { unit_id: None });
+            assert!(matches!(server, Err(Error::InvalidValue)));
+        }
+        let server = ModbusServer::try_new(247, AddressHandler { unit_id: None }).unwrap();
+        assert_eq!(server.unit_id(), 247);
+    }
+
+    #[test]
+    fn invalid_unit_id_change() {
+        /// Records the rejected unit ID changes
+        #[derive(Default)]
+        struct Rejected(Option<u8>);
+
+        impl Observer for Rejected {
+            fn unit_id_rejected(&mut self, unit_id: u8) {
+                self.0 = Some(unit_id);
+            }
+        }
+
+        let handler = AddressHandler { unit_id: Some(248) };
+        let mut server = ModbusServer::new(1, handler).with_observer(Rejected::default());
+        let mut tx_buf = [0u8; 32];
+
+        let mut frame = [0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx_buf);
+        assert_eq!(outcome, Ok(Outcome::Responded(5)));
+        assert_eq!(server.observer().0, Some(248));
+        assert_eq!(server.pending_unit_id(), None);
+        server.transmit_complete();
+        assert_eq!(server.unit_id(), 1);
+
+        // a server without observer keeps its unit ID as well
+        let handler = AddressHandler { unit_id: Some(0) };
+        let mut server = ModbusServer::new(1, handler);
+        server.process_frame(&frame, &mut tx_buf).unwrap();
+        server.transmit_complete();
+        assert_eq!(server.unit_id(), 1);
+    }
+
+    #[test]
+    fn unit_id_changed_by_master() {
+        let mut server = ModbusServer::new(1, AddressHandler { unit_id: None });
+        let mut tx_buf = [0u8; 32];
+
+        // write 0x0020 to holding register 0, answered under the old unit ID
+        let mut frame = [0x01, 0x06, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::Responded(8));
+        assert_eq!(tx_buf[..8], frame);
+        assert_eq!(server.unit_id(), 1);
+
+        server.transmit_complete();
+        assert_eq!(server.unit_id(), 0x20);
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::NotForUs);
+
+        // invalid unit IDs are rejected by the handler
+        let mut frame = [0x20, 0x06, 0x00, 0x00, 0x00, 0xF8, 0x00, 0x00];
+        assert_eq!(exception_code(&mut server, &mut frame), 0x03);
+        server.transmit_complete();
+        assert_eq!(server.unit_id(), 0x20);
+
+        // broadcasts are not answered, the change is applied right away
+        let mut frame = [0x00, 0x06, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+        assert_eq!(outcome, Outcome::Broadcast);
+        assert_eq!(server.unit_id(), 3);
+    }
+}
+
+#[cfg(all(
+    test,
+    not(all(
+        feature = "coils",
+        feature = "discrete-inputs",
+        feature = "holding-registers",
+        feature = "input-registers"
+    ))
+))]
+mod feature_tests {
+    use super::*;
+    use crate::testing::set_crc;
+
+    struct EmptyHandler;
+    impl ModbusHandler for EmptyHandler {}
+
+    /// Requests of each function code and whether its feature is enabled
+    const REQUESTS: [([u8; 6], bool); 6] = [
+        (
+            [0x01, 0x01, 0x00, 0x00, 0x00, 0x01],
+            cfg!(feature = "coils"),
+        ),
+        (
+            [0x01, 0x02, 0x00, 0x00, 0x00, 0x01],
+            cfg!(feature = "discrete-inputs"),
+        ),
+        (
+            [0x01, 0x03, 0x00, 0x00, 0x00, 0x01],
+            cfg!(feature = "holding-registers"),
+        ),
+        (
+            [0x01, 0x04, 0x00, 0x00, 0x00, 0x01],
+            cfg!(feature = "input-registers"),
+        ),
+        (
+            [0x01, 0x05, 0x00, 0x00, 0xFF, 0x00],
+            cfg!(feature = "coils"),
+        ),
+        (
+            [0x01, 0x06, 0x00, 0x00, 0x12, 0x34],
+            cfg!(feature = "holding-registers"),
+        ),
+    ];
+
+    #[test]
+    fn disabled_functions_are_illegal() {
+        let mut server = ModbusServer::new(1, EmptyHandler);
+
+        for (adu, enabled) in REQUESTS {
+            // quantity 0 is rejected by the validation of enabled functions only
+            let mut frame = [0u8; 8];
+            frame[..6].copy_from_slice(&adu);
+            frame[5] = 0;
+            set_crc(&mut frame);
+
+            let mut tx_buf = [0u8; 32];
+            let outcome = server.process_frame(&frame, &mut tx_buf).unwrap();
+            assert_eq!(outcome, Outcome::Responded(5));
+
+            let expected = match (enabled, adu[1]) {
+                (false, _) => 0x01,   // IllegalFunction
+                (true, 0x05) => 0x01, // coil value 0xFF00 is fine, handler not implemented
+                (true, 0x06) => 0x01, // register value is fine, handler not implemented
+                (true, _) => 0x03,    // IllegalDataValue (quantity 0)
+            };
+            assert_eq!(tx_buf[2], expected, "function code {:#04x}", adu[1]);
+        }
+    }
 }
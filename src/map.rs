@@ -0,0 +1,1652 @@
+//! Declarative register map
+//!
+//! A [`RegisterMap`] lists the [`Field`]s of a device: name, data table, address, format, access,
+//! scaling and description. Declared once as a `static` in the firmware, it generates the register
+//! list for integrators with [`RegisterMap::write_csv`] or [`RegisterMap::write_json`], so the
+//! documentation cannot drift from the firmware:
+//!
+//! ```
+//! use modbus_server::map::{Field, Format, RegisterMap, Table};
+//!
+//! static FIELDS: [Field; 2] = [
+//!     Field::new("temperature", Table::InputRegisters, 0, Format::I16)
+//!         .scaling(0.1, 0.0)
+//!         .unit("°C")
+//!         .description("Sensor temperature"),
+//!     Field::new("setpoint", Table::HoldingRegisters, 100, Format::U16)
+//!         .description("Target value"),
+//! ];
+//! static MAP: RegisterMap = RegisterMap::new(&FIELDS);
+//!
+//! let mut csv = String::new();
+//! MAP.write_csv(&mut csv).unwrap();
+//! assert!(csv.starts_with("name,table,address,format,access,scale,offset,min,max,unit,"));
+//! ```
+//!
+//! Fields convert between engineering values and registers, see [`Field::encode_f32`] and
+//! [`Field::decode_f32`]. A [`MapHandler`] serves a map from a [`FieldHandler`], which reads and
+//! writes whole fields in physical units instead of raw registers.
+//!
+//! With the `std` feature, a CSV register list is imported again with [`parse_csv`], e.g. to serve
+//! it from a [`SimulatedHandler`] on a host.
+
+use core::{fmt, ops::Range};
+
+use crate::{error::Error, handler::ModbusHandler};
+
+/// Columns of the CSV register list, in this order
+pub const CSV_HEADER: &str =
+    "name,table,address,format,access,scale,offset,min,max,unit,description";
+
+/// Data table a field is located in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(feature = "defmt", target_os = "none"), derive(defmt::Format))]
+pub enum Table {
+    Coils,
+    DiscreteInputs,
+    HoldingRegisters,
+    InputRegisters,
+}
+
+impl Table {
+    /// Name in the register list
+    pub const fn name(self) -> &'static str {
+        match self {
+            Table::Coils => "coil",
+            Table::DiscreteInputs => "discrete_input",
+            Table::HoldingRegisters => "holding_register",
+            Table::InputRegisters => "input_register",
+        }
+    }
+
+    /// Table of a name in the register list
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Table::Coils,
+            Table::DiscreteInputs,
+            Table::HoldingRegisters,
+            Table::InputRegisters,
+        ]
+        .into_iter()
+        .find(|table| table.name() == name)
+    }
+
+    /// Whether masters can write to the table at all
+    pub const fn is_writable(self) -> bool {
+        matches!(self, Table::Coils | Table::HoldingRegisters)
+    }
+}
+
+/// Access of masters to a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(feature = "defmt", target_os = "none"), derive(defmt::Format))]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+impl Access {
+    /// Name in the register list
+    pub const fn name(self) -> &'static str {
+        match self {
+            Access::ReadOnly => "R",
+            Access::ReadWrite => "RW",
+        }
+    }
+
+    /// Access of a name in the register list
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "R" => Some(Access::ReadOnly),
+            "RW" => Some(Access::ReadWrite),
+            _ => None,
+        }
+    }
+}
+
+/// Largest length of string and byte array fields served by a [`MapHandler`], in bytes
+pub const MAX_TEXT_LEN: usize = 64;
+
+/// Encoding of a field value
+///
+/// Values spanning two registers are transferred high word first. The name in the register
+/// list is the [`fmt::Display`] output, e.g. `u16` or `string[16]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(feature = "defmt", target_os = "none"), derive(defmt::Format))]
+pub enum Format {
+    /// Single coil or discrete input
+    Bool,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    /// ASCII string, two characters per register
+    String(Text),
+    /// Byte array, two bytes per register
+    Bytes(Text),
+}
+
+impl Format {
+    /// Format of a name in the register list
+    pub fn from_name(name: &str) -> Option<Self> {
+        let scalar = match name {
+            "bool" => Some(Format::Bool),
+            "u16" => Some(Format::U16),
+            "i16" => Some(Format::I16),
+            "u32" => Some(Format::U32),
+            "i32" => Some(Format::I32),
+            "f32" => Some(Format::F32),
+            _ => None,
+        };
+        scalar.or_else(|| {
+            let (kind, len) = name.strip_suffix(']')?.split_once('[')?;
+            let (kind, order) = match kind.strip_suffix("_swapped") {
+                Some(kind) => (kind, ByteOrder::LowFirst),
+                None => (kind, ByteOrder::HighFirst),
+            };
+            let text = Text {
+                len: len
+                    .parse()
+                    .ok()
+                    .filter(|&len| len > 0 && len <= MAX_TEXT_LEN)?,
+                order,
+                padding: 0,
+            };
+            match kind {
+                "string" => Some(Format::String(text)),
+                "bytes" => Some(Format::Bytes(text)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Number of coils or registers occupied
+    pub const fn width(self) -> usize {
+        match self {
+            Format::Bool | Format::U16 | Format::I16 => 1,
+            Format::U32 | Format::I32 | Format::F32 => 2,
+            Format::String(text) | Format::Bytes(text) => text.len.div_ceil(2),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, text) = match self {
+            Format::Bool => return f.write_str("bool"),
+            Format::U16 => return f.write_str("u16"),
+            Format::I16 => return f.write_str("i16"),
+            Format::U32 => return f.write_str("u32"),
+            Format::I32 => return f.write_str("i32"),
+            Format::F32 => return f.write_str("f32"),
+            Format::String(text) => ("string", text),
+            Format::Bytes(text) => ("bytes", text),
+        };
+        let swapped = match text.order {
+            ByteOrder::HighFirst => "",
+            ByteOrder::LowFirst => "_swapped",
+        };
+        write!(f, "{kind}{swapped}[{}]", text.len)
+    }
+}
+
+/// Order of the two bytes of a string or byte array within a register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(feature = "defmt", target_os = "none"), derive(defmt::Format))]
+pub enum ByteOrder {
+    /// First byte in the high byte, like all Modbus values
+    HighFirst,
+    /// First byte in the low byte, `swapped` in the register list
+    LowFirst,
+}
+
+/// Layout of a fixed length string or byte array
+///
+/// Values shorter than `len` are filled up with `padding`, which is not part of the register
+/// list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(all(feature = "defmt", target_os = "none"), derive(defmt::Format))]
+pub struct Text {
+    /// Length in bytes, an odd length leaves the last byte of the last register as padding
+    pub len: usize,
+    pub order: ByteOrder,
+    pub padding: u8,
+}
+
+impl Text {
+    /// `len` bytes, high byte first and padded with 0
+    ///
+    /// Panics if `len` is 0 or larger than [`MAX_TEXT_LEN`], at compile time in a `static`.
+    pub const fn new(len: usize) -> Self {
+        assert!(len > 0 && len <= MAX_TEXT_LEN, "invalid text length");
+        Self {
+            len,
+            order: ByteOrder::HighFirst,
+            padding: 0,
+        }
+    }
+
+    /// Place the first byte of each register in the low byte
+    pub const fn swapped(mut self) -> Self {
+        self.order = ByteOrder::LowFirst;
+        self
+    }
+
+    /// Fill up shorter values with `padding`, e.g. `b' '`
+    pub const fn padding(mut self, padding: u8) -> Self {
+        self.padding = padding;
+        self
+    }
+}
+
+/// Entry of a [`RegisterMap`]
+///
+/// The engineering value of a field is `raw * scale + offset`, masters may only write values
+/// within `min..=max`. Fields of the firmware use `&'static str` for their texts, imported ones
+/// `String`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Field<S = &'static str> {
+    /// Identifier, unique within the map
+    pub name: S,
+    pub table: Table,
+    /// Address of the first coil or register
+    pub address: u16,
+    pub format: Format,
+    pub access: Access,
+    pub scale: f32,
+    pub offset: f32,
+    /// Smallest engineering value masters may write, `f32::NEG_INFINITY` if unbounded
+    pub min: f32,
+    /// Largest engineering value masters may write, `f32::INFINITY` if unbounded
+    pub max: f32,
+    /// Unit of the engineering value, may be empty
+    pub unit: S,
+    /// Free text for the register list, may be empty
+    pub description: S,
+}
+
+impl Field {
+    /// Field without scaling, unit and description, writable if the table is
+    pub const fn new(name: &'static str, table: Table, address: u16, format: Format) -> Self {
+        Self {
+            name,
+            table,
+            address,
+            format,
+            access: if table.is_writable() {
+                Access::ReadWrite
+            } else {
+                Access::ReadOnly
+            },
+            scale: 1.0,
+            offset: 0.0,
+            min: f32::NEG_INFINITY,
+            max: f32::INFINITY,
+            unit: "",
+            description: "",
+        }
+    }
+
+    /// Make a field of a writable table read only for masters
+    pub const fn read_only(mut self) -> Self {
+        self.access = Access::ReadOnly;
+        self
+    }
+
+    /// Set the scaling of the engineering value, `raw * scale + offset`
+    pub const fn scaling(mut self, scale: f32, offset: f32) -> Self {
+        self.scale = scale;
+        self.offset = offset;
+        self
+    }
+
+    /// Limit the engineering values masters may write to `min..=max`
+    pub const fn range(mut self, min: f32, max: f32) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Set the unit of the engineering value
+    pub const fn unit(mut self, unit: &'static str) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Set the description
+    pub const fn description(mut self, description: &'static str) -> Self {
+        self.description = description;
+        self
+    }
+}
+
+impl<S> Field<S> {
+    /// Coil or register addresses occupied
+    pub fn addresses(&self) -> Range<usize> {
+        let start = usize::from(self.address);
+        start..start + self.format.width()
+    }
+
+    /// Write the registers of the engineering value `value` to `out`
+    ///
+    /// The raw value is rounded to the nearest integer and saturated to the range of the format.
+    /// Returns the number of registers written, [`Error::BufferTooSmall`] if they do not fit and
+    /// [`Error::InvalidValue`] for strings and byte arrays.
+    pub fn encode_f32(&self, value: f32, out: &mut [u16]) -> Result<usize, Error> {
+        self.encode(f64::from(value), out)
+    }
+
+    /// Write the registers of the engineering value `value` to `out`, like [`Field::encode_f32`]
+    pub fn encode_i32(&self, value: i32, out: &mut [u16]) -> Result<usize, Error> {
+        self.encode(f64::from(value), out)
+    }
+
+    /// Engineering value of the registers `regs`, written by a master
+    ///
+    /// Fails with [`Error::InvalidValue`] (IllegalDataValue) if the value is outside of
+    /// `min..=max` or the field is a string or byte array, with [`Error::LengthMismatch`] if
+    /// `regs` does not match the format.
+    pub fn decode_f32(&self, regs: &[u16]) -> Result<f32, Error> {
+        self.decode(regs).map(|value| value as f32)
+    }
+
+    /// Engineering value of the registers `regs`, rounded to the nearest integer
+    ///
+    /// Fails like [`Field::decode_f32`], and with [`Error::InvalidValue`] if the value does not
+    /// fit into an `i32`.
+    pub fn decode_i32(&self, regs: &[u16]) -> Result<i32, Error> {
+        let value = round(self.decode(regs)?);
+        i32::try_from(value).map_err(|_| Error::InvalidValue)
+    }
+
+    /// Write the registers of a string or byte array to `out`
+    ///
+    /// `value` is filled up with the padding of the field, longer values are truncated. Fails with
+    /// [`Error::InvalidValue`] if the field is neither a string nor a byte array, with
+    /// [`Error::BufferTooSmall`] if the registers do not fit into `out`.
+    pub fn encode_bytes(&self, value: &[u8], out: &mut [u16]) -> Result<usize, Error> {
+        let (Format::String(text) | Format::Bytes(text)) = self.format else {
+            return Err(Error::InvalidValue);
+        };
+        let out = out
+            .get_mut(..self.format.width())
+            .ok_or(Error::BufferTooSmall)?;
+        let value = &value[..value.len().min(text.len)];
+        let byte = |i: usize| value.get(i).copied().unwrap_or(text.padding);
+        for (i, reg) in out.iter_mut().enumerate() {
+            let (first, second) = (byte(2 * i), byte(2 * i + 1));
+            *reg = match text.order {
+                ByteOrder::HighFirst => u16::from_be_bytes([first, second]),
+                ByteOrder::LowFirst => u16::from_le_bytes([first, second]),
+            };
+        }
+        Ok(out.len())
+    }
+
+    /// Unpack the string or byte array in the registers `regs` into `out`, written by a master
+    ///
+    /// Returns the length of the value. Trailing padding is removed from strings, which have to
+    /// be ASCII, otherwise the write fails with [`Error::InvalidValue`] (IllegalDataValue). Fails
+    /// with [`Error::LengthMismatch`] if `regs` does not match the format, with
+    /// [`Error::BufferTooSmall`] if the value does not fit into `out`.
+    pub fn decode_bytes(&self, regs: &[u16], out: &mut [u8]) -> Result<usize, Error> {
+        let (Format::String(text) | Format::Bytes(text)) = self.format else {
+            return Err(Error::InvalidValue);
+        };
+        if regs.len() != self.format.width() {
+            return Err(Error::LengthMismatch);
+        }
+        let out = out.get_mut(..text.len).ok_or(Error::BufferTooSmall)?;
+        let bytes = regs.iter().flat_map(|reg| match text.order {
+            ByteOrder::HighFirst => reg.to_be_bytes(),
+            ByteOrder::LowFirst => reg.to_le_bytes(),
+        });
+        out.iter_mut().zip(bytes).for_each(|(b, byte)| *b = byte);
+        if matches!(self.format, Format::Bytes(_)) {
+            return Ok(out.len());
+        }
+        let len = out.len() - out.iter().rev().take_while(|&&b| b == text.padding).count();
+        if out[..len].is_ascii() {
+            Ok(len)
+        } else {
+            Err(Error::InvalidValue)
+        }
+    }
+
+    fn encode(&self, value: f64, out: &mut [u16]) -> Result<usize, Error> {
+        let out = out
+            .get_mut(..self.format.width())
+            .ok_or(Error::BufferTooSmall)?;
+        let raw = (value - f64::from(self.offset)) / f64::from(self.scale);
+        let saturated = |min: i64, max: i64| round(raw).clamp(min, max);
+        match self.format {
+            Format::Bool => out[0] = u16::from(round(raw) != 0),
+            Format::U16 => out[0] = saturated(0, u16::MAX.into()) as u16,
+            Format::I16 => out[0] = saturated(i16::MIN.into(), i16::MAX.into()) as i16 as u16,
+            Format::U32 => split(saturated(0, u32::MAX.into()) as u32, out),
+            Format::I32 => split(saturated(i32::MIN.into(), i32::MAX.into()) as u32, out),
+            Format::F32 => split((raw as f32).to_bits(), out),
+            Format::String(_) | Format::Bytes(_) => return Err(Error::InvalidValue),
+        }
+        Ok(out.len())
+    }
+
+    fn decode(&self, regs: &[u16]) -> Result<f64, Error> {
+        if regs.len() != self.format.width() {
+            return Err(Error::LengthMismatch);
+        }
+        let join = || u32::from(regs[0]) << 16 | u32::from(regs[1]);
+        let raw = match self.format {
+            Format::Bool => f64::from(u8::from(regs[0] != 0)),
+            Format::U16 => f64::from(regs[0]),
+            Format::I16 => f64::from(regs[0] as i16),
+            Format::U32 => f64::from(join()),
+            Format::I32 => f64::from(join() as i32),
+            Format::F32 => f64::from(f32::from_bits(join())),
+            Format::String(_) | Format::Bytes(_) => return Err(Error::InvalidValue),
+        };
+        let value = raw * f64::from(self.scale) + f64::from(self.offset);
+        // NaN fails the range check as well
+        if value >= f64::from(self.min) && value <= f64::from(self.max) {
+            Ok(value)
+        } else {
+            Err(Error::InvalidValue)
+        }
+    }
+}
+
+/// Round to the nearest integer, half away from zero, saturating to the range of `i64`
+///
+/// `f64::round` needs `std`.
+fn round(value: f64) -> i64 {
+    if value >= 0.0 {
+        (value + 0.5) as i64
+    } else {
+        (value - 0.5) as i64
+    }
+}
+
+/// Write `value` to two registers, high word first
+fn split(value: u32, out: &mut [u16]) {
+    out[0] = (value >> 16) as u16;
+    out[1] = value as u16;
+}
+
+/// Fields of a device
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterMap<'a, S = &'static str> {
+    fields: &'a [Field<S>],
+}
+
+impl<'a, S: AsRef<str>> RegisterMap<'a, S> {
+    /// Map of `fields`, listed in this order
+    pub const fn new(fields: &'a [Field<S>]) -> Self {
+        Self { fields }
+    }
+
+    /// All fields
+    pub fn fields(&self) -> &'a [Field<S>] {
+        self.fields
+    }
+
+    /// Field with the name `name`
+    pub fn get(&self, name: &str) -> Option<&'a Field<S>> {
+        self.fields.iter().find(|field| field.name.as_ref() == name)
+    }
+
+    /// Field occupying `addr` of `table`
+    pub fn find(&self, table: Table, addr: usize) -> Option<&'a Field<S>> {
+        self.fields
+            .iter()
+            .find(|field| field.table == table && field.addresses().contains(&addr))
+    }
+
+    /// First pair of fields occupying the same address
+    pub fn overlap(&self) -> Option<(&'a Field<S>, &'a Field<S>)> {
+        self.fields.iter().enumerate().find_map(|(i, a)| {
+            self.fields[i + 1..].iter().find_map(|b| {
+                let (x, y) = (a.addresses(), b.addresses());
+                (a.table == b.table && x.start < y.end && y.start < x.end).then_some((a, b))
+            })
+        })
+    }
+
+    /// Write the register list as CSV, starting with [`CSV_HEADER`]
+    pub fn write_csv<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        writeln!(out, "{CSV_HEADER}")?;
+        for field in self.fields {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                Csv(field.name.as_ref()),
+                field.table.name(),
+                field.address,
+                field.format,
+                field.access.name(),
+                field.scale,
+                field.offset,
+                Bound(field.min, ""),
+                Bound(field.max, ""),
+                Csv(field.unit.as_ref()),
+                Csv(field.description.as_ref()),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the register list as a JSON array of objects, with the keys of [`CSV_HEADER`]
+    pub fn write_json<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        writeln!(out, "[")?;
+        for (i, field) in self.fields.iter().enumerate() {
+            let separator = if i + 1 < self.fields.len() { "," } else { "" };
+            writeln!(
+                out,
+                "  {{\"name\": {}, \"table\": \"{}\", \"address\": {}, \"format\": \"{}\", \
+                 \"access\": \"{}\", \"scale\": {}, \"offset\": {}, \"min\": {}, \
+                 \"max\": {}, \"unit\": {}, \"description\": {}}}{separator}",
+                Json(field.name.as_ref()),
+                field.table.name(),
+                field.address,
+                field.format,
+                field.access.name(),
+                field.scale,
+                field.offset,
+                Bound(field.min, "null"),
+                Bound(field.max, "null"),
+                Json(field.unit.as_ref()),
+                Json(field.description.as_ref()),
+            )?;
+        }
+        writeln!(out, "]")
+    }
+}
+
+/// Limit of the value range, the text in the second field if unbounded
+struct Bound(f32, &'static str);
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            value if value.is_infinite() => f.write_str(self.1),
+            value => write!(f, "{value}"),
+        }
+    }
+}
+
+/// CSV cell, quoted if necessary
+struct Csv<'a>(&'a str);
+
+impl fmt::Display for Csv<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.0.contains([',', '"', '\n', '\r']) {
+            return f.write_str(self.0);
+        }
+        f.write_str("\"")?;
+        for (i, part) in self.0.split('"').enumerate() {
+            if i > 0 {
+                f.write_str("\"\"")?;
+            }
+            f.write_str(part)?;
+        }
+        f.write_str("\"")
+    }
+}
+
+/// JSON string literal
+struct Json<'a>(&'a str);
+
+impl fmt::Display for Json<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", u32::from(c))?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+/// Engineering value of a field
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+}
+
+impl Value {
+    /// The value as `bool`, `true` if not 0
+    pub fn as_bool(self) -> bool {
+        match self {
+            Value::Bool(value) => value,
+            Value::Int(value) => value != 0,
+            Value::Float(value) => value != 0.0,
+        }
+    }
+
+    /// The value as `i32`, a float rounded to the nearest integer and saturated
+    pub fn as_i32(self) -> i32 {
+        match self {
+            Value::Bool(value) => i32::from(value),
+            Value::Int(value) => value,
+            Value::Float(value) => {
+                round(f64::from(value)).clamp(i32::MIN.into(), i32::MAX.into()) as i32
+            }
+        }
+    }
+
+    /// The value as `f32`
+    pub fn as_f32(self) -> f32 {
+        match self {
+            Value::Bool(value) => f32::from(u8::from(value)),
+            Value::Int(value) => value as f32,
+            Value::Float(value) => value,
+        }
+    }
+}
+
+/// Application side of a [`MapHandler`], accessing whole fields in engineering values
+///
+/// Strings and byte arrays are accessed with [`FieldHandler::read_bytes`] and
+/// [`FieldHandler::write_bytes`], all other fields with [`FieldHandler::read_field`] and
+/// [`FieldHandler::write_field`].
+pub trait FieldHandler {
+    /// Current value of `field`
+    fn read_field(&mut self, field: &Field) -> Result<Value, Error>;
+
+    /// Write the current string or byte array of `field` to `out`, which holds the length of
+    /// the field
+    ///
+    /// Returns the length of the value, the rest is filled up with the padding of the field.
+    fn read_bytes(&mut self, _field: &Field, _out: &mut [u8]) -> Result<usize, Error> {
+        Err(Error::NotSupported)
+    }
+
+    /// Set `field` to `value`, written by a master
+    ///
+    /// `value` is within `field.min..=field.max`. Fields of coils receive a [`Value::Bool`],
+    /// fields of the F32 format or with scaling a [`Value::Float`], all others a [`Value::Int`].
+    fn write_field(&mut self, _field: &Field, _value: Value) -> Result<(), Error> {
+        Err(Error::NotSupported)
+    }
+
+    /// Set the string or byte array of `field` to `value`, written by a master
+    ///
+    /// Trailing padding is already removed from strings.
+    fn write_bytes(&mut self, _field: &Field, _value: &[u8]) -> Result<(), Error> {
+        Err(Error::NotSupported)
+    }
+}
+
+/// Handler serving the fields of a [`RegisterMap`] from a [`FieldHandler`]
+///
+/// Reads may start and end within a field spanning several registers, e.g. in the middle of a
+/// string. Strings and byte arrays may be up to [`MAX_TEXT_LEN`] bytes long. Writes have to cover
+/// complete read-write fields, otherwise they fail with [`Error::InvalidAddress`] before
+/// any field is written. Values outside of the range of a field are rejected with
+/// [`Error::InvalidValue`] (IllegalDataValue), also before any field is written.
+///
+/// Masters write fields of several registers, e.g. F32 values or strings, with Write Multiple
+/// Registers. Write Single Register only reaches fields of a single register.
+pub struct MapHandler<'a, F> {
+    map: RegisterMap<'a>,
+    handler: F,
+}
+
+impl<'a, F: FieldHandler> MapHandler<'a, F> {
+    /// Serve the fields of `map` from `handler`
+    pub fn new(map: RegisterMap<'a>, handler: F) -> Self {
+        Self { map, handler }
+    }
+
+    /// The served register map
+    pub fn map(&self) -> RegisterMap<'a> {
+        self.map
+    }
+
+    /// Reference to the wrapped handler
+    pub fn inner(&self) -> &F {
+        &self.handler
+    }
+
+    /// Mutable reference to the wrapped handler
+    pub fn inner_mut(&mut self) -> &mut F {
+        &mut self.handler
+    }
+
+    /// Read the coils or discrete inputs from `addr` on into `out`
+    #[cfg(any(feature = "coils", feature = "discrete-inputs"))]
+    fn read_bits(&mut self, table: Table, addr: usize, out: &mut [bool]) -> Result<usize, Error> {
+        for (a, slot) in (addr..).zip(out.iter_mut()) {
+            let field = self.map.find(table, a).ok_or(Error::InvalidAddress)?;
+            *slot = self.handler.read_field(field)?.as_bool();
+        }
+        Ok(out.len())
+    }
+
+    /// Read the registers from `addr` on into `out`
+    #[cfg(any(feature = "holding-registers", feature = "input-registers"))]
+    fn read_registers(
+        &mut self,
+        table: Table,
+        addr: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        let end = addr + out.len();
+        let mut pos = addr;
+        while pos < end {
+            let field = self.map.find(table, pos).ok_or(Error::InvalidAddress)?;
+            let mut regs = [0u16; MAX_TEXT_LEN / 2];
+            match field.format {
+                Format::String(text) | Format::Bytes(text) => {
+                    let mut bytes = [0u8; MAX_TEXT_LEN];
+                    let bytes = bytes.get_mut(..text.len).ok_or(Error::BufferTooSmall)?;
+                    let len = self.handler.read_bytes(field, bytes)?;
+                    field.encode_bytes(&bytes[..len.min(text.len)], &mut regs)?
+                }
+                _ => match self.handler.read_field(field)? {
+                    Value::Float(value) => field.encode_f32(value, &mut regs)?,
+                    value => field.encode_i32(value.as_i32(), &mut regs)?,
+                },
+            };
+            let addresses = field.addresses();
+            let next = addresses.end.min(end);
+            out[pos - addr..next - addr]
+                .copy_from_slice(&regs[pos - addresses.start..next - addresses.start]);
+            pos = next;
+        }
+        Ok(out.len())
+    }
+}
+
+impl<F: FieldHandler> ModbusHandler for MapHandler<'_, F> {
+    #[cfg(feature = "coils")]
+    fn read_coils(&mut self, addr: usize, len: usize, out: &mut [bool]) -> Result<usize, Error> {
+        self.read_bits(Table::Coils, addr, &mut out[..len])
+    }
+
+    #[cfg(feature = "discrete-inputs")]
+    fn read_discrete_input(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [bool],
+    ) -> Result<usize, Error> {
+        self.read_bits(Table::DiscreteInputs, addr, &mut out[..len])
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn read_holding_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.read_registers(Table::HoldingRegisters, addr, &mut out[..len])
+    }
+
+    #[cfg(feature = "input-registers")]
+    fn read_input_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.read_registers(Table::InputRegisters, addr, &mut out[..len])
+    }
+
+    #[cfg(feature = "coils")]
+    fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+        // check all coils first, so a failed write changes nothing
+        let writable = |a| {
+            self.map
+                .find(Table::Coils, a)
+                .is_some_and(|field| field.access == Access::ReadWrite)
+        };
+        if !(addr..addr + len).all(writable) {
+            return Err(Error::InvalidAddress);
+        }
+        for (a, &value) in (addr..).zip(&buf[..len]) {
+            if let Some(field) = self.map.find(Table::Coils, a) {
+                self.handler.write_field(field, Value::Bool(value))?;
+            }
+        }
+        Ok(len)
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn write_registers(&mut self, addr: usize, len: usize, buf: &[u16]) -> Result<usize, Error> {
+        let end = addr + len;
+        // validate all fields first, so a rejected write changes nothing
+        for apply in [false, true] {
+            let mut pos = addr;
+            while pos < end {
+                let field = self
+                    .map
+                    .find(Table::HoldingRegisters, pos)
+                    .ok_or(Error::InvalidAddress)?;
+                let addresses = field.addresses();
+                if addresses.start < addr
+                    || addresses.end > end
+                    || field.access != Access::ReadWrite
+                {
+                    return Err(Error::InvalidAddress);
+                }
+                let regs = &buf[addresses.start - addr..addresses.end - addr];
+                if let Format::String(_) | Format::Bytes(_) = field.format {
+                    let mut bytes = [0u8; MAX_TEXT_LEN];
+                    let len = field.decode_bytes(regs, &mut bytes)?;
+                    if apply {
+                        self.handler.write_bytes(field, &bytes[..len])?;
+                    }
+                } else {
+                    let value =
+                        if field.format == Format::F32 || field.scale != 1.0 || field.offset != 0.0
+                        {
+                            Value::Float(field.decode_f32(regs)?)
+                        } else {
+                            Value::Int(field.decode_i32(regs)?)
+                        };
+                    if apply {
+                        self.handler.write_field(field, value)?;
+                    }
+                }
+                pos = addresses.end;
+            }
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "std")]
+pub use import::{ImportError, SimulatedHandler, parse_csv};
+
+#[cfg(feature = "std")]
+mod import {
+    use std::{collections::BTreeMap, fmt, string::String, vec::Vec};
+
+    use super::{Access, CSV_HEADER, Field, Format, RegisterMap, Table};
+    use crate::{error::Error, handler::ModbusHandler};
+
+    /// Invalid line in an imported register list
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ImportError {
+        /// Line number, starting at 1
+        pub line: usize,
+        pub reason: &'static str,
+    }
+
+    impl fmt::Display for ImportError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "line {}: {}", self.line, self.reason)
+        }
+    }
+
+    impl std::error::Error for ImportError {}
+
+    /// Parse a register list written by [`RegisterMap::write_csv`]
+    ///
+    /// Overlapping fields are rejected.
+    pub fn parse_csv(text: &str) -> Result<Vec<Field<String>>, ImportError> {
+        let mut records = Records::new(text);
+        match records.next() {
+            Some((_, Ok(header))) if header.join(",") == CSV_HEADER => {}
+            Some((line, _)) => {
+                return Err(ImportError {
+                    line,
+                    reason: "unexpected header",
+                });
+            }
+            None => {
+                return Err(ImportError {
+                    line: 1,
+                    reason: "missing header",
+                });
+            }
+        }
+
+        let mut fields: Vec<Field<String>> = Vec::new();
+        for (line, record) in records {
+            let error = |reason| ImportError { line, reason };
+            let record = record.map_err(error)?;
+            let [
+                name,
+                table,
+                address,
+                format,
+                access,
+                scale,
+                offset,
+                min,
+                max,
+                unit,
+                description,
+            ] = <[String; 11]>::try_from(record).map_err(|_| error("expected 11 columns"))?;
+            let bound = |value: &str, unbounded| match value {
+                "" => Ok(unbounded),
+                value => value.parse().map_err(|_| error("invalid range")),
+            };
+            let field = Field {
+                name,
+                table: Table::from_name(&table).ok_or(error("invalid table"))?,
+                address: address.parse().map_err(|_| error("invalid address"))?,
+                format: Format::from_name(&format).ok_or(error("invalid format"))?,
+                access: Access::from_name(&access).ok_or(error("invalid access"))?,
+                scale: scale.parse().map_err(|_| error("invalid scale"))?,
+                offset: offset.parse().map_err(|_| error("invalid offset"))?,
+                min: bound(&min, f32::NEG_INFINITY)?,
+                max: bound(&max, f32::INFINITY)?,
+                unit,
+                description,
+            };
+            let bits = matches!(field.table, Table::Coils | Table::DiscreteInputs);
+            if bits != (field.format == Format::Bool) {
+                return Err(error("format does not match the table"));
+            }
+            if field.addresses().end > 0x1_0000 {
+                return Err(error("exceeds the address space"));
+            }
+            if field.access == Access::ReadWrite && !field.table.is_writable() {
+                return Err(error("table is read only"));
+            }
+            fields.push(field);
+            if RegisterMap::new(&fields).overlap().is_some() {
+                return Err(error("overlaps a previous field"));
+            }
+        }
+        Ok(fields)
+    }
+
+    /// Records of a CSV text with their line numbers, empty lines are skipped
+    struct Records<'a> {
+        chars: core::iter::Peekable<core::str::Chars<'a>>,
+        line: usize,
+    }
+
+    impl<'a> Records<'a> {
+        fn new(text: &'a str) -> Self {
+            Self {
+                chars: text.chars().peekable(),
+                line: 1,
+            }
+        }
+
+        /// Parse the cells of the next record
+        fn record(&mut self) -> Result<Vec<String>, &'static str> {
+            let mut cells = Vec::new();
+            let mut cell = String::new();
+            let mut quoted = false;
+            while let Some(c) = self.chars.next() {
+                match c {
+                    '"' if quoted && self.chars.peek() == Some(&'"') => {
+                        self.chars.next();
+                        cell.push('"');
+                    }
+                    '"' if quoted => quoted = false,
+                    '"' if cell.is_empty() => quoted = true,
+                    ',' if !quoted => cells.push(core::mem::take(&mut cell)),
+                    '\n' if !quoted => break,
+                    '\r' if !quoted => {}
+                    c => {
+                        if c == '\n' {
+                            self.line += 1;
+                        }
+                        cell.push(c);
+                    }
+                }
+            }
+            if quoted {
+                return Err("unterminated quote");
+            }
+            cells.push(cell);
+            Ok(cells)
+        }
+    }
+
+    impl Iterator for Records<'_> {
+        type Item = (usize, Result<Vec<String>, &'static str>);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            loop {
+                self.chars.peek()?;
+                let line = self.line;
+                let record = self.record();
+                self.line += 1;
+                if !matches!(&record, Ok(cells) if cells.len() == 1 && cells[0].is_empty()) {
+                    return Some((line, record));
+                }
+            }
+        }
+    }
+
+    /// Value of one coil or register, and whether masters may write it
+    struct Entry<T> {
+        value: T,
+        #[cfg_attr(
+            not(any(feature = "coils", feature = "holding-registers")),
+            allow(dead_code)
+        )]
+        writable: bool,
+    }
+
+    /// Handler serving the fields of a register map from memory, e.g. an imported register list
+    ///
+    /// All values start at 0. Accesses to addresses without a field, and writes to read only
+    /// fields, fail with [`Error::InvalidAddress`].
+    pub struct SimulatedHandler {
+        fields: Vec<Field<String>>,
+        bits: BTreeMap<(u8, usize), Entry<bool>>,
+        registers: BTreeMap<(u8, usize), Entry<u16>>,
+    }
+
+    impl SimulatedHandler {
+        /// Serve `fields`
+        pub fn new(fields: Vec<Field<String>>) -> Self {
+            let mut bits = BTreeMap::new();
+            let mut registers = BTreeMap::new();
+            for field in &fields {
+                let writable = field.access == Access::ReadWrite;
+                for addr in field.addresses() {
+                    let key = (field.table as u8, addr);
+                    if field.format == Format::Bool {
+                        bits.insert(
+                            key,
+                            Entry {
+                                value: false,
+                                writable,
+                            },
+                        );
+                    } else {
+                        registers.insert(key, Entry { value: 0, writable });
+                    }
+                }
+            }
+            Self {
+                fields,
+                bits,
+                registers,
+            }
+        }
+
+        /// Serve the fields of a CSV register list, see [`parse_csv`]
+        pub fn from_csv(text: &str) -> Result<Self, ImportError> {
+            parse_csv(text).map(Self::new)
+        }
+
+        /// Map of the served fields
+        pub fn map(&self) -> RegisterMap<'_, String> {
+            RegisterMap::new(&self.fields)
+        }
+
+        /// Value of the coil or discrete input `addr` of `table`
+        pub fn bit(&self, table: Table, addr: usize) -> Option<bool> {
+            self.bits.get(&(table as u8, addr)).map(|e| e.value)
+        }
+
+        /// Set a coil or discrete input, regardless of the access of masters
+        ///
+        /// Returns `false` if no field occupies the address.
+        pub fn set_bit(&mut self, table: Table, addr: usize, value: bool) -> bool {
+            self.bits
+                .get_mut(&(table as u8, addr))
+                .map(|e| e.value = value)
+                .is_some()
+        }
+
+        /// Value of the register `addr` of `table`
+        pub fn register(&self, table: Table, addr: usize) -> Option<u16> {
+            self.registers.get(&(table as u8, addr)).map(|e| e.value)
+        }
+
+        /// Set a register, regardless of the access of masters
+        ///
+        /// Returns `false` if no field occupies the address.
+        pub fn set_register(&mut self, table: Table, addr: usize, value: u16) -> bool {
+            self.registers
+                .get_mut(&(table as u8, addr))
+                .map(|e| e.value = value)
+                .is_some()
+        }
+    }
+
+    fn read<T: Copy>(
+        entries: &BTreeMap<(u8, usize), Entry<T>>,
+        table: Table,
+        addr: usize,
+        out: &mut [T],
+    ) -> Result<usize, Error> {
+        for (i, slot) in out.iter_mut().enumerate() {
+            let entry = entries
+                .get(&(table as u8, addr + i))
+                .ok_or(Error::InvalidAddress)?;
+            *slot = entry.value;
+        }
+        Ok(out.len())
+    }
+
+    #[cfg(any(feature = "coils", feature = "holding-registers"))]
+    fn write<T: Copy>(
+        entries: &mut BTreeMap<(u8, usize), Entry<T>>,
+        table: Table,
+        addr: usize,
+        values: &[T],
+    ) -> Result<usize, Error> {
+        // check the complete range first, so a failed write changes nothing
+        let writable = |a| entries.get(&(table as u8, a)).is_some_and(|e| e.writable);
+        if !(addr..addr + values.len()).all(writable) {
+            return Err(Error::InvalidAddress);
+        }
+        for (i, value) in values.iter().enumerate() {
+            if let Some(entry) = entries.get_mut(&(table as u8, addr + i)) {
+                entry.value = *value;
+            }
+        }
+        Ok(values.len())
+    }
+
+    impl ModbusHandler for SimulatedHandler {
+        #[cfg(feature = "coils")]
+        fn read_coils(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [bool],
+        ) -> Result<usize, Error> {
+            read(&self.bits, Table::Coils, addr, &mut out[..len])
+        }
+
+        #[cfg(feature = "discrete-inputs")]
+        fn read_discrete_input(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [bool],
+        ) -> Result<usize, Error> {
+            read(&self.bits, Table::DiscreteInputs, addr, &mut out[..len])
+        }
+
+        #[cfg(feature = "holding-registers")]
+        fn read_holding_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            read(
+                &self.registers,
+                Table::HoldingRegisters,
+                addr,
+                &mut out[..len],
+            )
+        }
+
+        #[cfg(feature = "input-registers")]
+        fn read_input_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            read(
+                &self.registers,
+                Table::InputRegisters,
+                addr,
+                &mut out[..len],
+            )
+        }
+
+        #[cfg(feature = "coils")]
+        fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+            write(&mut self.bits, Table::Coils, addr, &buf[..len])
+        }
+
+        #[cfg(feature = "holding-registers")]
+        fn write_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            buf: &[u16],
+        ) -> Result<usize, Error> {
+            write(
+                &mut self.registers,
+                Table::HoldingRegisters,
+                addr,
+                &buf[..len],
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    static FIELDS: [Field; 4] = [
+        Field::new("temperature", Table::InputRegisters, 0, Format::I16)
+            .scaling(0.1, 0.0)
+            .unit("°C")
+            .description("Sensor temperature"),
+        Field::new("energy", Table::InputRegisters, 1, Format::U32).unit("Wh"),
+        Field::new("setpoint", Table::HoldingRegisters, 100, Format::F32)
+            .scaling(1.0, -40.0)
+            .range(-40.0, 150.0)
+            .description("Target, \"raw\" value\nsee manual"),
+        Field::new("relay", Table::Coils, 3, Format::Bool)
+            .read_only()
+            .description("Output, on = closed"),
+    ];
+
+    static MAP: RegisterMap = RegisterMap::new(&FIELDS);
+
+    #[test]
+    fn export() {
+        let mut csv = String::new();
+        MAP.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            csv,
+            "name,table,address,format,access,scale,offset,min,max,unit,description\n\
+             temperature,input_register,0,i16,R,0.1,0,,,°C,Sensor temperature\n\
+             energy,input_register,1,u32,R,1,0,,,Wh,\n\
+             setpoint,holding_register,100,f32,RW,1,-40,-40,150,,\"Target, \"\"raw\"\" value\nsee manual\"\n\
+             relay,coil,3,bool,R,1,0,,,,\"Output, on = closed\"\n"
+        );
+
+        let mut json = String::new();
+        MAP.write_json(&mut json).unwrap();
+        assert!(json.starts_with(
+            "[\n  {\"name\": \"temperature\", \"table\": \"input_register\", \"address\": 0, \
+             \"format\": \"i16\", \"access\": \"R\", \"scale\": 0.1, \"offset\": 0, \
+             \"min\": null, \"max\": null, \"unit\": \"°C\", \
+             \"description\": \"Sensor temperature\"},\n"
+        ));
+        assert!(json.contains("\"offset\": -40, \"min\": -40, \"max\": 150, "));
+        assert!(json.contains("\"description\": \"Target, \\\"raw\\\" value\\nsee manual\"}"));
+        assert!(json.ends_with("\"description\": \"Output, on = closed\"}\n]\n"));
+    }
+
+    #[test]
+    fn lookup() {
+        assert_eq!(MAP.get("energy"), Some(&FIELDS[1]));
+        assert_eq!(MAP.find(Table::InputRegisters, 2), Some(&FIELDS[1]));
+        assert_eq!(MAP.find(Table::HoldingRegisters, 2), None);
+        assert!(MAP.overlap().is_none());
+
+        let overlapping = [
+            FIELDS[1],
+            Field::new("low", Table::InputRegisters, 2, Format::U16),
+        ];
+        let map = RegisterMap::new(&overlapping);
+        assert_eq!(map.overlap(), Some((&overlapping[0], &overlapping[1])));
+    }
+
+    #[cfg(all(feature = "std", feature = "holding-registers", feature = "coils"))]
+    #[test]
+    fn import() {
+        use crate::{error::Error, handler::ModbusHandler};
+
+        let mut csv = String::new();
+        MAP.write_csv(&mut csv).unwrap();
+        let fields = parse_csv(&csv).unwrap();
+        assert_eq!(fields.len(), FIELDS.len());
+        for (imported, field) in fields.iter().zip(&FIELDS) {
+            assert_eq!(imported.name, field.name);
+            assert_eq!(imported.description, field.description);
+            assert_eq!(imported.addresses(), field.addresses());
+            assert_eq!(
+                (
+                    imported.format,
+                    imported.access,
+                    imported.scale,
+                    imported.offset,
+                    imported.min,
+                    imported.max,
+                ),
+                (
+                    field.format,
+                    field.access,
+                    field.scale,
+                    field.offset,
+                    field.min,
+                    field.max,
+                )
+            );
+        }
+
+        let mut handler = SimulatedHandler::new(fields);
+        let mut out = [0u16; 2];
+        assert_eq!(handler.write_registers(100, 2, &[1, 2]), Ok(2));
+        assert_eq!(handler.read_holding_registers(100, 2, &mut out), Ok(2));
+        assert_eq!(out, [1, 2]);
+        assert_eq!(
+            handler.write_registers(101, 2, &[3, 4]),
+            Err(Error::InvalidAddress)
+        );
+        assert_eq!(
+            handler.write_coils(3, 1, &[true]),
+            Err(Error::InvalidAddress)
+        );
+        assert!(handler.set_bit(Table::Coils, 3, true));
+        assert_eq!(handler.bit(Table::Coils, 3), Some(true));
+        assert_eq!(handler.map().get("relay").unwrap().address, 3);
+
+        let error = |line, reason| Err(ImportError { line, reason });
+        assert_eq!(parse_csv(""), error(1, "missing header"));
+        assert_eq!(parse_csv("name,table\n"), error(1, "unexpected header"));
+        let rows = [
+            ("a,coil,0,u16,RW,1,0,,,,", "format does not match the table"),
+            ("a,input_register,0,u16,RW,1,0,,,,", "table is read only"),
+            (
+                "a,holding_register,65535,u32,RW,1,0,,,,",
+                "exceeds the address space",
+            ),
+            ("a,holding_register,x,u16,RW,1,0,,,,", "invalid address"),
+            (
+                "a,holding_register,0,u16,RW,1,0,,,,\"x",
+                "unterminated quote",
+            ),
+            ("a,holding_register,0,u16,RW,1,0,x,,,", "invalid range"),
+            ("a,holding_register,0,u16,RW", "expected 11 columns"),
+            (
+                "energy,input_register,2,u16,R,1,0,,,,",
+                "overlaps a previous field",
+            ),
+        ];
+        for (row, reason) in rows {
+            let text = std::format!("{csv}\n{row}\n");
+            assert_eq!(parse_csv(&text), error(8, reason), "{row}");
+        }
+    }
+
+    #[test]
+    fn convert() {
+        let temperature = FIELDS[0].range(-40.0, 150.0);
+        let mut regs = [0u16; 2];
+        assert_eq!(temperature.encode_f32(21.46, &mut regs), Ok(1));
+        assert_eq!(regs[0], 215);
+        assert_eq!(temperature.encode_f32(-12.34, &mut regs), Ok(1));
+        assert_eq!(regs[0] as i16, -123);
+        assert_eq!(temperature.encode_f32(-5000.0, &mut regs), Ok(1));
+        assert_eq!(regs[0] as i16, i16::MIN);
+        assert_eq!(temperature.decode_f32(&[215]), Ok(21.5));
+        assert_eq!(temperature.decode_i32(&[215]), Ok(22));
+        assert_eq!(temperature.decode_f32(&[1501]), Err(Error::InvalidValue));
+        assert_eq!(temperature.decode_f32(&[1, 2]), Err(Error::LengthMismatch));
+
+        // Pressure in bar, transferred in mbar above vacuum
+        let pressure = Field::new("pressure", Table::HoldingRegisters, 0, Format::U16)
+            .scaling(0.001, -1.0)
+            .range(-1.0, 10.0);
+        assert_eq!(pressure.encode_f32(2.5, &mut regs), Ok(1));
+        assert_eq!(regs[0], 3500);
+        assert_eq!(pressure.encode_f32(-2.0, &mut regs), Ok(1));
+        assert_eq!(regs[0], 0);
+        assert_eq!(pressure.encode_f32(100.0, &mut regs), Ok(1));
+        assert_eq!(regs[0], u16::MAX);
+        assert_eq!(pressure.decode_f32(&[12000]), Err(Error::InvalidValue));
+
+        let energy = FIELDS[1];
+        assert_eq!(energy.encode_i32(70000, &mut regs), Ok(2));
+        assert_eq!(regs, [1, 4464]);
+        assert_eq!(energy.encode_i32(-1, &mut regs), Ok(2));
+        assert_eq!(regs, [0, 0]);
+        assert_eq!(
+            energy.decode_i32(&[0xFFFF, 0xFFFF]),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            energy.encode_i32(1, &mut regs[..1]),
+            Err(Error::BufferTooSmall)
+        );
+
+        let setpoint = FIELDS[2];
+        assert_eq!(setpoint.encode_f32(60.5, &mut regs), Ok(2));
+        assert_eq!(setpoint.decode_f32(&regs), Ok(60.5));
+        let nan = f32::NAN.to_bits();
+        let nan = [(nan >> 16) as u16, nan as u16];
+        assert_eq!(setpoint.decode_f32(&nan), Err(Error::InvalidValue));
+
+        assert_eq!(Value::Float(-2.5).as_i32(), -3);
+        assert_eq!(Value::Float(1e12).as_i32(), i32::MAX);
+        assert!(Value::Int(2).as_bool());
+    }
+
+    #[cfg(all(feature = "holding-registers", feature = "input-registers"))]
+    #[test]
+    fn serve_fields() {
+        use modbus_core::{Exception, rtu::MAX_FRAME_LEN};
+
+        use crate::{
+            ModbusServer, Outcome,
+            client::{self, Request, Response},
+        };
+
+        /// Device in physical units
+        struct Device {
+            temperature: f32,
+            energy: i32,
+            setpoint: f32,
+        }
+
+        impl FieldHandler for Device {
+            fn read_field(&mut self, field: &Field) -> Result<Value, Error> {
+                Ok(match field.name {
+                    "temperature" => Value::Float(self.temperature),
+                    "energy" => Value::Int(self.energy),
+                    "setpoint" => Value::Float(self.setpoint),
+                    _ => Value::Bool(false),
+                })
+            }
+
+            fn write_field(&mut self, field: &Field, value: Value) -> Result<(), Error> {
+                assert_eq!(field.name, "setpoint");
+                self.setpoint = value.as_f32();
+                Ok(())
+            }
+        }
+
+        let device = Device {
+            temperature: 21.5,
+            energy: 0x12345,
+            setpoint: 20.0,
+        };
+        let raw = |value: f32| [(value.to_bits() >> 16) as u16, value.to_bits() as u16];
+        let (valid, too_high) = (raw(80.0), raw(200.0));
+        let mut server = ModbusServer::new(1, MapHandler::new(MAP, device));
+        let mut tx = [0u8; 256];
+        let mut process = |request| {
+            let mut rx = [0u8; MAX_FRAME_LEN];
+            let len = client::encode_request(1, request, &mut rx).unwrap();
+            let Ok(Outcome::Responded(len)) = server.process_frame(&rx[..len], &mut tx) else {
+                panic!("no response");
+            };
+            let mut regs = [0u16; 3];
+            match client::decode_response(1, request, &tx[..len]) {
+                Ok(Response::ReadInputRegisters(r)) => r.copy_to(&mut regs),
+                Ok(_) => {}
+                Err(e) => return Err(e),
+            }
+            Ok(regs)
+        };
+
+        // Starts within the energy field
+        assert_eq!(
+            process(Request::ReadInputRegisters(0, 3)),
+            Ok([215, 1, 0x2345])
+        );
+        assert_eq!(
+            process(Request::ReadInputRegisters(2, 1)),
+            Ok([0x2345, 0, 0])
+        );
+        assert_eq!(
+            process(Request::ReadInputRegisters(2, 2)),
+            Err(Error::Exception(Exception::IllegalDataAddress))
+        );
+        // Single register writes cannot cover the two registers of the setpoint
+        assert_eq!(
+            process(Request::WriteSingleRegister(100, 0)),
+            Err(Error::Exception(Exception::IllegalDataAddress))
+        );
+        // Write Multiple Registers with raw values
+        assert_eq!(
+            process(Request::WriteMultipleRegisters(100, &valid)),
+            Ok([0; 3])
+        );
+        assert_eq!(
+            process(Request::WriteMultipleRegisters(100, &too_high)),
+            Err(Error::Exception(Exception::IllegalDataValue))
+        );
+        // half of the setpoint
+        assert_eq!(
+            process(Request::WriteMultipleRegisters(101, &valid[1..])),
+            Err(Error::Exception(Exception::IllegalDataAddress))
+        );
+        assert_eq!(server.handler().inner().setpoint, 40.0);
+    }
+
+    #[test]
+    fn text() {
+        let name = Field::new(
+            "name",
+            Table::HoldingRegisters,
+            0,
+            Format::String(Text::new(5).padding(b' ')),
+        );
+        let mut regs = [0u16; 4];
+        assert_eq!(name.encode_bytes(b"AB", &mut regs), Ok(3));
+        assert_eq!(regs[..3], [0x4142, 0x2020, 0x2020]);
+        assert_eq!(name.encode_bytes(b"ABCDEFG", &mut regs), Ok(3));
+        assert_eq!(regs[..3], [0x4142, 0x4344, 0x4520]);
+        assert_eq!(name.encode_f32(1.0, &mut regs), Err(Error::InvalidValue));
+
+        let mut bytes = [0u8; 8];
+        assert_eq!(
+            name.decode_bytes(&[0x4142, 0x4320, 0x2020], &mut bytes),
+            Ok(3)
+        );
+        assert_eq!(bytes[..3], *b"ABC");
+        assert_eq!(
+            name.decode_bytes(&[0x41C3, 0x2020, 0x2020], &mut bytes),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            name.decode_bytes(&[0x4142], &mut bytes),
+            Err(Error::LengthMismatch)
+        );
+
+        let serial = Field::new(
+            "serial",
+            Table::InputRegisters,
+            0,
+            Format::Bytes(Text::new(4).swapped()),
+        );
+        assert_eq!(serial.encode_bytes(&[1, 2, 3], &mut regs), Ok(2));
+        assert_eq!(regs[..2], [0x0201, 0x0003]);
+        assert_eq!(serial.decode_bytes(&regs[..2], &mut bytes), Ok(4));
+        assert_eq!(bytes[..4], [1, 2, 3, 0]);
+
+        // Padding is not part of the register list
+        for format in [Format::String(Text::new(5)), serial.format, Format::F32] {
+            assert_eq!(Format::from_name(&std::format!("{format}")), Some(format));
+        }
+        assert_eq!(std::format!("{}", serial.format), "bytes_swapped[4]");
+        assert_eq!(Format::from_name("string[0]"), None);
+        assert_eq!(
+            Format::from_name("string[64]"),
+            Some(Format::String(Text::new(MAX_TEXT_LEN)))
+        );
+        assert_eq!(Format::from_name("string[65]"), None);
+        assert_eq!(Format::from_name("text[4]"), None);
+    }
+
+    #[cfg(feature = "holding-registers")]
+    #[test]
+    fn serve_text() {
+        use std::vec::Vec;
+
+        use crate::{
+            ModbusServer, Outcome,
+            client::{self, Request, Response},
+        };
+
+        static FIELDS: [Field; 2] = [
+            Field::new("version", Table::HoldingRegisters, 0, Format::U16).read_only(),
+            Field::new(
+                "name",
+                Table::HoldingRegisters,
+                1,
+                Format::String(Text::new(8).padding(b' ')),
+            ),
+        ];
+
+        struct Device {
+            name: Vec<u8>,
+        }
+
+        impl FieldHandler for Device {
+            fn read_field(&mut self, _field: &Field) -> Result<Value, Error> {
+                Ok(Value::Int(3))
+            }
+
+            fn read_bytes(&mut self, _field: &Field, out: &mut [u8]) -> Result<usize, Error> {
+                out[..self.name.len()].copy_from_slice(&self.name);
+                Ok(self.name.len())
+            }
+
+            fn write_bytes(&mut self, _field: &Field, value: &[u8]) -> Result<(), Error> {
+                self.name = value.to_vec();
+                Ok(())
+            }
+        }
+
+        let device = Device {
+            name: b"pump".to_vec(),
+        };
+        let mut handler = MapHandler::new(RegisterMap::new(&FIELDS), device);
+        let mut out = [0u16; 5];
+        assert_eq!(handler.read_holding_registers(0, 5, &mut out), Ok(5));
+        assert_eq!(out, [3, 0x7075, 0x6D70, 0x2020, 0x2020]);
+        // Starting in the middle of the string
+        assert_eq!(handler.read_holding_registers(2, 2, &mut out), Ok(2));
+        assert_eq!(out[..2], [0x6D70, 0x2020]);
+
+        // written by a master with Write Multiple Registers
+        let mut server = ModbusServer::new(1, handler);
+        let name = [0x7661, 0x6C76, 0x6520, 0x2020];
+        let request = Request::WriteMultipleRegisters(1, &name);
+        let mut rx = [0u8; 32];
+        let len = client::encode_request(1, request, &mut rx).unwrap();
+        let mut tx = [0u8; 32];
+        assert_eq!(
+            server.process_frame(&rx[..len], &mut tx),
+            Ok(Outcome::Responded(8))
+        );
+        assert_eq!(
+            client::decode_response(1, request, &tx[..8]),
+            Ok(Response::WriteMultipleRegisters(1, 4))
+        );
+        let handler = server.handler_mut();
+        assert_eq!(handler.inner().name, b"valve");
+
+        assert_eq!(
+            handler.write_registers(1, 3, &name[..3]),
+            Err(Error::InvalidAddress)
+        );
+        assert_eq!(
+            handler.write_registers(1, 4, &[0x00FF, 0, 0, 0]),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(handler.inner().name, b"valve");
+    }
+}
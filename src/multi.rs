@@ -0,0 +1,251 @@
+//! Server hosting several units on one bus
+//!
+//! A [`MultiUnitServer`] answers requests for a fixed table of unit IDs, each backed by its own
+//! [`ModbusHandler`]. Handlers of different types can be combined through
+//! `&mut dyn ModbusHandler`.
+
+use crate::{
+    BROADCAST_ID, Outcome, check_unit_id, decode_frame, error::Error, execute_broadcast,
+    handler::ModbusHandler, respond,
+};
+
+/// Modbus RTU server for several units on one port
+///
+/// Frames are routed to the handler registered for their unit ID and processed like
+/// [`crate::ModbusServer::process_frame`] does. The unit table is statically sized, if a unit ID
+/// is listed more than once, the first entry serves it. [`MultiUnitServer::try_new`] rejects such
+/// tables.
+pub struct MultiUnitServer<H, const N: usize> {
+    /// Unit IDs and their handlers
+    units: [(u8, H); N],
+    /// In listen only mode requests are neither executed nor answered
+    listen_only: bool,
+}
+
+impl<H, const N: usize> MultiUnitServer<H, N>
+where
+    H: ModbusHandler,
+{
+    /// Create a new server for the given units
+    ///
+    /// # Parameters
+    ///
+    /// * `units` - Unit IDs and the handlers serving them. The unit IDs should be distinct and in
+    ///   [`crate::UNIT_ID_RANGE`], which is not checked, see [`MultiUnitServer::try_new`].
+    pub fn new(units: [(u8, H); N]) -> Self {
+        Self {
+            units,
+            listen_only: false,
+        }
+    }
+
+    /// Create a new server for the given units with a checked unit table, like
+    /// [`MultiUnitServer::new`]
+    ///
+    /// Returns [`Error::InvalidValue`] if a unit ID is outside of [`crate::UNIT_ID_RANGE`] or
+    /// listed more than once, like [`crate::ModbusServer::try_new`].
+    pub fn try_new(units: [(u8, H); N]) -> Result<Self, Error> {
+        for (i, (unit_id, _)) in units.iter().enumerate() {
+            check_unit_id(*unit_id)?;
+            if units[..i].iter().any(|(id, _)| id == unit_id) {
+                return Err(Error::InvalidValue);
+            }
+        }
+        Ok(Self::new(units))
+    }
+
+    /// Enable or disable listen only mode for all units, see
+    /// [`crate::ModbusServer::set_listen_only`]
+    pub fn set_listen_only(&mut self, listen_only: bool) {
+        self.listen_only = listen_only;
+    }
+
+    /// Returns `true` if listen only mode is active
+    pub fn is_listen_only(&self) -> bool {
+        self.listen_only
+    }
+
+    /// Handler of the given unit, `None` if the unit is not served
+    pub fn handler(&self, unit_id: u8) -> Option<&H> {
+        self.units
+            .iter()
+            .find(|(id, _)| *id == unit_id)
+            .map(|(_, handler)| handler)
+    }
+
+    /// Mutable handler of the given unit, `None` if the unit is not served
+    pub fn handler_mut(&mut self, unit_id: u8) -> Option<&mut H> {
+        self.units
+            .iter_mut()
+            .find(|(id, _)| *id == unit_id)
+            .map(|(_, handler)| handler)
+    }
+
+    /// Process a single complete Modbus RTU request frame.
+    ///
+    /// Behaves like [`crate::ModbusServer::process_frame`], with the response sent under the unit
+    /// ID of the request. Frames for units which are not in the table are reported as
+    /// [`Outcome::NotForUs`]. Write requests in broadcast frames are executed by every unit.
+    ///
+    /// The unit table is fixed, unit ID changes requested through
+    /// [`ModbusHandler::take_unit_id_change`] are ignored.
+    pub fn process_frame(&mut self, rx: &[u8], tx: &mut [u8]) -> Result<Outcome, Error> {
+        let decoded = match decode_frame(rx, |slave| self.units.iter().any(|(id, _)| *id == slave))
+        {
+            Ok(decoded) => decoded,
+            Err(outcome) => return Ok(outcome),
+        };
+
+        if self.listen_only {
+            return Ok(Outcome::ListenOnly);
+        }
+
+        if decoded.slave == BROADCAST_ID {
+            for (_, handler) in self.units.iter_mut() {
+                execute_broadcast(handler, &decoded.request, tx)?;
+            }
+            return Ok(Outcome::Broadcast);
+        }
+
+        match self.handler_mut(decoded.slave) {
+            Some(handler) => respond(handler, decoded, tx),
+            None => Ok(Outcome::NotForUs),
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "coils",
+    feature = "discrete-inputs",
+    feature = "holding-registers",
+    feature = "input-registers"
+))]
+mod tests {
+    use super::*;
+    use crate::testing::set_crc;
+
+    struct Registers {
+        regs: [u16; 4],
+    }
+
+    impl ModbusHandler for Registers {
+        fn read_holding_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            let regs = self
+                .regs
+                .get(addr..addr + len)
+                .ok_or(Error::InvalidAddress)?;
+            out.copy_from_slice(regs);
+            Ok(len)
+        }
+
+        fn write_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            buf: &[u16],
+        ) -> Result<usize, Error> {
+            let regs = self
+                .regs
+                .get_mut(addr..addr + len)
+                .ok_or(Error::InvalidAddress)?;
+            regs.copy_from_slice(buf);
+            Ok(len)
+        }
+    }
+
+    struct CoilsOnly;
+
+    impl ModbusHandler for CoilsOnly {
+        fn read_coils(
+            &mut self,
+            _addr: usize,
+            len: usize,
+            out: &mut [bool],
+        ) -> Result<usize, Error> {
+            out.fill(true);
+            Ok(len)
+        }
+    }
+
+    fn registers(values: [u16; 4]) -> Registers {
+        Registers { regs: values }
+    }
+
+    #[test]
+    fn route_by_unit_id() {
+        let mut server = MultiUnitServer::new([(1, registers([1; 4])), (2, registers([2; 4]))]);
+        let mut tx = [0u8; 256];
+
+        // read holding register 0 of unit 2
+        let mut frame = [0x02, 0x03, 0x00, 0x00, 0x00, 0x01, 0, 0];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::Responded(7));
+        assert_eq!(tx[..5], [0x02, 0x03, 0x02, 0x00, 0x02]);
+
+        // unit 3 is not served
+        frame[0] = 3;
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::NotForUs);
+    }
+
+    #[test]
+    fn check_unit_table() {
+        for units in [[0, 1], [1, 248], [255, 1], [7, 7]] {
+            let server = MultiUnitServer::try_new(units.map(|unit_id| (unit_id, CoilsOnly)));
+            assert!(matches!(server, Err(Error::InvalidValue)), "{units:?}");
+        }
+        let server = MultiUnitServer::try_new([(1, CoilsOnly), (247, CoilsOnly)]).unwrap();
+        assert!(server.handler(247).is_some());
+    }
+
+    #[test]
+    fn broadcast_to_all_units() {
+        let mut server = MultiUnitServer::new([(1, registers([0; 4])), (2, registers([0; 4]))]);
+        let mut tx = [0u8; 256];
+
+        // write 0x1234 to holding register 3
+        let mut frame = [0x00, 0x06, 0x00, 0x03, 0x12, 0x34, 0, 0];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::Broadcast);
+        assert_eq!(server.handler(1).unwrap().regs[3], 0x1234);
+        assert_eq!(server.handler(2).unwrap().regs[3], 0x1234);
+
+        server.set_listen_only(true);
+        server.handler_mut(2).unwrap().regs[3] = 0;
+        let outcome = server.process_frame(&frame, &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::ListenOnly);
+        assert_eq!(server.handler(2).unwrap().regs[3], 0);
+    }
+
+    #[test]
+    fn mixed_handler_types() {
+        let mut regs = registers([7; 4]);
+        let mut coils = CoilsOnly;
+        let mut server =
+            MultiUnitServer::<&mut dyn ModbusHandler, 2>::new([(10, &mut regs), (20, &mut coils)]);
+        let mut tx = [0u8; 256];
+
+        // read 3 coils of unit 20
+        let mut frame = [20, 0x01, 0x00, 0x00, 0x00, 0x03, 0, 0];
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::Responded(6));
+        assert_eq!(tx[..4], [20, 0x01, 0x01, 0b0000_0111]);
+
+        // unit 10 does not support coils
+        frame[0] = 10;
+        set_crc(&mut frame);
+        let outcome = server.process_frame(&frame, &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::Responded(5));
+        assert_eq!(tx[..3], [10, 0x81, 0x01]);
+    }
+}
@@ -0,0 +1,225 @@
+//! Hooks into request processing
+//!
+//! An [`Observer`] attached to a [`crate::ModbusServer`] with
+//! [`crate::ModbusServer::with_observer`] is notified of every request addressed to the server,
+//! before and after the handler runs. This allows audit trails of the writes performed by
+//! masters, or statistics, independent of the handler.
+//!
+//! Frames which are not decoded (CRC errors, malformed frames, frames for other units) are not
+//! reported, they are visible from the returned [`crate::Outcome`].
+
+use modbus_core::{Exception, FunctionCode, Request};
+
+/// Transport a request was received on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Serial line, [`crate::ModbusServer::process_frame`]
+    Rtu,
+    /// Modbus TCP, `ModbusServer::process_tcp_frame`
+    Tcp,
+}
+
+/// A decoded request addressed to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestEvent<'a> {
+    /// Unit ID the request was addressed to, [`crate::BROADCAST_ID`] for broadcasts
+    pub unit_id: u8,
+    /// Transport the request was received on
+    pub transport: Transport,
+    /// Function code of the request
+    pub function_code: FunctionCode,
+    /// The request, `None` if it failed validation and is answered with an exception
+    pub request: Option<Request<'a>>,
+}
+
+/// What was sent back for a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    /// A normal response
+    Response,
+    /// An exception response with this exception code
+    Exception(Exception),
+    /// Nothing: the request was a broadcast, the server is in listen only mode, or the tx buffer
+    /// was too small
+    Dropped,
+    /// Nothing, the request was a broadcast which the handler rejected with this exception
+    BroadcastFailed(Exception),
+}
+
+impl Reply {
+    /// Reply described by a response PDU
+    pub(crate) fn from_pdu(pdu: &[u8]) -> Self {
+        match pdu {
+            [function_code, exception, ..] if function_code & 0x80 != 0 => Reply::Exception(
+                Exception::try_from(*exception).unwrap_or(Exception::ServerDeviceFailure),
+            ),
+            _ => Reply::Response,
+        }
+    }
+}
+
+/// Receives the requests processed by a [`crate::ModbusServer`]
+///
+/// Both methods are called for every decoded request, also for broadcasts and in listen only
+/// mode, where the handler does not run.
+pub trait Observer {
+    /// Called before the handler executes `request`
+    fn before_request(&mut self, _request: &RequestEvent<'_>) {}
+
+    /// Called after `request` was processed, with the reply written to the tx buffer
+    fn after_request(&mut self, _request: &RequestEvent<'_>, _reply: Reply) {}
+
+    /// Called if the handler requested a change to `unit_id` through
+    /// [`crate::handler::ModbusHandler::take_unit_id_change`], which is outside of
+    /// [`crate::UNIT_ID_RANGE`] and therefore not applied
+    fn unit_id_rejected(&mut self, _unit_id: u8) {}
+}
+
+/// Observer which ignores all requests, used if none is attached
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
+
+/// Allows attaching an observer which is owned by the application
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn before_request(&mut self, request: &RequestEvent<'_>) {
+        (**self).before_request(request);
+    }
+
+    fn after_request(&mut self, request: &RequestEvent<'_>, reply: Reply) {
+        (**self).after_request(request, reply);
+    }
+
+    fn unit_id_rejected(&mut self, unit_id: u8) {
+        (**self).unit_id_rejected(unit_id);
+    }
+}
+
+#[cfg(all(test, feature = "holding-registers"))]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::{
+        BROADCAST_ID, ModbusServer,
+        client::{self, REQUEST_FRAME_LEN},
+        error::Error,
+        handler::ModbusHandler,
+    };
+
+    struct Registers([u16; 4]);
+
+    impl ModbusHandler for Registers {
+        fn read_holding_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            let regs = self.0.get(addr..addr + len).ok_or(Error::InvalidAddress)?;
+            out.copy_from_slice(regs);
+            Ok(len)
+        }
+
+        fn write_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            buf: &[u16],
+        ) -> Result<usize, Error> {
+            let regs = self
+                .0
+                .get_mut(addr..addr + len)
+                .ok_or(Error::InvalidAddress)?;
+            regs.copy_from_slice(buf);
+            Ok(len)
+        }
+    }
+
+    /// Audit trail of register writes, checking that every request is reported before and after
+    #[derive(Default)]
+    struct Audit {
+        pending: Option<(u8, FunctionCode)>,
+        log: Vec<(u8, u16, u16, Reply)>,
+    }
+
+    impl Observer for Audit {
+        fn before_request(&mut self, request: &RequestEvent<'_>) {
+            assert_eq!(self.pending, None);
+            self.pending = Some((request.unit_id, request.function_code));
+        }
+
+        fn after_request(&mut self, request: &RequestEvent<'_>, reply: Reply) {
+            assert_eq!(
+                self.pending.take(),
+                Some((request.unit_id, request.function_code))
+            );
+            assert_eq!(request.transport, Transport::Rtu);
+            if let Some(Request::WriteSingleRegister(addr, value)) = request.request {
+                self.log.push((request.unit_id, addr, value, reply));
+            }
+        }
+    }
+
+    fn process(server: &mut ModbusServer<Registers, Audit>, unit_id: u8, request: client::Request) {
+        let mut rx = [0u8; REQUEST_FRAME_LEN];
+        let len = client::encode_request(unit_id, request, &mut rx).unwrap();
+        let mut tx = [0u8; 256];
+        server.process_frame(&rx[..len], &mut tx).unwrap();
+    }
+
+    #[test]
+    fn audit_writes() {
+        let mut server = ModbusServer::new(1, Registers([0; 4])).with_observer(Audit::default());
+
+        process(&mut server, 1, client::Request::WriteSingleRegister(2, 7));
+        process(&mut server, 1, client::Request::WriteSingleRegister(4, 7));
+        process(&mut server, 1, client::Request::ReadHoldingRegisters(0, 1));
+        process(
+            &mut server,
+            BROADCAST_ID,
+            client::Request::WriteSingleRegister(3, 9),
+        );
+        process(
+            &mut server,
+            BROADCAST_ID,
+            client::Request::WriteSingleRegister(4, 9),
+        );
+        process(&mut server, 2, client::Request::WriteSingleRegister(3, 9));
+        server.set_listen_only(true);
+        process(&mut server, 1, client::Request::WriteSingleRegister(1, 5));
+
+        assert_eq!(server.handler().0, [0, 0, 7, 9]);
+        assert_eq!(
+            server.observer().log,
+            [
+                (1, 2, 7, Reply::Response),
+                (1, 4, 7, Reply::Exception(Exception::IllegalDataAddress)),
+                (BROADCAST_ID, 3, 9, Reply::Dropped),
+                (
+                    BROADCAST_ID,
+                    4,
+                    9,
+                    Reply::BroadcastFailed(Exception::IllegalDataAddress)
+                ),
+                (1, 1, 5, Reply::Dropped),
+            ]
+        );
+    }
+
+    #[test]
+    fn reply_from_pdu() {
+        assert_eq!(Reply::from_pdu(&[0x03, 0x02, 0x00, 0x01]), Reply::Response);
+        assert_eq!(
+            Reply::from_pdu(&[0x83, 0x02]),
+            Reply::Exception(Exception::IllegalDataAddress)
+        );
+        assert_eq!(
+            Reply::from_pdu(&[0x83, 0x42]),
+            Reply::Exception(Exception::ServerDeviceFailure)
+        );
+    }
+}
@@ -0,0 +1,649 @@
+//! Persistent holding registers
+//!
+//! [`PersistentRegisters`] wraps a handler and saves a range of its holding registers to a
+//! [`Storage`], e.g. a few flash pages, after masters changed them. On startup,
+//! [`PersistentRegisters::load`] writes the last saved values back into the handler.
+//!
+//! To spare the flash, saving is batched and wear leveled:
+//!
+//! - Writes only mark the registers as changed if a value actually differs. The registers are
+//!   saved by [`PersistentRegisters::poll`] once a delay passed since the first unsaved change,
+//!   so a master writing a configuration register by register causes a single save.
+//! - The storage is split into two halves, which are erased in turn. Each save appends a record
+//!   of all registers with a sequence number and a CRC to the current half, a half is only erased
+//!   when the other one is full. A record interrupted by a power loss fails the CRC check on
+//!   load, which then falls back to the previous record.
+
+use core::{ops::Range, time::Duration};
+
+use modbus_core::rtu::crc16;
+
+#[cfg(feature = "coils")]
+use crate::bits::Bits;
+#[cfg(any(feature = "coils", feature = "discrete-inputs"))]
+use crate::bits::BitsMut;
+use crate::{
+    clock::Clock,
+    error::Error,
+    handler::{MAX_CHUNK_LEN, ModbusHandler},
+};
+
+/// Delay between the first unsaved change and the save, if not set otherwise
+pub const DEFAULT_SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Bytes of the sequence number in front of every record
+const HEADER_LEN: usize = 4;
+
+/// Bytes of the CRC at the end of every record
+const CRC_LEN: usize = 2;
+
+/// Bytes read or written in one storage access
+const CHUNK_LEN: usize = 32;
+
+/// Byte addressable non-volatile memory, e.g. flash pages reserved for the registers
+///
+/// Offsets are relative to the start of the storage. [`Storage::erase`] is only called for
+/// either half of the storage, so each half has to consist of complete erase blocks.
+pub trait Storage {
+    /// Error reported by the memory
+    type Error;
+
+    /// Size of the storage in bytes
+    fn capacity(&self) -> usize;
+
+    /// Read `buf.len()` bytes from `offset`
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write `data` to `offset`, which was erased before
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Erase `range`, all bytes read as 0xFF afterwards
+    fn erase(&mut self, range: Range<usize>) -> Result<(), Self::Error>;
+}
+
+/// Error of [`PersistentRegisters`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersistError<E> {
+    /// Error of the storage
+    Storage(E),
+    /// Error of the handler while transferring the registers
+    Handler(Error),
+    /// A half of the storage cannot hold a single record
+    Capacity,
+}
+
+/// State of a record slot in the storage
+enum Slot {
+    /// All bytes erased
+    Erased,
+    /// Written, but the CRC does not match
+    Invalid,
+    /// Valid record with this sequence number
+    Valid(u32),
+}
+
+/// Handler saving the holding registers `base..base + N` of the wrapped handler to a [`Storage`]
+///
+/// All accesses are forwarded to the wrapped handler. Successful writes to the persistent
+/// registers, including those of the application through [`ModbusHandler::write_registers`] on
+/// the wrapper, are saved by the next [`PersistentRegisters::poll`] after the save delay.
+/// Changes bypassing the wrapper are not saved.
+///
+/// ```
+/// # use core::time::Duration;
+/// use modbus_server::{
+///     ModbusServer,
+///     persist::{PersistentRegisters, RamFlash},
+///     store::DataStore,
+/// };
+///
+/// let mut handler = PersistentRegisters::<_, _, _, 8>::new(
+///     DataStore::<0, 0, 16, 0>::new(),
+///     RamFlash::<256>::new(),
+///     || Duration::ZERO,
+///     8,
+/// );
+/// handler.load().unwrap();
+/// let mut server = ModbusServer::new(1, handler);
+/// // [...] process frames, and in the main loop:
+/// server.handler_mut().poll().unwrap();
+/// ```
+pub struct PersistentRegisters<H, S, C, const N: usize> {
+    handler: H,
+    storage: S,
+    clock: C,
+    base: usize,
+    delay: Duration,
+    /// Current values of the persistent registers
+    values: [u16; N],
+    /// Time of the first change since the last save
+    changed_at: Option<Duration>,
+    /// Sequence number of the last record
+    sequence: u32,
+    /// Half of the storage holding the last record
+    half: usize,
+    /// Erased slot in `half` for the next record, `None` if the other half has to be erased
+    next: Option<usize>,
+}
+
+impl<H: ModbusHandler, S: Storage, C: Clock, const N: usize> PersistentRegisters<H, S, C, N> {
+    /// Bytes of a record: sequence number, registers and CRC
+    const RECORD_LEN: usize = HEADER_LEN + 2 * N + CRC_LEN;
+
+    /// Save the holding registers from `base` on of `handler` to `storage`
+    ///
+    /// Call [`PersistentRegisters::load`] before processing requests.
+    pub fn new(handler: H, storage: S, clock: C, base: u16) -> Self {
+        Self {
+            handler,
+            storage,
+            clock,
+            base: usize::from(base),
+            delay: DEFAULT_SAVE_DELAY,
+            values: [0; N],
+            changed_at: None,
+            sequence: 0,
+            half: 1,
+            next: None,
+        }
+    }
+
+    /// Set the delay between the first unsaved change and the save
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Reference to the wrapped handler
+    pub fn inner(&self) -> &H {
+        &self.handler
+    }
+
+    /// Mutable reference to the wrapped handler
+    pub fn inner_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Reference to the storage
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Give back the wrapped handler and the storage
+    pub fn release(self) -> (H, S) {
+        (self.handler, self.storage)
+    }
+
+    /// Whether changed registers wait to be saved
+    pub fn is_pending(&self) -> bool {
+        self.changed_at.is_some()
+    }
+
+    /// Write the last saved registers into the handler
+    ///
+    /// Returns `false` if the storage holds no valid record, e.g. on first startup. The handler
+    /// then keeps its values, which are saved with the next change.
+    pub fn load(&mut self) -> Result<bool, PersistError<S::Error>> {
+        let slots = self.slots()?;
+        let half_len = self.storage.capacity() / 2;
+        let mut values = [0; N];
+        let mut last = None;
+        let mut ends = [slots; 2];
+        for (half, end) in ends.iter_mut().enumerate() {
+            for slot in 0..slots {
+                let offset = half * half_len + slot * Self::RECORD_LEN;
+                match self.read_record(offset, &mut values)? {
+                    Slot::Erased => {
+                        *end = slot;
+                        break;
+                    }
+                    Slot::Invalid => {}
+                    Slot::Valid(sequence) => {
+                        if last.is_none_or(|(last, _)| sequence > last) {
+                            last = Some((sequence, half));
+                            self.values = values;
+                        }
+                    }
+                }
+            }
+        }
+        self.changed_at = None;
+
+        let Some((sequence, half)) = last else {
+            self.sequence = 0;
+            self.half = 1;
+            self.next = None;
+            for start in (0..N).step_by(MAX_CHUNK_LEN) {
+                let len = MAX_CHUNK_LEN.min(N - start);
+                let out = &mut self.values[start..start + len];
+                self.handler
+                    .read_holding_registers(self.base + start, len, out)
+                    .map_err(PersistError::Handler)?;
+            }
+            return Ok(false);
+        };
+        self.sequence = sequence;
+        self.half = half;
+        self.next = (ends[half] < slots).then_some(ends[half]);
+        for start in (0..N).step_by(MAX_CHUNK_LEN) {
+            let len = MAX_CHUNK_LEN.min(N - start);
+            let buf = &self.values[start..start + len];
+            self.handler
+                .write_registers(self.base + start, len, buf)
+                .map_err(PersistError::Handler)?;
+        }
+        Ok(true)
+    }
+
+    /// Save the changed registers, if the save delay passed since the first change
+    ///
+    /// Call this regularly from the main loop. Returns whether the registers were saved.
+    pub fn poll(&mut self) -> Result<bool, PersistError<S::Error>> {
+        match self.changed_at {
+            Some(changed_at) if self.clock.now().saturating_sub(changed_at) >= self.delay => {
+                self.save()
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Save the changed registers right away, e.g. before a reset
+    ///
+    /// Returns whether there were changes to save.
+    pub fn save(&mut self) -> Result<bool, PersistError<S::Error>> {
+        if self.changed_at.is_none() {
+            return Ok(false);
+        }
+        let slots = self.slots()?;
+        let half_len = self.storage.capacity() / 2;
+        // If the save fails, start over in the other half
+        let (half, slot) = match self.next.take() {
+            Some(slot) => (self.half, slot),
+            None => {
+                let half = self.half ^ 1;
+                let start = half * half_len;
+                self.storage
+                    .erase(start..start + half_len)
+                    .map_err(PersistError::Storage)?;
+                (half, 0)
+            }
+        };
+        let sequence = self.sequence.wrapping_add(1);
+        self.write_record(half * half_len + slot * Self::RECORD_LEN, sequence)?;
+
+        self.sequence = sequence;
+        self.half = half;
+        self.next = (slot + 1 < slots).then_some(slot + 1);
+        self.changed_at = None;
+        Ok(true)
+    }
+
+    /// Number of records fitting into one half of the storage
+    fn slots(&self) -> Result<usize, PersistError<S::Error>> {
+        match self.storage.capacity() / 2 / Self::RECORD_LEN {
+            0 => Err(PersistError::Capacity),
+            slots => Ok(slots),
+        }
+    }
+
+    /// Read the record at `offset`, its registers into `values`
+    fn read_record(
+        &mut self,
+        offset: usize,
+        values: &mut [u16; N],
+    ) -> Result<Slot, PersistError<S::Error>> {
+        let mut record = Record::<N>::new();
+        let bytes = record.as_bytes_mut();
+        for start in (0..bytes.len()).step_by(CHUNK_LEN) {
+            let end = bytes.len().min(start + CHUNK_LEN);
+            self.storage
+                .read(offset + start, &mut bytes[start..end])
+                .map_err(PersistError::Storage)?;
+        }
+        if record.as_bytes().iter().all(|&byte| byte == 0xFF) {
+            return Ok(Slot::Erased);
+        }
+        for (value, bytes) in values.iter_mut().zip(&record.values) {
+            *value = u16::from_le_bytes(*bytes);
+        }
+        Ok(if record.crc == record.compute_crc() {
+            Slot::Valid(u32::from_le_bytes(record.sequence))
+        } else {
+            Slot::Invalid
+        })
+    }
+
+    /// Write a record of the current registers to `offset`
+    fn write_record(&mut self, offset: usize, sequence: u32) -> Result<(), PersistError<S::Error>> {
+        let mut record = Record::<N>::new();
+        record.sequence = sequence.to_le_bytes();
+        for (bytes, value) in record.values.iter_mut().zip(&self.values) {
+            *bytes = value.to_le_bytes();
+        }
+        record.crc = record.compute_crc();
+
+        let bytes = record.as_bytes();
+        for start in (0..bytes.len()).step_by(CHUNK_LEN) {
+            let end = bytes.len().min(start + CHUNK_LEN);
+            self.storage
+                .write(offset + start, &bytes[start..end])
+                .map_err(PersistError::Storage)?;
+        }
+        Ok(())
+    }
+
+    /// Take over the values written to the persistent registers
+    fn track(&mut self, addr: usize, buf: &[u16]) {
+        let start = addr.max(self.base);
+        let end = (addr + buf.len()).min(self.base + N);
+        let mut changed = false;
+        for a in start..end {
+            let value = &mut self.values[a - self.base];
+            changed |= *value != buf[a - addr];
+            *value = buf[a - addr];
+        }
+        if changed && self.changed_at.is_none() {
+            self.changed_at = Some(self.clock.now());
+        }
+    }
+}
+
+impl<H: ModbusHandler, S: Storage, C: Clock, const N: usize> ModbusHandler
+    for PersistentRegisters<H, S, C, N>
+{
+    #[cfg(feature = "coils")]
+    fn read_coils(&mut self, addr: usize, len: usize, out: &mut [bool]) -> Result<usize, Error> {
+        self.handler.read_coils(addr, len, out)
+    }
+
+    #[cfg(feature = "discrete-inputs")]
+    fn read_discrete_input(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [bool],
+    ) -> Result<usize, Error> {
+        self.handler.read_discrete_input(addr, len, out)
+    }
+
+    #[cfg(feature = "coils")]
+    fn read_coils_packed(&mut self, addr: usize, out: &mut BitsMut) -> Result<usize, Error> {
+        self.handler.read_coils_packed(addr, out)
+    }
+
+    #[cfg(feature = "discrete-inputs")]
+    fn read_discrete_input_packed(
+        &mut self,
+        addr: usize,
+        out: &mut BitsMut,
+    ) -> Result<usize, Error> {
+        self.handler.read_discrete_input_packed(addr, out)
+    }
+
+    fn read_holding_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.handler.read_holding_registers(addr, len, out)
+    }
+
+    #[cfg(feature = "input-registers")]
+    fn read_input_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.handler.read_input_registers(addr, len, out)
+    }
+
+    #[cfg(feature = "coils")]
+    fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+        self.handler.write_coils(addr, len, buf)
+    }
+
+    #[cfg(feature = "coils")]
+    fn write_coils_packed(&mut self, addr: usize, bits: Bits) -> Result<usize, Error> {
+        self.handler.write_coils_packed(addr, bits)
+    }
+
+    fn write_registers(&mut self, addr: usize, len: usize, buf: &[u16]) -> Result<usize, Error> {
+        let written = self.handler.write_registers(addr, len, buf)?;
+        if written == len {
+            self.track(addr, &buf[..len]);
+        }
+        Ok(written)
+    }
+
+    fn take_unit_id_change(&mut self) -> Option<u8> {
+        self.handler.take_unit_id_change()
+    }
+}
+
+/// Record as stored: sequence number and registers in little endian, and the Modbus CRC of both
+///
+/// The CRC is stored in the byte order of an RTU frame.
+#[repr(C)]
+struct Record<const N: usize> {
+    sequence: [u8; HEADER_LEN],
+    values: [[u8; 2]; N],
+    crc: [u8; CRC_LEN],
+}
+
+impl<const N: usize> Record<N> {
+    const fn new() -> Self {
+        Self {
+            sequence: [0; HEADER_LEN],
+            values: [[0; 2]; N],
+            crc: [0; CRC_LEN],
+        }
+    }
+
+    /// The stored bytes of the record
+    fn as_bytes(&self) -> &[u8] {
+        // Safety: the record consists of byte arrays only, so it has no padding
+        unsafe { core::slice::from_raw_parts((self as *const Self).cast(), size_of::<Self>()) }
+    }
+
+    /// Mutable view of the stored bytes
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        // Safety: the record consists of byte arrays only, so it has no padding and every bit
+        // pattern is valid, and the bytes borrow the record mutably
+        unsafe { core::slice::from_raw_parts_mut((self as *mut Self).cast(), size_of::<Self>()) }
+    }
+
+    /// CRC of the sequence number and the registers
+    fn compute_crc(&self) -> [u8; CRC_LEN] {
+        let bytes = self.as_bytes();
+        let len = bytes.len() - CRC_LEN;
+        crc16(&bytes[..len]).to_be_bytes()
+    }
+}
+
+/// Access outside of a [`RamFlash`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds;
+
+/// In-memory [`Storage`] of `N` bytes behaving like NOR flash, for tests and simulations
+///
+/// Writes can only clear bits, erasing sets all bytes to 0xFF.
+#[derive(Debug, Clone)]
+pub struct RamFlash<const N: usize> {
+    data: [u8; N],
+    erase_count: usize,
+}
+
+impl<const N: usize> RamFlash<N> {
+    /// Create an erased flash image
+    pub const fn new() -> Self {
+        Self {
+            data: [0xFF; N],
+            erase_count: 0,
+        }
+    }
+
+    /// Contents of the flash
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.data
+    }
+
+    /// Mutable contents of the flash, e.g. to simulate corruption
+    pub fn as_bytes_mut(&mut self) -> &mut [u8; N] {
+        &mut self.data
+    }
+
+    /// Number of erase operations so far
+    pub fn erase_count(&self) -> usize {
+        self.erase_count
+    }
+}
+
+impl<const N: usize> Default for RamFlash<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Storage for RamFlash<N> {
+    type Error = OutOfBounds;
+
+    fn capacity(&self) -> usize {
+        N
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), OutOfBounds> {
+        let data = self
+            .data
+            .get(offset..offset + buf.len())
+            .ok_or(OutOfBounds)?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), OutOfBounds> {
+        let bytes = self
+            .data
+            .get_mut(offset..offset + data.len())
+            .ok_or(OutOfBounds)?;
+        bytes.iter_mut().zip(data).for_each(|(b, d)| *b &= d);
+        Ok(())
+    }
+
+    fn erase(&mut self, range: Range<usize>) -> Result<(), OutOfBounds> {
+        self.data.get_mut(range).ok_or(OutOfBounds)?.fill(0xFF);
+        self.erase_count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::store::DataStore;
+
+    type Store = DataStore<0, 0, 16, 0>;
+
+    /// Registers 10 to 13 persistent, two records per half of the flash
+    fn persistent<'a>(
+        flash: RamFlash<64>,
+        now: &'a Cell<Duration>,
+    ) -> PersistentRegisters<Store, RamFlash<64>, impl Clock + 'a, 4> {
+        PersistentRegisters::new(Store::new(), flash, || now.get(), 10)
+            .with_delay(Duration::from_millis(100))
+    }
+
+    fn advance(now: &Cell<Duration>, ms: u64) {
+        now.set(now.get() + Duration::from_millis(ms));
+    }
+
+    #[test]
+    fn record_layout() {
+        let mut record = Record::<2>::new();
+        record.sequence = 7u32.to_le_bytes();
+        record.values = [0x1234u16.to_le_bytes(), 0xABCDu16.to_le_bytes()];
+        record.crc = record.compute_crc();
+        let bytes = record.as_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 2 * 2 + CRC_LEN);
+        assert_eq!(bytes[..8], [0x07, 0x00, 0x00, 0x00, 0x34, 0x12, 0xCD, 0xAB]);
+        assert_eq!(crc16(bytes), 0);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let now = Cell::new(Duration::ZERO);
+        let mut handler = persistent(RamFlash::new(), &now);
+        handler.inner_mut().holding_registers[13] = 4;
+        assert_eq!(handler.load(), Ok(false));
+
+        // Partly persistent write, saved once the delay passed
+        handler.write_registers(8, 4, &[1, 2, 3, 4]).unwrap();
+        handler.write_registers(12, 1, &[5]).unwrap();
+        assert!(handler.is_pending());
+        advance(&now, 50);
+        assert_eq!(handler.poll(), Ok(false));
+        advance(&now, 50);
+        assert_eq!(handler.poll(), Ok(true));
+        assert!(!handler.is_pending());
+        assert_eq!(handler.storage().erase_count(), 1);
+
+        // Unchanged values and other registers are not saved
+        handler.write_registers(10, 1, &[3]).unwrap();
+        handler.write_registers(14, 1, &[7]).unwrap();
+        assert!(!handler.is_pending());
+
+        let (_, flash) = handler.release();
+        let mut handler = persistent(flash, &now);
+        assert_eq!(handler.load(), Ok(true));
+        assert_eq!(
+            handler.inner().holding_registers[8..16],
+            [0, 0, 3, 4, 5, 4, 0, 0]
+        );
+    }
+
+    #[test]
+    fn alternate_halves() {
+        let now = Cell::new(Duration::ZERO);
+        let mut handler = persistent(RamFlash::new(), &now);
+        handler.load().unwrap();
+        for value in 1..=5 {
+            handler.write_registers(10, 1, &[value]).unwrap();
+            assert_eq!(handler.save(), Ok(true));
+        }
+        // Records 1 and 2 in the first half, 3 and 4 in the second, 5 in the first again
+        assert_eq!(handler.storage().erase_count(), 3);
+
+        let (_, mut flash) = handler.release();
+        let mut handler = persistent(flash.clone(), &now);
+        assert_eq!(handler.load(), Ok(true));
+        assert_eq!(handler.inner().holding_registers[10], 5);
+
+        // A corrupted last record falls back to the previous one, which continues
+        flash.as_bytes_mut()[5] ^= 0x01;
+        let mut handler = persistent(flash, &now);
+        assert_eq!(handler.load(), Ok(true));
+        assert_eq!(handler.inner().holding_registers[10], 4);
+        handler.write_registers(10, 1, &[6]).unwrap();
+        assert_eq!(handler.save(), Ok(true));
+
+        let (_, flash) = handler.release();
+        let mut handler = persistent(flash, &now);
+        assert_eq!(handler.load(), Ok(true));
+        assert_eq!(handler.inner().holding_registers[10], 6);
+    }
+
+    #[test]
+    fn too_small() {
+        let now = Cell::new(Duration::ZERO);
+        let mut handler = PersistentRegisters::<_, _, _, 4>::new(
+            Store::new(),
+            RamFlash::<16>::new(),
+            || now.get(),
+            0,
+        );
+        assert_eq!(handler.load(), Err(PersistError::Capacity));
+    }
+}
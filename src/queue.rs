@@ -0,0 +1,323 @@
+//! Frame queue between a UART interrupt and the server
+//!
+//! A [`FrameQueue`] holds up to `N` frames of up to `LEN` bytes. It is split into a [`Producer`],
+//! which the UART interrupt feeds with received bytes, and a [`Consumer`], from which the main
+//! loop takes complete frames. The producer assembles the frames in place like a
+//! [`crate::rtu::FrameAssembler`], so a complete frame is handed over without copying.
+//!
+//! Producer and consumer only synchronize through atomic loads and stores, no lock or critical
+//! section is needed, also on targets without compare-and-swap instructions.
+//!
+//! ```
+//! use modbus_server::queue::FrameQueue;
+//!
+//! let mut queue: FrameQueue<2> = FrameQueue::new();
+//! let (mut producer, mut consumer) = queue.split();
+//!
+//! // UART interrupt
+//! for byte in [0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x84, 0x0A] {
+//!     producer.push(byte);
+//! }
+//!
+//! // main loop
+//! if let Some(frame) = consumer.dequeue() {
+//!     // server.process_frame(&frame, &mut tx)
+//!     assert_eq!(frame.len(), 8);
+//! }
+//! ```
+
+use core::{
+    cell::UnsafeCell,
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use modbus_core::rtu::MAX_FRAME_LEN;
+
+use crate::rtu::Assembly;
+
+/// Buffer of one frame
+struct Slot<const LEN: usize> {
+    buf: [u8; LEN],
+    len: usize,
+}
+
+/// Lock-free single-producer single-consumer queue of RTU frames
+///
+/// Frames which arrive while all `N` slots are taken are discarded, as are frames longer than
+/// `LEN` bytes.
+pub struct FrameQueue<const N: usize, const LEN: usize = MAX_FRAME_LEN> {
+    slots: [UnsafeCell<Slot<LEN>>; N],
+    /// Number of frames taken by the consumer, only written by the consumer
+    head: AtomicUsize,
+    /// Number of frames completed by the producer, only written by the producer
+    tail: AtomicUsize,
+}
+
+// Safety: the producer only writes the slot at `tail`, which the consumer does not access before
+// `tail` was advanced, and the consumer only reads the slot at `head`, which the producer does not
+// access before `head` was advanced.
+unsafe impl<const N: usize, const LEN: usize> Sync for FrameQueue<N, LEN> {}
+
+impl<const N: usize, const LEN: usize> FrameQueue<N, LEN> {
+    /// Create an empty queue
+    pub const fn new() -> Self {
+        Self {
+            slots: [const {
+                UnsafeCell::new(Slot {
+                    buf: [0; LEN],
+                    len: 0,
+                })
+            }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Split the queue into the producer and consumer side
+    ///
+    /// For use from an interrupt, the queue has to be `'static`, e.g. a `static` initialized
+    /// once with a cell.
+    pub fn split(&mut self) -> (Producer<'_, N, LEN>, Consumer<'_, N, LEN>) {
+        let queue = &*self;
+        (
+            Producer {
+                queue,
+                state: Assembly::new(),
+            },
+            Consumer { queue },
+        )
+    }
+
+    /// Number of complete frames in the queue
+    fn len(&self) -> usize {
+        self.tail
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+}
+
+impl<const N: usize, const LEN: usize> Default for FrameQueue<N, LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Receiving side of a [`FrameQueue`], fed with bytes from the UART
+pub struct Producer<'a, const N: usize, const LEN: usize> {
+    queue: &'a FrameQueue<N, LEN>,
+    state: Assembly,
+}
+
+impl<const N: usize, const LEN: usize> Producer<'_, N, LEN> {
+    /// Append a received byte
+    ///
+    /// Returns `true` if this byte completed a frame according to its function code, which is
+    /// then available to the consumer.
+    pub fn push(&mut self, byte: u8) -> bool {
+        // a frame which starts while all slots are taken is dropped as a whole, also if the
+        // consumer frees a slot before its end
+        if self.state.is_empty() {
+            self.state.start();
+            if self.queue.len() == N {
+                self.state.discard();
+            }
+        }
+        // the buffer of the frame being received, empty if the frame is discarded
+        let buf: &mut [u8] = if self.state.is_discarding() {
+            &mut []
+        } else {
+            let tail = self.queue.tail.load(Ordering::Relaxed);
+            // Safety: the slot at `tail` is not accessed by the consumer while the queue is not
+            // full
+            unsafe { &mut (*self.queue.slots[tail % N].get()).buf }
+        };
+        let len = self.state.push(buf, byte);
+        len.map(|len| self.commit(len)).is_some()
+    }
+
+    /// Notify the producer of the inter-frame silence (see [`crate::rtu::inter_frame_delay`])
+    ///
+    /// Returns `true` if this ended a frame, which is then available to the consumer.
+    pub fn idle(&mut self) -> bool {
+        let len = self.state.idle();
+        len.map(|len| self.commit(len)).is_some()
+    }
+
+    /// Hand the frame over to the consumer
+    fn commit(&mut self, len: usize) {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        // Safety: see `push`
+        unsafe { (*self.queue.slots[tail % N].get()).len = len };
+        self.queue
+            .tail
+            .store(tail.wrapping_add(1), Ordering::Release);
+    }
+}
+
+/// Processing side of a [`FrameQueue`], taking complete frames
+pub struct Consumer<'a, const N: usize, const LEN: usize> {
+    queue: &'a FrameQueue<N, LEN>,
+}
+
+impl<const N: usize, const LEN: usize> Consumer<'_, N, LEN> {
+    /// Number of complete frames in the queue
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if there is no complete frame
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take the oldest complete frame
+    ///
+    /// The slot is released to the producer when the returned frame is dropped.
+    pub fn dequeue(&mut self) -> Option<Frame<'_, N, LEN>> {
+        if self.is_empty() {
+            return None;
+        }
+        let head = self.queue.head.load(Ordering::Relaxed);
+        // Safety: the producer does not access the slot at `head` while the queue is not empty
+        let slot = unsafe { &*self.queue.slots[head % N].get() };
+        Some(Frame {
+            queue: self.queue,
+            frame: &slot.buf[..slot.len],
+        })
+    }
+}
+
+/// A complete frame of a [`FrameQueue`], released to the producer on drop
+pub struct Frame<'a, const N: usize, const LEN: usize> {
+    queue: &'a FrameQueue<N, LEN>,
+    frame: &'a [u8],
+}
+
+impl<const N: usize, const LEN: usize> Deref for Frame<'_, N, LEN> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.frame
+    }
+}
+
+impl<const N: usize, const LEN: usize> Drop for Frame<'_, N, LEN> {
+    fn drop(&mut self) {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        self.queue
+            .head
+            .store(head.wrapping_add(1), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{thread, vec::Vec};
+
+    use super::*;
+
+    const READ: [u8; 8] = [0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x84, 0x0A];
+
+    #[test]
+    fn full_queue() {
+        let mut queue: FrameQueue<2, 16> = FrameQueue::new();
+        let (mut producer, mut consumer) = queue.split();
+        assert!(consumer.dequeue().is_none());
+
+        for frame in [&READ[..], &[0x01, 0x41, 0x00], &READ[..]] {
+            for &byte in frame {
+                producer.push(byte);
+            }
+            producer.idle();
+        }
+        assert_eq!(consumer.len(), 2);
+
+        assert_eq!(*consumer.dequeue().unwrap(), READ);
+        // a slot is free again
+        for &byte in &READ[..7] {
+            assert!(!producer.push(byte));
+        }
+        assert!(producer.push(READ[7]));
+        assert_eq!(*consumer.dequeue().unwrap(), [0x01, 0x41, 0x00]);
+        assert_eq!(*consumer.dequeue().unwrap(), READ);
+        assert!(consumer.is_empty());
+
+        // overlong frames are discarded
+        for _ in 0..=16 {
+            producer.push(0x41);
+        }
+        assert!(!producer.idle());
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn free_slot_during_discarded_frame() {
+        let mut queue: FrameQueue<1, 16> = FrameQueue::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        for &byte in &READ {
+            producer.push(byte);
+        }
+        // the queue is full when the next frame starts, a slot is freed in its middle
+        for &byte in &READ[..4] {
+            assert!(!producer.push(byte));
+        }
+        drop(consumer.dequeue());
+        for &byte in &READ[4..] {
+            assert!(!producer.push(byte));
+        }
+        assert!(consumer.is_empty());
+
+        // the discarded frame ended by its length, the next one is received
+        for &byte in &READ[..7] {
+            assert!(!producer.push(byte));
+        }
+        assert!(producer.push(READ[7]));
+        assert_eq!(*consumer.dequeue().unwrap(), READ);
+
+        // a discarded frame of unknown length ends with the silence
+        for &byte in &READ {
+            producer.push(byte);
+        }
+        for &byte in &[0x01, 0x41, 0x00] {
+            assert!(!producer.push(byte));
+        }
+        drop(consumer.dequeue());
+        assert!(!producer.push(0x00));
+        assert!(!producer.idle());
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn concurrent() {
+        let mut queue: FrameQueue<3> = FrameQueue::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for i in 0..1000u16 {
+                    let frame = [0x01, 0x41, (i >> 8) as u8, i as u8];
+                    // wait for a free slot instead of losing the frame
+                    while producer.queue.len() == 3 {
+                        thread::yield_now();
+                    }
+                    for byte in frame {
+                        producer.push(byte);
+                    }
+                    assert!(producer.idle());
+                }
+            });
+
+            let mut received = Vec::new();
+            while received.len() < 1000 {
+                if let Some(frame) = consumer.dequeue() {
+                    received.push(u16::from_be_bytes([frame[2], frame[3]]));
+                }
+            }
+            assert!(received.into_iter().eq(0..1000));
+        });
+    }
+}
@@ -0,0 +1,445 @@
+//! RTU framing helpers
+//!
+//! RTU frames are delimited by silence on the line: a gap of at least 3.5 character times ends a
+//! frame. Detecting such gaps needs precise timing, which is not always available (e.g. behind USB
+//! serial adapters or pseudo terminals). The [`FrameAssembler`] therefore also ends a request
+//! frame as soon as its length, known from the function code, has been received.
+//!
+//! The length is the one of a request. On a bus shared with other slaves, their responses are
+//! framed wrongly (e.g. exception responses, or read responses with another byte count), so the
+//! silence has to be detected with a timer of 3.5 character times there, and
+//! [`FrameAssembler::idle`] called after every silence to resynchronize.
+//!
+//! On half-duplex RS-485 buses the [`TransmitScheduler`] computes when to drive the bus for a
+//! response.
+
+use core::time::Duration;
+
+use modbus_core::rtu::MAX_FRAME_LEN;
+
+/// Number of bits of a character on the line: start bit, 8 data bits, parity or second stop
+/// bit, stop bit
+const CHAR_BITS: u32 = 11;
+
+/// Time to transmit a single character at `baud_rate`
+pub fn char_time(baud_rate: u32) -> Duration {
+    Duration::from_nanos(u64::from(CHAR_BITS) * 1_000_000_000 / u64::from(baud_rate.max(1)))
+}
+
+/// Minimum silence between two frames (3.5 character times)
+///
+/// Above 19200 baud the specification recommends a fixed value of 1.75 ms.
+pub fn inter_frame_delay(baud_rate: u32) -> Duration {
+    if baud_rate > 19200 {
+        Duration::from_micros(1750)
+    } else {
+        char_time(baud_rate) * 7 / 2
+    }
+}
+
+/// Timing of a response transmission on a half-duplex bus
+///
+/// All times are measured from the end of the request frame, e.g. from the return of
+/// [`crate::ModbusServer::process_frame`]. They can be loaded into a hardware timer, see
+/// [`TransmitSchedule::events`] for the order of the steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransmitSchedule {
+    /// Set the driver enable (DE) line
+    pub assert_de: Duration,
+    /// Write the first byte of the response
+    pub start: Duration,
+    /// Clear the DE line, after the last stop bit left the line
+    pub release_de: Duration,
+}
+
+/// Step of a [`TransmitSchedule`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransmitEvent {
+    /// Set the driver enable line
+    AssertDe,
+    /// Write the response
+    Start,
+    /// Clear the driver enable line
+    ReleaseDe,
+}
+
+impl TransmitSchedule {
+    /// Steps in chronological order, with their time after the end of the request
+    pub fn events(&self) -> [(Duration, TransmitEvent); 3] {
+        [
+            (self.assert_de, TransmitEvent::AssertDe),
+            (self.start, TransmitEvent::Start),
+            (self.release_de, TransmitEvent::ReleaseDe),
+        ]
+    }
+}
+
+/// Computes when to drive the bus for a response
+///
+/// ```
+/// use core::time::Duration;
+/// use modbus_server::rtu::TransmitScheduler;
+///
+/// let scheduler = TransmitScheduler::new(9600)
+///     .with_response_delay(Duration::from_millis(5))
+///     .with_de_setup(Duration::from_micros(50));
+/// let schedule = scheduler.schedule(8);
+/// assert_eq!(schedule.assert_de, Duration::from_micros(4950));
+/// assert_eq!(schedule.start, Duration::from_millis(5));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransmitScheduler {
+    baud_rate: u32,
+    /// Minimum time between the end of the request and the first byte of the response
+    response_delay: Duration,
+    /// Time the transceiver needs after DE was set, before it can transmit
+    de_setup: Duration,
+    /// Time DE is held after the last stop bit
+    de_hold: Duration,
+}
+
+impl TransmitScheduler {
+    /// Schedule for `baud_rate`, responding immediately without any DE setup or hold time
+    pub const fn new(baud_rate: u32) -> Self {
+        Self {
+            baud_rate,
+            response_delay: Duration::ZERO,
+            de_setup: Duration::ZERO,
+            de_hold: Duration::ZERO,
+        }
+    }
+
+    /// Wait at least `delay` after the request before responding, for masters which need time
+    /// to turn their transceiver around
+    pub const fn with_response_delay(mut self, delay: Duration) -> Self {
+        self.response_delay = delay;
+        self
+    }
+
+    /// Set DE `setup` before the first byte is written
+    pub const fn with_de_setup(mut self, setup: Duration) -> Self {
+        self.de_setup = setup;
+        self
+    }
+
+    /// Hold DE for `hold` after the last stop bit
+    pub const fn with_de_hold(mut self, hold: Duration) -> Self {
+        self.de_hold = hold;
+        self
+    }
+
+    /// Time to transmit `len` bytes
+    pub fn transmit_time(&self, len: usize) -> Duration {
+        char_time(self.baud_rate) * u32::try_from(len).unwrap_or(u32::MAX)
+    }
+
+    /// Schedule the transmission of a response of `len` bytes, e.g. the length returned with
+    /// [`crate::Outcome::Responded`]
+    pub fn schedule(&self, len: usize) -> TransmitSchedule {
+        let start = self.response_delay.max(self.de_setup);
+        TransmitSchedule {
+            assert_de: start - self.de_setup,
+            start,
+            release_de: start + self.transmit_time(len) + self.de_hold,
+        }
+    }
+}
+
+/// Length of the request frame starting at `adu`, derived from its function code
+///
+/// Returns `None` if `adu` is too short to tell, or the length of the function code is not known.
+fn request_frame_len(adu: &[u8]) -> Option<usize> {
+    let pdu_len = match *adu.get(1)? {
+        // reads and single writes: address and quantity or value
+        0x01..=0x06 | 0x08 => 5,
+        // requests without data
+        0x07 | 0x0B | 0x0C | 0x11 => 1,
+        // multiple writes: address, quantity, byte count and data
+        0x0F | 0x10 => 6 + usize::from(*adu.get(6)?),
+        0x16 => 7,
+        0x17 => 10 + usize::from(*adu.get(10)?),
+        0x18 => 3,
+        _ => return None,
+    };
+    // unit ID and CRC
+    Some(pdu_len + 3)
+}
+
+/// Number of leading bytes which determine the length of a request frame
+const HEAD_LEN: usize = 11;
+
+/// State of a frame being assembled in a buffer owned by the caller
+///
+/// Shared by [`FrameAssembler`] and the producer of a [`crate::queue::FrameQueue`].
+#[derive(Debug, Default)]
+pub(crate) struct Assembly {
+    /// Number of received bytes
+    len: usize,
+    /// The current frame has been handed out, the next byte starts a new one
+    complete: bool,
+    /// The current frame exceeded the buffer or was dropped, it is discarded up to its end
+    overflow: bool,
+    /// The current frame was completed by its length, not by the silence
+    by_length: bool,
+    /// The current frame started right after a frame completed by its length, without a silence
+    follows: bool,
+    /// First bytes of the current frame, to tell the length also of a discarded frame
+    head: [u8; HEAD_LEN],
+}
+
+impl Assembly {
+    pub(crate) const fn new() -> Self {
+        Self {
+            len: 0,
+            complete: false,
+            overflow: false,
+            by_length: false,
+            follows: false,
+            head: [0; HEAD_LEN],
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Returns `true` if no byte of a new frame has been received
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0 || self.complete
+    }
+
+    /// Returns `true` if the current frame is discarded
+    pub(crate) fn is_discarding(&self) -> bool {
+        self.overflow
+    }
+
+    /// Start a new frame if the current one is complete
+    pub(crate) fn start(&mut self) {
+        if self.complete {
+            let follows = self.by_length;
+            self.reset();
+            self.follows = follows;
+        }
+    }
+
+    /// Discard the current frame up to the next [`Assembly::idle`] or the end of its length
+    pub(crate) fn discard(&mut self) {
+        self.overflow = true;
+    }
+
+    /// Append `byte` to the frame in `buf`, returns the frame length once it is complete
+    ///
+    /// The bytes of a discarded frame are counted, but not stored in `buf`.
+    pub(crate) fn push(&mut self, buf: &mut [u8], byte: u8) -> Option<usize> {
+        self.start();
+        if self.len == buf.len() {
+            self.overflow = true;
+        }
+        if let Some(head) = self.head.get_mut(self.len) {
+            *head = byte;
+        }
+        if !self.overflow {
+            buf[self.len] = byte;
+        }
+        self.len = self.len.saturating_add(1);
+
+        let head = &self.head[..self.len.min(HEAD_LEN)];
+        if request_frame_len(head) == Some(self.len) {
+            // the next byte starts a new frame, also after a discarded one
+            self.complete = true;
+            self.by_length = true;
+            return (!self.overflow).then_some(self.len);
+        }
+        None
+    }
+
+    /// End the current frame, returns its length unless it was already complete or discarded
+    ///
+    /// A frame which started right after a frame completed by its length is dropped: without a
+    /// silence in between, the length was wrong (e.g. the response of another slave) and the
+    /// bytes are the rest of the previous frame.
+    pub(crate) fn idle(&mut self) -> Option<usize> {
+        if self.is_empty() || self.overflow || self.follows {
+            self.reset();
+            return None;
+        }
+        self.complete = true;
+        Some(self.len)
+    }
+}
+
+/// Assembles request frames from received bytes
+///
+/// Bytes are passed in one at a time with [`FrameAssembler::push`]. A frame is complete once the
+/// length given by its function code has been received, or when the application detects the
+/// inter-frame silence and calls [`FrameAssembler::idle`]. The completed frame is returned by
+/// either call and can be passed to [`crate::ModbusServer::process_frame`]. The next received
+/// byte starts a new frame.
+///
+/// On a bus shared with other slaves, [`FrameAssembler::idle`] has to be called after every
+/// inter-frame silence: bytes received after a frame completed by its length, without a silence
+/// in between, are the rest of a wrongly framed transmission and dropped on the next silence.
+///
+/// Frames longer than [`MAX_FRAME_LEN`] are discarded.
+pub struct FrameAssembler {
+    /// Received bytes of the current frame
+    buf: [u8; MAX_FRAME_LEN],
+    state: Assembly,
+}
+
+impl FrameAssembler {
+    /// Create an empty frame assembler
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            state: Assembly::new(),
+        }
+    }
+
+    /// Discard the current frame
+    pub fn reset(&mut self) {
+        self.state.reset();
+    }
+
+    /// Returns `true` if no byte of a new frame has been received
+    pub fn is_empty(&self) -> bool {
+        self.state.is_empty()
+    }
+
+    /// Append a received byte
+    ///
+    /// Returns the frame once it is complete according to its function code.
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        let len = self.state.push(&mut self.buf, byte)?;
+        Some(&self.buf[..len])
+    }
+
+    /// Notify the assembler of the inter-frame silence (see [`inter_frame_delay`])
+    ///
+    /// Returns the frame received so far, unless it was already returned by
+    /// [`FrameAssembler::push`], exceeded the maximum frame length, or directly followed a frame
+    /// completed by its length.
+    pub fn idle(&mut self) -> Option<&[u8]> {
+        let len = self.state.idle()?;
+        Some(&self.buf[..len])
+    }
+}
+
+impl Default for FrameAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_timing() {
+        assert_eq!(char_time(9600), Duration::from_nanos(1_145_833));
+        assert_eq!(inter_frame_delay(9600), Duration::from_nanos(4_010_415));
+        assert_eq!(inter_frame_delay(115200), Duration::from_micros(1750));
+    }
+
+    #[test]
+    fn transmit_schedule() {
+        let scheduler = TransmitScheduler::new(19200).with_de_hold(Duration::from_micros(100));
+        let schedule = scheduler.schedule(10);
+        assert_eq!(schedule.assert_de, Duration::ZERO);
+        assert_eq!(schedule.start, Duration::ZERO);
+        assert_eq!(
+            schedule.release_de,
+            Duration::from_nanos(572_916 * 10 + 100_000)
+        );
+
+        // the setup time exceeds the response delay, transmission starts later
+        let schedule = scheduler
+            .with_response_delay(Duration::from_micros(20))
+            .with_de_setup(Duration::from_micros(50))
+            .schedule(0);
+        assert_eq!(
+            schedule.events(),
+            [
+                (Duration::ZERO, TransmitEvent::AssertDe),
+                (Duration::from_micros(50), TransmitEvent::Start),
+                (Duration::from_micros(150), TransmitEvent::ReleaseDe),
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble_by_length() {
+        let mut assembler = FrameAssembler::new();
+        let request = [0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x84, 0x0A];
+
+        for &byte in &request[..7] {
+            assert_eq!(assembler.push(byte), None);
+        }
+        assert_eq!(assembler.push(request[7]), Some(&request[..]));
+        assert_eq!(assembler.idle(), None);
+
+        // write multiple registers, the length depends on the byte count
+        let request = [
+            0x01, 0x10, 0x00, 0x00, 0x00, 0x01, 0x02, 0x12, 0x34, 0x00, 0x00,
+        ];
+        let frame = request
+            .iter()
+            .filter_map(|&b| assembler.push(b).map(<[u8]>::to_vec));
+        assert_eq!(frame.last(), Some(request.to_vec()));
+    }
+
+    #[test]
+    fn assemble_by_silence() {
+        let mut assembler = FrameAssembler::new();
+        assert_eq!(assembler.idle(), None);
+
+        // unknown function code
+        for byte in [0x01, 0x41, 0x00, 0x00] {
+            assert_eq!(assembler.push(byte), None);
+        }
+        assert_eq!(assembler.idle(), Some(&[0x01, 0x41, 0x00, 0x00][..]));
+        assert!(assembler.is_empty());
+        assert_eq!(assembler.idle(), None);
+
+        // overlong frames are discarded
+        for _ in 0..=MAX_FRAME_LEN {
+            assert_eq!(assembler.push(0x41), None);
+        }
+        assert_eq!(assembler.idle(), None);
+        assert_eq!(assembler.push(0x01), None);
+        assert_eq!(assembler.idle(), Some(&[0x01][..]));
+    }
+
+    #[test]
+    fn resynchronize_on_silence() {
+        let mut assembler = FrameAssembler::new();
+        let request = [0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x84, 0x0A];
+
+        // response of slave 2 to a read of 2 registers, longer than a read request
+        let response = [0x02, 0x03, 0x04, 0x00, 0x01, 0x00, 0x02, 0x2A, 0x32];
+        for &byte in &response[..7] {
+            assert_eq!(assembler.push(byte), None);
+        }
+        assert_eq!(assembler.push(response[7]), Some(&response[..8]));
+        assert_eq!(assembler.push(response[8]), None);
+        // the rest of the response is dropped
+        assert_eq!(assembler.idle(), None);
+
+        for &byte in &request[..7] {
+            assert_eq!(assembler.push(byte), None);
+        }
+        assert_eq!(assembler.push(request[7]), Some(&request[..]));
+        assert_eq!(assembler.idle(), None);
+
+        // exception response of slave 2, the function code is not known
+        for byte in [0x02, 0x83, 0x02, 0xC0, 0xF1] {
+            assert_eq!(assembler.push(byte), None);
+        }
+        assert_eq!(assembler.idle(), Some(&[0x02, 0x83, 0x02, 0xC0, 0xF1][..]));
+        for &byte in &request[..7] {
+            assert_eq!(assembler.push(byte), None);
+        }
+        assert_eq!(assembler.push(request[7]), Some(&request[..]));
+    }
+}
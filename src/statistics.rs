@@ -0,0 +1,526 @@
+//! Request statistics
+//!
+//! [`Statistics`] is an [`Observer`] counting the requests per function code, the exception
+//! responses per exception code and the dropped requests, and measuring how long requests take
+//! with a [`Clock`] supplied by the application. Attach it to a server with
+//! [`crate::ModbusServer::with_observer`].
+//!
+//! The counters use interior mutability, so a shared reference can be attached to the server
+//! while a `StatisticsRegisters` handler publishes them as input registers to the master
+//! (`input-registers` feature).
+
+use core::{cell::Cell, time::Duration};
+
+use modbus_core::{Exception, FunctionCode};
+
+use crate::{
+    clock::Clock,
+    error::Error,
+    observer::{Observer, Reply, RequestEvent},
+};
+
+/// Function codes counted individually, in the order of their input registers
+pub const FUNCTION_CODES: [FunctionCode; 8] = [
+    FunctionCode::ReadCoils,
+    FunctionCode::ReadDiscreteInputs,
+    FunctionCode::ReadHoldingRegisters,
+    FunctionCode::ReadInputRegisters,
+    FunctionCode::WriteSingleCoil,
+    FunctionCode::WriteSingleRegister,
+    FunctionCode::WriteMultipleCoils,
+    FunctionCode::WriteMultipleRegisters,
+];
+
+/// Number of exception codes counted, exception codes 1 to 11
+const EXCEPTION_CODES: usize = 11;
+
+/// Number of input registers of [`StatisticsRegisters`]
+///
+/// Every value is a 32 bit counter in two registers, high word first:
+///
+/// | Registers | Value                                                |
+/// |-----------|------------------------------------------------------|
+/// | 0 - 15    | requests per function code, see [`FUNCTION_CODES`]   |
+/// | 16 - 17   | requests with other function codes                   |
+/// | 18 - 39   | exception responses per exception code 1 to 11       |
+/// | 40 - 41   | dropped requests                                     |
+/// | 42 - 43   | duration of the last request in µs                   |
+/// | 44 - 45   | longest duration of a request in µs                  |
+pub const STATISTICS_REGISTERS: usize = 2 * (FUNCTION_CODES.len() + 1 + EXCEPTION_CODES + 3);
+
+/// Counters and timing of the processed requests
+pub struct Statistics<C> {
+    clock: C,
+    requests: [Cell<u32>; FUNCTION_CODES.len()],
+    other_requests: Cell<u32>,
+    exceptions: [Cell<u32>; EXCEPTION_CODES],
+    dropped: Cell<u32>,
+    /// Start of the request being processed
+    started: Cell<Option<Duration>>,
+    last_duration: Cell<Duration>,
+    max_duration: Cell<Duration>,
+}
+
+impl<C: Clock> Statistics<C> {
+    /// Create statistics measuring durations with `clock`
+    pub const fn new(clock: C) -> Self {
+        Self {
+            clock,
+            requests: [const { Cell::new(0) }; FUNCTION_CODES.len()],
+            other_requests: Cell::new(0),
+            exceptions: [const { Cell::new(0) }; EXCEPTION_CODES],
+            dropped: Cell::new(0),
+            started: Cell::new(None),
+            last_duration: Cell::new(Duration::ZERO),
+            max_duration: Cell::new(Duration::ZERO),
+        }
+    }
+
+    /// Number of requests with `function_code`
+    pub fn requests(&self, function_code: FunctionCode) -> u32 {
+        match FUNCTION_CODES.iter().position(|fc| *fc == function_code) {
+            Some(i) => self.requests[i].get(),
+            None => self.other_requests.get(),
+        }
+    }
+
+    /// Number of requests with function codes other than [`FUNCTION_CODES`]
+    pub fn other_requests(&self) -> u32 {
+        self.other_requests.get()
+    }
+
+    /// Number of exception responses with `exception`
+    pub fn exceptions(&self, exception: Exception) -> u32 {
+        self.exceptions
+            .get(exception as usize - 1)
+            .map_or(0, Cell::get)
+    }
+
+    /// Number of requests which were not answered: broadcasts, requests in listen only mode and
+    /// requests whose response did not fit into the tx buffer
+    pub fn dropped(&self) -> u32 {
+        self.dropped.get()
+    }
+
+    /// Time taken by the last request
+    pub fn last_duration(&self) -> Duration {
+        self.last_duration.get()
+    }
+
+    /// Longest time taken by a request
+    pub fn max_duration(&self) -> Duration {
+        self.max_duration.get()
+    }
+
+    /// Clear all counters and durations
+    pub fn reset(&self) {
+        for counter in self.requests.iter().chain(&self.exceptions) {
+            counter.set(0);
+        }
+        self.other_requests.set(0);
+        self.dropped.set(0);
+        self.last_duration.set(Duration::ZERO);
+        self.max_duration.set(Duration::ZERO);
+    }
+
+    /// Read input registers `addr..addr + out.len()` of the layout described at
+    /// [`STATISTICS_REGISTERS`]
+    ///
+    /// Returns [`Error::InvalidAddress`] if the range exceeds the statistics registers.
+    pub fn read_registers(&self, addr: usize, out: &mut [u16]) -> Result<(), Error> {
+        if addr + out.len() > STATISTICS_REGISTERS {
+            return Err(Error::InvalidAddress);
+        }
+        for (i, register) in out.iter_mut().enumerate() {
+            let value = self.value((addr + i) / 2);
+            *register = if (addr + i).is_multiple_of(2) {
+                (value >> 16) as u16
+            } else {
+                value as u16
+            };
+        }
+        Ok(())
+    }
+
+    /// 32 bit value `index` of the register layout
+    fn value(&self, index: usize) -> u32 {
+        let micros = |duration: Duration| duration.as_micros().min(u32::MAX.into()) as u32;
+
+        let exceptions = FUNCTION_CODES.len() + 1;
+        let dropped = exceptions + EXCEPTION_CODES;
+        match index {
+            i if i < FUNCTION_CODES.len() => self.requests[i].get(),
+            i if i < exceptions => self.other_requests.get(),
+            i if i < dropped => self.exceptions[i - exceptions].get(),
+            i if i == dropped => self.dropped.get(),
+            i if i == dropped + 1 => micros(self.last_duration.get()),
+            _ => micros(self.max_duration.get()),
+        }
+    }
+
+    /// Count a request and start measuring its duration
+    fn record_request(&self, request: &RequestEvent<'_>) {
+        let counter = match FUNCTION_CODES
+            .iter()
+            .position(|fc| *fc == request.function_code)
+        {
+            Some(i) => &self.requests[i],
+            None => &self.other_requests,
+        };
+        counter.set(counter.get().saturating_add(1));
+        self.started.set(Some(self.clock.now()));
+    }
+
+    /// Count the reply to a request and finish measuring its duration
+    fn record_reply(&self, reply: Reply) {
+        let counter = match reply {
+            Reply::Response => None,
+            Reply::Exception(exception) => self.exceptions.get(exception as usize - 1),
+            Reply::Dropped | Reply::BroadcastFailed(_) => Some(&self.dropped),
+        };
+        if let Some(counter) = counter {
+            counter.set(counter.get().saturating_add(1));
+        }
+
+        if let Some(started) = self.started.take() {
+            let duration = self.clock.now().saturating_sub(started);
+            self.last_duration.set(duration);
+            self.max_duration.set(duration.max(self.max_duration.get()));
+        }
+    }
+}
+
+impl<C: Clock> Observer for Statistics<C> {
+    fn before_request(&mut self, request: &RequestEvent<'_>) {
+        self.record_request(request);
+    }
+
+    fn after_request(&mut self, _request: &RequestEvent<'_>, reply: Reply) {
+        self.record_reply(reply);
+    }
+}
+
+/// Allows reading the statistics while they are attached to a server
+impl<C: Clock> Observer for &Statistics<C> {
+    fn before_request(&mut self, request: &RequestEvent<'_>) {
+        self.record_request(request);
+    }
+
+    fn after_request(&mut self, _request: &RequestEvent<'_>, reply: Reply) {
+        self.record_reply(reply);
+    }
+}
+
+#[cfg(feature = "input-registers")]
+pub use registers::StatisticsRegisters;
+
+#[cfg(feature = "input-registers")]
+mod registers {
+    #[cfg(feature = "coils")]
+    use crate::bits::Bits;
+    #[cfg(any(feature = "coils", feature = "discrete-inputs"))]
+    use crate::bits::BitsMut;
+    use crate::{clock::Clock, error::Error, handler::ModbusHandler};
+
+    use super::{STATISTICS_REGISTERS, Statistics};
+
+    /// Handler publishing [`Statistics`] as input registers
+    ///
+    /// Serves [`STATISTICS_REGISTERS`] input registers starting at a base address, all other
+    /// accesses are forwarded to the wrapped handler. Reads which extend from the statistics
+    /// registers to other addresses are rejected with [`Error::InvalidAddress`].
+    ///
+    /// ```
+    /// # use core::time::Duration;
+    /// use modbus_server::{
+    ///     ModbusServer,
+    ///     handler::ModbusHandler,
+    ///     statistics::{Statistics, StatisticsRegisters},
+    /// };
+    ///
+    /// struct MyHandler;
+    /// impl ModbusHandler for MyHandler {}
+    ///
+    /// let statistics = Statistics::new(|| Duration::ZERO);
+    /// let handler = StatisticsRegisters::new(MyHandler, &statistics, 1000);
+    /// let mut server = ModbusServer::new(1, handler).with_observer(&statistics);
+    /// ```
+    pub struct StatisticsRegisters<'a, H, C> {
+        handler: H,
+        statistics: &'a Statistics<C>,
+        base: usize,
+    }
+
+    impl<'a, H, C> StatisticsRegisters<'a, H, C> {
+        /// Serve `statistics` from input register `base` on, and everything else from `handler`
+        pub fn new(handler: H, statistics: &'a Statistics<C>, base: u16) -> Self {
+            Self {
+                handler,
+                statistics,
+                base: usize::from(base),
+            }
+        }
+
+        /// Reference to the wrapped handler
+        pub fn inner(&self) -> &H {
+            &self.handler
+        }
+
+        /// Mutable reference to the wrapped handler
+        pub fn inner_mut(&mut self) -> &mut H {
+            &mut self.handler
+        }
+    }
+
+    impl<H: ModbusHandler, C: Clock> ModbusHandler for StatisticsRegisters<'_, H, C> {
+        #[cfg(feature = "coils")]
+        fn read_coils(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [bool],
+        ) -> Result<usize, Error> {
+            self.handler.read_coils(addr, len, out)
+        }
+
+        #[cfg(feature = "discrete-inputs")]
+        fn read_discrete_input(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [bool],
+        ) -> Result<usize, Error> {
+            self.handler.read_discrete_input(addr, len, out)
+        }
+
+        #[cfg(feature = "coils")]
+        fn read_coils_packed(&mut self, addr: usize, out: &mut BitsMut) -> Result<usize, Error> {
+            self.handler.read_coils_packed(addr, out)
+        }
+
+        #[cfg(feature = "discrete-inputs")]
+        fn read_discrete_input_packed(
+            &mut self,
+            addr: usize,
+            out: &mut BitsMut,
+        ) -> Result<usize, Error> {
+            self.handler.read_discrete_input_packed(addr, out)
+        }
+
+        #[cfg(feature = "holding-registers")]
+        fn read_holding_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            self.handler.read_holding_registers(addr, len, out)
+        }
+
+        fn read_input_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            let statistics = self.base..self.base + STATISTICS_REGISTERS;
+            if addr + len <= statistics.start || addr >= statistics.end {
+                return self.handler.read_input_registers(addr, len, out);
+            }
+            if addr < statistics.start {
+                return Err(Error::InvalidAddress);
+            }
+            self.statistics
+                .read_registers(addr - self.base, &mut out[..len])?;
+            Ok(len)
+        }
+
+        #[cfg(feature = "coils")]
+        fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+            self.handler.write_coils(addr, len, buf)
+        }
+
+        #[cfg(feature = "coils")]
+        fn write_coils_packed(&mut self, addr: usize, bits: Bits) -> Result<usize, Error> {
+            self.handler.write_coils_packed(addr, bits)
+        }
+
+        #[cfg(feature = "holding-registers")]
+        fn write_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            buf: &[u16],
+        ) -> Result<usize, Error> {
+            self.handler.write_registers(addr, len, buf)
+        }
+
+        fn take_unit_id_change(&mut self) -> Option<u8> {
+            self.handler.take_unit_id_change()
+        }
+    }
+}
+
+#[cfg(all(test, feature = "holding-registers", feature = "input-registers"))]
+mod tests {
+    use super::*;
+    use crate::{
+        BROADCAST_ID, ModbusServer, Outcome,
+        client::{self, REQUEST_FRAME_LEN, Request},
+        handler::ModbusHandler,
+    };
+
+    /// Holding registers 0 to 3, every read takes 5 ms, and input registers of value 7
+    struct Slow<'a>(&'a Cell<Duration>);
+
+    impl ModbusHandler for Slow<'_> {
+        fn read_holding_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            self.0.set(self.0.get() + Duration::from_millis(5));
+            if addr + len > 4 {
+                return Err(Error::InvalidAddress);
+            }
+            out.fill(0);
+            Ok(len)
+        }
+
+        fn read_input_registers(
+            &mut self,
+            _addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            out.fill(7);
+            Ok(len)
+        }
+    }
+
+    fn process<H: ModbusHandler, O: Observer>(
+        server: &mut ModbusServer<H, O>,
+        unit_id: u8,
+        request: Request,
+        tx: &mut [u8],
+    ) -> Outcome {
+        let mut rx = [0u8; REQUEST_FRAME_LEN];
+        let len = client::encode_request(unit_id, request, &mut rx).unwrap();
+        server.process_frame(&rx[..len], tx).unwrap()
+    }
+
+    #[test]
+    fn count_and_publish() {
+        let now = Cell::new(Duration::ZERO);
+        let statistics = Statistics::new(|| now.get());
+        let handler = StatisticsRegisters::new(Slow(&now), &statistics, 100);
+        let mut server = ModbusServer::new(1, handler).with_observer(&statistics);
+        let mut tx = [0u8; 256];
+
+        process(&mut server, 1, Request::ReadHoldingRegisters(0, 2), &mut tx);
+        process(&mut server, 1, Request::ReadHoldingRegisters(2, 4), &mut tx);
+        process(&mut server, 1, Request::ReadCoils(0, 1), &mut tx);
+        process(
+            &mut server,
+            BROADCAST_ID,
+            Request::WriteSingleRegister(0, 1),
+            &mut tx,
+        );
+
+        assert_eq!(statistics.requests(FunctionCode::ReadHoldingRegisters), 2);
+        assert_eq!(statistics.requests(FunctionCode::WriteSingleRegister), 1);
+        assert_eq!(statistics.exceptions(Exception::IllegalDataAddress), 1);
+        assert_eq!(statistics.exceptions(Exception::IllegalFunction), 1);
+        assert_eq!(statistics.dropped(), 1);
+        assert_eq!(statistics.last_duration(), Duration::ZERO);
+        assert_eq!(statistics.max_duration(), Duration::from_millis(5));
+
+        // published as input registers, this read is counted before it is answered
+        let request = Request::ReadInputRegisters(100 + 4, 2);
+        let outcome = process(&mut server, 1, request, &mut tx);
+        let Ok(client::Response::ReadInputRegisters(registers)) =
+            client::decode_response(1, request, &tx[..outcome.response_len()])
+        else {
+            panic!("unexpected response {outcome:?}");
+        };
+        assert!(registers.iter().eq([0, 2]));
+
+        let request = Request::ReadInputRegisters(100 + 44, 2);
+        let outcome = process(&mut server, 1, request, &mut tx);
+        let Ok(client::Response::ReadInputRegisters(registers)) =
+            client::decode_response(1, request, &tx[..outcome.response_len()])
+        else {
+            panic!("unexpected response {outcome:?}");
+        };
+        assert!(registers.iter().eq([0, 5000]));
+
+        // partially covering the statistics
+        let request = Request::ReadInputRegisters(100 + 44, 3);
+        let outcome = process(&mut server, 1, request, &mut tx);
+        assert_eq!(
+            client::decode_response(1, request, &tx[..outcome.response_len()]),
+            Err(Error::Exception(Exception::IllegalDataAddress))
+        );
+
+        // straddling the start of the statistics
+        let request = Request::ReadInputRegisters(100 - 32, 42);
+        let outcome = process(&mut server, 1, request, &mut tx);
+        assert_eq!(
+            client::decode_response(1, request, &tx[..outcome.response_len()]),
+            Err(Error::Exception(Exception::IllegalDataAddress))
+        );
+
+        // all statistics, and registers of the wrapped handler
+        let request = Request::ReadInputRegisters(100, STATISTICS_REGISTERS as u16);
+        let outcome = process(&mut server, 1, request, &mut tx);
+        let Ok(client::Response::ReadInputRegisters(registers)) =
+            client::decode_response(1, request, &tx[..outcome.response_len()])
+        else {
+            panic!("unexpected response {outcome:?}");
+        };
+        assert_eq!(registers.get(7), Some(5));
+        let request = Request::ReadInputRegisters(100 - 40, 40);
+        let outcome = process(&mut server, 1, request, &mut tx);
+        let Ok(client::Response::ReadInputRegisters(registers)) =
+            client::decode_response(1, request, &tx[..outcome.response_len()])
+        else {
+            panic!("unexpected response {outcome:?}");
+        };
+        assert!(registers.iter().all(|register| register == 7));
+        assert_eq!(statistics.requests(FunctionCode::ReadInputRegisters), 6);
+
+        statistics.reset();
+        assert_eq!(statistics.requests(FunctionCode::ReadInputRegisters), 0);
+    }
+
+    #[test]
+    fn publish_without_observer() {
+        let now = Cell::new(Duration::ZERO);
+        let statistics = Statistics::new(|| now.get());
+        let handler = StatisticsRegisters::new(Slow(&now), &statistics, 100);
+        let mut server = ModbusServer::new(1, handler);
+        let mut tx = [0u8; 256];
+
+        // across the end and the start of the statistics
+        for request in [
+            Request::ReadInputRegisters(100 + 40, 40),
+            Request::ReadInputRegisters(100 - 40, 42),
+        ] {
+            let outcome = process(&mut server, 1, request, &mut tx);
+            assert_eq!(
+                client::decode_response(1, request, &tx[..outcome.response_len()]),
+                Err(Error::Exception(Exception::IllegalDataAddress))
+            );
+        }
+
+        let request = Request::ReadInputRegisters(100, STATISTICS_REGISTERS as u16);
+        let outcome = process(&mut server, 1, request, &mut tx);
+        let Ok(client::Response::ReadInputRegisters(registers)) =
+            client::decode_response(1, request, &tx[..outcome.response_len()])
+        else {
+            panic!("unexpected response {outcome:?}");
+        };
+        assert!(registers.iter().all(|register| register == 0));
+    }
+}
@@ -0,0 +1,280 @@
+//! Built-in data store
+//!
+//! [`DataStore`] is a [`ModbusHandler`] keeping all four data types in fixed size arrays, starting
+//! at address 0. It suits devices which only exchange values with the master, without side effects
+//! on access.
+//!
+//! Writes of a master mark the written coils and holding registers as changed. The main loop
+//! iterates over the changed address ranges and acknowledges them after reacting, instead of
+//! comparing all values with a copy:
+//!
+//! ```
+//! use modbus_server::store::DataStore;
+//!
+//! let mut store = DataStore::<0, 0, 16, 0>::new();
+//! // [...] pass `&mut store` to the server and process frames
+//! for range in store.changed_holding_registers() {
+//!     // apply the new setpoints in store.holding_registers[range]
+//! }
+//! store.acknowledge_holding_registers();
+//! ```
+
+use core::ops::Range;
+
+use crate::error::Error;
+use crate::handler::ModbusHandler;
+
+/// Handler holding `COILS` coils, `DISCRETE_INPUTS` discrete inputs, `HOLDING_REGISTERS` holding
+/// registers and `INPUT_REGISTERS` input registers in memory
+///
+/// The application accesses the values through the public arrays. Its own changes are not tracked,
+/// only writes through the handler methods are. The changed flags take one `bool` per coil and
+/// holding register.
+#[derive(Debug, Clone)]
+pub struct DataStore<
+    const COILS: usize,
+    const DISCRETE_INPUTS: usize,
+    const HOLDING_REGISTERS: usize,
+    const INPUT_REGISTERS: usize,
+> {
+    /// Coil values by address
+    pub coils: [bool; COILS],
+    /// Discrete input values by address
+    pub discrete_inputs: [bool; DISCRETE_INPUTS],
+    /// Holding register values by address
+    pub holding_registers: [u16; HOLDING_REGISTERS],
+    /// Input register values by address
+    pub input_registers: [u16; INPUT_REGISTERS],
+    /// Coils written since the last acknowledgement
+    changed_coils: [bool; COILS],
+    /// Holding registers written since the last acknowledgement
+    changed_holding_registers: [bool; HOLDING_REGISTERS],
+}
+
+impl<const C: usize, const D: usize, const H: usize, const I: usize> DataStore<C, D, H, I> {
+    /// Create a store with all values cleared and nothing changed
+    pub const fn new() -> Self {
+        Self {
+            coils: [false; C],
+            discrete_inputs: [false; D],
+            holding_registers: [0; H],
+            input_registers: [0; I],
+            changed_coils: [false; C],
+            changed_holding_registers: [false; H],
+        }
+    }
+
+    /// Address ranges of the coils written since the last acknowledgement
+    ///
+    /// A written coil counts as changed, also if the master wrote the value it already had.
+    pub fn changed_coils(&self) -> ChangedRanges<'_> {
+        ChangedRanges::new(&self.changed_coils)
+    }
+
+    /// Address ranges of the holding registers written since the last acknowledgement
+    ///
+    /// A written register counts as changed, also if the master wrote the value it already had.
+    pub fn changed_holding_registers(&self) -> ChangedRanges<'_> {
+        ChangedRanges::new(&self.changed_holding_registers)
+    }
+
+    /// Whether a coil or holding register was written since the last acknowledgement
+    pub fn has_changes(&self) -> bool {
+        self.changed_coils.contains(&true) || self.changed_holding_registers.contains(&true)
+    }
+
+    /// Mark all coils as unchanged
+    pub fn acknowledge_coils(&mut self) {
+        self.changed_coils = [false; C];
+    }
+
+    /// Mark all holding registers as unchanged
+    pub fn acknowledge_holding_registers(&mut self) {
+        self.changed_holding_registers = [false; H];
+    }
+}
+
+impl<const C: usize, const D: usize, const H: usize, const I: usize> Default
+    for DataStore<C, D, H, I>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Copy `len` values from `addr` into `out`
+fn read<T: Copy>(values: &[T], addr: usize, len: usize, out: &mut [T]) -> Result<usize, Error> {
+    let values = values.get(addr..addr + len).ok_or(Error::InvalidAddress)?;
+    out[..len].copy_from_slice(values);
+    Ok(len)
+}
+
+/// Copy `buf` to `addr` and mark the written values as changed
+#[cfg(any(feature = "coils", feature = "holding-registers"))]
+fn write<T: Copy>(
+    values: &mut [T],
+    changed: &mut [bool],
+    addr: usize,
+    len: usize,
+    buf: &[T],
+) -> Result<usize, Error> {
+    let values = values
+        .get_mut(addr..addr + len)
+        .ok_or(Error::InvalidAddress)?;
+    values.copy_from_slice(&buf[..len]);
+    changed[addr..addr + len].fill(true);
+    Ok(len)
+}
+
+impl<const C: usize, const D: usize, const H: usize, const I: usize> ModbusHandler
+    for DataStore<C, D, H, I>
+{
+    #[cfg(feature = "coils")]
+    fn read_coils(&mut self, addr: usize, len: usize, out: &mut [bool]) -> Result<usize, Error> {
+        read(&self.coils, addr, len, out)
+    }
+
+    #[cfg(feature = "discrete-inputs")]
+    fn read_discrete_input(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [bool],
+    ) -> Result<usize, Error> {
+        read(&self.discrete_inputs, addr, len, out)
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn read_holding_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        read(&self.holding_registers, addr, len, out)
+    }
+
+    #[cfg(feature = "input-registers")]
+    fn read_input_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        read(&self.input_registers, addr, len, out)
+    }
+
+    #[cfg(feature = "coils")]
+    fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+        write(&mut self.coils, &mut self.changed_coils, addr, len, buf)
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn write_registers(&mut self, addr: usize, len: usize, buf: &[u16]) -> Result<usize, Error> {
+        write(
+            &mut self.holding_registers,
+            &mut self.changed_holding_registers,
+            addr,
+            len,
+            buf,
+        )
+    }
+}
+
+/// Iterator over the ranges of consecutive changed addresses, in ascending order
+#[derive(Debug, Clone)]
+pub struct ChangedRanges<'a> {
+    changed: &'a [bool],
+    pos: usize,
+}
+
+impl<'a> ChangedRanges<'a> {
+    fn new(changed: &'a [bool]) -> Self {
+        Self { changed, pos: 0 }
+    }
+}
+
+impl Iterator for ChangedRanges<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        let rest = self.changed.get(self.pos..)?;
+        let start = self.pos + rest.iter().position(|&c| c)?;
+        let end = self.changed[start..]
+            .iter()
+            .position(|&c| !c)
+            .map_or(self.changed.len(), |len| start + len);
+        self.pos = end;
+        Some(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn changed_ranges() {
+        let changed = [true, true, false, false, true, false, true];
+        let ranges: Vec<_> = ChangedRanges::new(&changed).collect();
+        assert_eq!(ranges, [0..2, 4..5, 6..7]);
+        assert_eq!(ChangedRanges::new(&[false; 4]).next(), None);
+        assert_eq!(ChangedRanges::new(&[]).next(), None);
+    }
+
+    #[cfg(feature = "holding-registers")]
+    #[test]
+    fn track_register_writes() {
+        use crate::{
+            ModbusServer,
+            client::{self, REQUEST_FRAME_LEN},
+        };
+
+        let mut store = DataStore::<0, 0, 8, 0>::new();
+        store.holding_registers[1] = 5;
+        assert!(!store.has_changes());
+
+        store.write_registers(2, 3, &[1, 2, 3]).unwrap();
+        assert_eq!(
+            store.write_registers(6, 3, &[1, 2, 3]),
+            Err(Error::InvalidAddress)
+        );
+        {
+            let mut server = ModbusServer::new(1, &mut store);
+            let mut tx = [0u8; 256];
+            for request in [
+                client::Request::WriteSingleRegister(7, 9),
+                client::Request::ReadHoldingRegisters(0, 8),
+            ] {
+                let mut rx = [0u8; REQUEST_FRAME_LEN];
+                let len = client::encode_request(1, request, &mut rx).unwrap();
+                server.process_frame(&rx[..len], &mut tx).unwrap();
+            }
+        }
+        assert_eq!(store.holding_registers, [0, 5, 1, 2, 3, 0, 0, 9]);
+        let ranges: Vec<_> = store.changed_holding_registers().collect();
+        assert_eq!(ranges, [2..5, 7..8]);
+        assert!(store.has_changes());
+
+        store.acknowledge_holding_registers();
+        assert_eq!(store.changed_holding_registers().next(), None);
+        assert!(!store.has_changes());
+    }
+
+    #[cfg(feature = "coils")]
+    #[test]
+    fn track_coil_writes() {
+        let mut store = DataStore::<4, 0, 0, 0>::new();
+        store.write_coils(1, 2, &[true, false]).unwrap();
+        let mut ranges = store.changed_coils();
+        assert_eq!(ranges.next(), Some(1..3));
+        assert_eq!(ranges.next(), None);
+        assert_eq!(store.coils, [false, true, false, false]);
+        store.acknowledge_coils();
+        assert!(!store.has_changes());
+    }
+}
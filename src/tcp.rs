@@ -0,0 +1,220 @@
+//! Modbus TCP framing
+//!
+//! A Modbus TCP frame consists of the MBAP header followed by the PDU. Unlike RTU frames, TCP
+//! frames carry their length in the header and have no CRC.
+//!
+//! [`ModbusServer::process_tcp_frame`] serves Modbus TCP requests with the same handler as RTU
+//! frames.
+
+use crate::{
+    ModbusServer, Outcome, decode_pdu,
+    error::Error,
+    handler::ModbusHandler,
+    observer::{Observer, Reply, RequestEvent, Transport},
+    respond_pdu,
+};
+
+/// Length of the MBAP header: transaction ID, protocol ID, length and unit ID
+pub const MBAP_HEADER_LEN: usize = 7;
+
+/// Maximum length of a PDU, shared by RTU and TCP
+pub const MAX_PDU_LEN: usize = 253;
+
+/// Maximum length of a Modbus TCP frame
+pub const MAX_FRAME_LEN: usize = MBAP_HEADER_LEN + MAX_PDU_LEN;
+
+/// Unit ID addressing a Modbus TCP server directly, as recommended by the specification
+pub const DIRECT_UNIT_ID: u8 = 0xFF;
+
+/// Protocol ID of Modbus in the MBAP header
+const PROTOCOL_ID: u16 = 0;
+
+/// Fields of the MBAP header which identify a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MbapHeader {
+    /// Chosen by the master, copied into the response
+    pub transaction_id: u16,
+    /// Unit ID, used to address a device behind a gateway
+    pub unit_id: u8,
+}
+
+/// Length of the frame at the start of `buf` according to its MBAP header
+///
+/// Returns `None` if `buf` holds less than the header or the header does not describe a valid
+/// Modbus frame. `buf` may hold more or fewer bytes than the frame, which allows framing a byte
+/// stream.
+pub fn frame_len(buf: &[u8]) -> Option<usize> {
+    if buf.len() < MBAP_HEADER_LEN {
+        return None;
+    }
+    let protocol_id = u16::from_be_bytes([buf[2], buf[3]]);
+    // the length field counts the unit ID and the PDU
+    let length = usize::from(u16::from_be_bytes([buf[4], buf[5]]));
+    if protocol_id != PROTOCOL_ID || !(2..=MAX_PDU_LEN + 1).contains(&length) {
+        return None;
+    }
+    Some(MBAP_HEADER_LEN - 1 + length)
+}
+
+/// Split a complete Modbus TCP frame into its header and PDU
+///
+/// Returns `None` if the header is invalid or its length field does not match the frame.
+pub fn decode(frame: &[u8]) -> Option<(MbapHeader, &[u8])> {
+    if frame_len(frame)? != frame.len() {
+        return None;
+    }
+    let header = MbapHeader {
+        transaction_id: u16::from_be_bytes([frame[0], frame[1]]),
+        unit_id: frame[6],
+    };
+    Some((header, &frame[MBAP_HEADER_LEN..]))
+}
+
+/// Write the MBAP header for a PDU of `pdu_len` bytes to the start of `buf`
+///
+/// The PDU itself is expected at `buf[MBAP_HEADER_LEN..]`. Returns the length of the frame.
+///
+/// # Panics
+///
+/// Panics if `buf` is shorter than [`MBAP_HEADER_LEN`].
+pub fn encode_header(buf: &mut [u8], header: MbapHeader, pdu_len: usize) -> usize {
+    buf[0..2].copy_from_slice(&header.transaction_id.to_be_bytes());
+    buf[2..4].copy_from_slice(&PROTOCOL_ID.to_be_bytes());
+    buf[4..6].copy_from_slice(&(pdu_len as u16 + 1).to_be_bytes());
+    buf[6] = header.unit_id;
+    MBAP_HEADER_LEN + pdu_len
+}
+
+impl<H, O> ModbusServer<H, O>
+where
+    H: ModbusHandler,
+    O: Observer,
+{
+    /// Process a single complete Modbus TCP request frame.
+    ///
+    /// Works like [`ModbusServer::process_frame`], with an MBAP header instead of the unit ID and
+    /// CRC. The response carries the transaction ID and unit ID of the request.
+    ///
+    /// A TCP server is addressed by its IP address, so besides its own unit ID the server also
+    /// answers requests for [`DIRECT_UNIT_ID`] and unit ID 0. There are no broadcasts over TCP,
+    /// requests for unit ID 0 are answered like any other (see also [`crate::gateway::Gateway`],
+    /// which rejects them).
+    /// Frames with an invalid MBAP header are reported as [`Outcome::Malformed`], the application
+    /// should close the connection then.
+    pub fn process_tcp_frame(&mut self, rx: &[u8], tx: &mut [u8]) -> Result<Outcome, Error> {
+        let Some((header, pdu)) = decode(rx) else {
+            debug!("invalid MBAP header");
+            return Ok(Outcome::Malformed);
+        };
+        if ![self.unit_id, DIRECT_UNIT_ID, 0].contains(&header.unit_id) {
+            trace!("frame for unit {}", header.unit_id);
+            return Ok(Outcome::NotForUs);
+        }
+        let Some((function_code, request)) = decode_pdu(pdu) else {
+            debug!("malformed PDU with function code {}", pdu[0]);
+            return Ok(Outcome::Malformed);
+        };
+
+        let event = RequestEvent {
+            unit_id: header.unit_id,
+            transport: Transport::Tcp,
+            function_code,
+            request: request.ok(),
+        };
+        self.observer.before_request(&event);
+
+        if self.listen_only {
+            self.observer.after_request(&event, Reply::Dropped);
+            return Ok(Outcome::ListenOnly);
+        }
+
+        let pdu_len = match tx.get_mut(MBAP_HEADER_LEN..) {
+            Some(pdu) => respond_pdu(&mut self.handler, function_code, request, pdu),
+            None => Err(Error::BufferTooSmall),
+        };
+        let reply = match pdu_len {
+            Ok(_) => Reply::from_pdu(&tx[MBAP_HEADER_LEN..]),
+            Err(_) => Reply::Dropped,
+        };
+        self.observer.after_request(&event, reply);
+        let pdu_len = pdu_len?;
+        self.take_unit_id_change();
+        Ok(Outcome::Responded(encode_header(tx, header, pdu_len)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trip() {
+        let header = MbapHeader {
+            transaction_id: 0x1234,
+            unit_id: 0x11,
+        };
+        let mut frame = [0u8; 12];
+        frame[MBAP_HEADER_LEN..].copy_from_slice(&[0x03, 0x00, 0x6B, 0x00, 0x03]);
+        let len = encode_header(&mut frame, header, 5);
+
+        assert_eq!(len, 12);
+        assert_eq!(frame[..7], [0x12, 0x34, 0x00, 0x00, 0x00, 0x06, 0x11]);
+        assert_eq!(frame_len(&frame[..7]), Some(12));
+        assert_eq!(decode(&frame), Some((header, &frame[7..])));
+
+        // truncated frame, wrong protocol ID, missing PDU
+        assert_eq!(decode(&frame[..11]), None);
+        frame[3] = 1;
+        assert_eq!(frame_len(&frame), None);
+        assert_eq!(frame_len(&[0, 0, 0, 0, 0, 1, 0]), None);
+    }
+
+    #[cfg(feature = "holding-registers")]
+    #[test]
+    fn process_tcp_frame() {
+        struct Registers;
+
+        impl ModbusHandler for Registers {
+            fn read_holding_registers(
+                &mut self,
+                addr: usize,
+                len: usize,
+                out: &mut [u16],
+            ) -> Result<usize, Error> {
+                out.fill(addr as u16);
+                Ok(len)
+            }
+        }
+
+        let mut server = ModbusServer::new(3, Registers);
+        let mut tx = [0u8; MAX_FRAME_LEN];
+
+        // read 2 holding registers at 0x0102
+        let mut frame = [
+            0x00, 0x2A, 0x00, 0x00, 0x00, 0x06, 0xFF, 0x03, 0x01, 0x02, 0x00, 0x02,
+        ];
+        let outcome = server.process_tcp_frame(&frame, &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::Responded(13));
+        assert_eq!(
+            tx[..13],
+            [
+                0x00, 0x2A, 0x00, 0x00, 0x00, 0x07, 0xFF, 0x03, 0x04, 0x01, 0x02, 0x01, 0x02
+            ]
+        );
+
+        // exception responses keep the header
+        frame[11] = 0;
+        let outcome = server.process_tcp_frame(&frame, &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::Responded(9));
+        assert_eq!(
+            tx[..9],
+            [0x00, 0x2A, 0x00, 0x00, 0x00, 0x03, 0xFF, 0x83, 0x03]
+        );
+
+        frame[6] = 4;
+        let outcome = server.process_tcp_frame(&frame, &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::NotForUs);
+        let outcome = server.process_tcp_frame(&frame[..11], &mut tx).unwrap();
+        assert_eq!(outcome, Outcome::Malformed);
+    }
+}
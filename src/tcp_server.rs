@@ -0,0 +1,268 @@
+//! Blocking Modbus TCP server for `std` targets
+//!
+//! Available with the `std` and `tcp` features. A [`TcpServer`] accepts master connections on a
+//! TCP listener and serves each one in its own thread. All connections share one
+//! [`ModbusServer`] behind a mutex, which the application can lock to access the handler.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    ModbusServer, Outcome,
+    handler::ModbusHandler,
+    observer::{NoObserver, Observer},
+    tcp::{MAX_FRAME_LEN, MBAP_HEADER_LEN, frame_len},
+};
+
+/// Settings of a [`TcpServer`]
+#[derive(Debug, Clone)]
+pub struct TcpServerConfig {
+    /// Maximum number of simultaneous connections, further connections are closed right away
+    pub max_connections: usize,
+    /// Connections without any request for this time are closed, `None` keeps them open
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for TcpServerConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 8,
+            idle_timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Modbus TCP server accepting several master connections
+pub struct TcpServer<H, O = NoObserver> {
+    /// Listener accepting master connections
+    listener: TcpListener,
+    /// Server shared by all connections
+    server: Arc<Mutex<ModbusServer<H, O>>>,
+    /// Connection settings
+    config: TcpServerConfig,
+    /// Number of open connections
+    connections: Arc<AtomicUsize>,
+}
+
+impl<H, O> TcpServer<H, O>
+where
+    H: ModbusHandler + Send + 'static,
+    O: Observer + Send + 'static,
+{
+    /// Listen for connections on `addr`, e.g. `"0.0.0.0:502"`
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        server: ModbusServer<H, O>,
+        config: TcpServerConfig,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            server: Arc::new(Mutex::new(server)),
+            config,
+            connections: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Address the server is listening on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The shared server, lock it to access the handler while the server is running
+    pub fn server(&self) -> Arc<Mutex<ModbusServer<H, O>>> {
+        Arc::clone(&self.server)
+    }
+
+    /// Number of open connections
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    /// Accept and serve connections
+    ///
+    /// Every connection is served by its own thread. Returns only if accepting a connection
+    /// fails.
+    pub fn run(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            if self.connections.fetch_add(1, Ordering::Relaxed) >= self.config.max_connections {
+                self.connections.fetch_sub(1, Ordering::Relaxed);
+                continue;
+            }
+
+            let connection = Connection {
+                stream,
+                server: Arc::clone(&self.server),
+                connections: Arc::clone(&self.connections),
+            };
+            let idle_timeout = self.config.idle_timeout;
+            thread::spawn(move || {
+                // errors only end this connection
+                let _ = connection.serve(idle_timeout);
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A master connection, counted while it is open
+struct Connection<H, O> {
+    stream: TcpStream,
+    server: Arc<Mutex<ModbusServer<H, O>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl<H: ModbusHandler, O: Observer> Connection<H, O> {
+    /// Serve requests until the master closes the connection, the idle timeout expires or a
+    /// malformed frame is received
+    fn serve(mut self, idle_timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(idle_timeout)?;
+        self.stream.set_nodelay(true)?;
+
+        let mut rx = [0u8; MAX_FRAME_LEN];
+        let mut tx = [0u8; MAX_FRAME_LEN];
+        loop {
+            match self.stream.read_exact(&mut rx[..MBAP_HEADER_LEN]) {
+                Ok(()) => {}
+                Err(e) if is_closed(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            let Some(len) = frame_len(&rx[..MBAP_HEADER_LEN]) else {
+                return Ok(());
+            };
+            self.stream.read_exact(&mut rx[MBAP_HEADER_LEN..len])?;
+
+            let outcome = self.lock().process_tcp_frame(&rx[..len], &mut tx);
+            match outcome {
+                Ok(Outcome::Responded(len)) => {
+                    self.stream.write_all(&tx[..len])?;
+                    self.lock().transmit_complete();
+                }
+                Ok(Outcome::Malformed) | Err(_) => return Ok(()),
+                Ok(_) => {}
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ModbusServer<H, O>> {
+        // a panicking handler in another connection does not stop the server
+        self.server.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<H, O> Drop for Connection<H, O> {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The master closed the connection, or the idle timeout expired
+fn is_closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::UnexpectedEof | ErrorKind::WouldBlock | ErrorKind::TimedOut
+    )
+}
+
+#[cfg(all(test, feature = "holding-registers"))]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::error::Error;
+
+    /// Holding registers, every register holds its address
+    struct Addresses;
+
+    impl ModbusHandler for Addresses {
+        fn read_holding_registers(
+            &mut self,
+            addr: usize,
+            len: usize,
+            out: &mut [u16],
+        ) -> Result<usize, Error> {
+            for (i, value) in out.iter_mut().enumerate() {
+                *value = (addr + i) as u16;
+            }
+            Ok(len)
+        }
+    }
+
+    fn start(config: TcpServerConfig) -> (SocketAddr, Arc<TcpServer<Addresses>>) {
+        let server = ModbusServer::new(1, Addresses);
+        let server = Arc::new(TcpServer::bind("127.0.0.1:0", server, config).unwrap());
+        let running = Arc::clone(&server);
+        thread::spawn(move || running.run());
+        (server.local_addr().unwrap(), server)
+    }
+
+    /// Read one holding register, `None` if the connection was closed
+    fn read_register(stream: &mut TcpStream, transaction_id: u8, addr: u8) -> Option<u16> {
+        let request = [0, transaction_id, 0, 0, 0, 6, 1, 0x03, 0, addr, 0, 1];
+        stream.write_all(&request).ok()?;
+        let mut response = [0u8; 11];
+        stream.read_exact(&mut response).ok()?;
+        assert_eq!(response[..9], [0, transaction_id, 0, 0, 0, 5, 1, 0x03, 2]);
+        Some(u16::from_be_bytes([response[9], response[10]]))
+    }
+
+    fn connect(addr: SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    #[test]
+    fn concurrent_connections() {
+        let (addr, _server) = start(TcpServerConfig::default());
+
+        let clients: Vec<_> = (0..4)
+            .map(|i| {
+                thread::spawn(move || {
+                    let mut stream = connect(addr);
+                    for n in 0..20 {
+                        let reg = i * 20 + n;
+                        assert_eq!(read_register(&mut stream, n, reg), Some(u16::from(reg)));
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn connection_limit_and_idle_timeout() {
+        let (addr, server) = start(TcpServerConfig {
+            max_connections: 1,
+            idle_timeout: Some(Duration::from_millis(200)),
+        });
+
+        let mut first = connect(addr);
+        assert_eq!(read_register(&mut first, 1, 7), Some(7));
+        assert_eq!(server.connections(), 1);
+
+        // the second connection is closed right away
+        let mut second = connect(addr);
+        assert_eq!(read_register(&mut second, 1, 7), None);
+
+        // the first one after being idle
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(read_register(&mut first, 2, 7), None);
+        assert_eq!(server.connections(), 0);
+
+        let mut third = connect(addr);
+        assert_eq!(read_register(&mut third, 1, 8), Some(8));
+    }
+}
@@ -0,0 +1,692 @@
+//! Test harness for handlers
+//!
+//! Available with the `testing` feature, which requires `std`. A [`Loopback`] builds requests
+//! with the [`crate::client`], runs them through a [`ModbusServer`] and decodes the responses,
+//! while a [`Recorder`] logs every call the server makes to the handler. Tests then assert on
+//! decoded values and handler calls instead of raw frames:
+//!
+//! ```
+//! use modbus_server::{
+//!     client::Request,
+//!     testing::{Call, Loopback, MockHandler, OwnedResponse},
+//! };
+//!
+//! let mut loopback = Loopback::new(1, MockHandler::new());
+//! loopback.handler_mut().holding_registers[10] = 42;
+//!
+//! let response = loopback.request(Request::ReadHoldingRegisters(10, 1));
+//! assert_eq!(response, Ok(OwnedResponse::ReadHoldingRegisters(vec![42])));
+//! assert_eq!(
+//!     loopback.take_calls(),
+//!     [Call::ReadHoldingRegisters { addr: 10, len: 1 }]
+//! );
+//! ```
+//!
+//! [`MockHandler`] keeps the complete address space in memory and serves as a stand-in for
+//! application handlers, any other [`ModbusHandler`] can be tested the same way.
+//!
+//! With one of the transport features, [`MockSerial`] and [`MockPin`] stand in for the UART and
+//! the RS-485 driver enable pin of a [`crate::transport::RtuTransport`].
+
+#[cfg(any(
+    feature = "embedded-io",
+    feature = "embedded-io-async",
+    feature = "embedded-hal-nb"
+))]
+use core::{cell::Cell, convert::Infallible};
+#[cfg(any(
+    feature = "embedded-io",
+    feature = "embedded-io-async",
+    feature = "embedded-hal-nb"
+))]
+use std::{collections::VecDeque, rc::Rc};
+use std::{vec, vec::Vec};
+
+use modbus_core::rtu::{MAX_FRAME_LEN, crc16};
+
+#[cfg(feature = "coils")]
+use crate::bits::Bits;
+#[cfg(any(feature = "coils", feature = "discrete-inputs"))]
+use crate::bits::BitsMut;
+use crate::{
+    ModbusServer, Outcome,
+    client::{self, Request, Response},
+    error::Error,
+    handler::ModbusHandler,
+};
+
+/// Number of addresses of each data type
+const ADDRESS_SPACE: usize = 0x10000;
+
+/// Handler method call recorded by a [`Recorder`]
+///
+/// Calls are recorded as the server makes them, i.e. bit reads and writes through the packed
+/// methods, each with the whole range of the request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    /// [`ModbusHandler::read_coils_packed`]
+    ReadCoils { addr: usize, len: usize },
+    /// [`ModbusHandler::read_discrete_input_packed`]
+    ReadDiscreteInputs { addr: usize, len: usize },
+    /// [`ModbusHandler::read_holding_registers`]
+    ReadHoldingRegisters { addr: usize, len: usize },
+    /// [`ModbusHandler::read_input_registers`]
+    ReadInputRegisters { addr: usize, len: usize },
+    /// [`ModbusHandler::write_coils_packed`]
+    WriteCoils { addr: usize, values: Vec<bool> },
+    /// [`ModbusHandler::write_registers`]
+    WriteRegisters { addr: usize, values: Vec<u16> },
+}
+
+/// Overwrite the last two bytes of an RTU frame with the CRC of the bytes before
+///
+/// For frames built by hand, e.g. of function codes the [`crate::client`] does not build.
+pub fn set_crc(frame: &mut [u8]) {
+    let len = frame.len();
+    let crc = crc16(&frame[..len - 2]);
+    frame[len - 2..].copy_from_slice(&crc.to_be_bytes());
+}
+
+/// Handler wrapper recording every call before forwarding it to the inner handler
+pub struct Recorder<H> {
+    /// Wrapped handler
+    inner: H,
+    /// Calls in the order they were made
+    calls: Vec<Call>,
+}
+
+impl<H> Recorder<H> {
+    /// Wrap a handler
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            calls: Vec::new(),
+        }
+    }
+
+    /// Calls recorded so far
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    /// Return and clear the recorded calls
+    pub fn take_calls(&mut self) -> Vec<Call> {
+        core::mem::take(&mut self.calls)
+    }
+
+    /// Reference to the wrapped handler
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    /// Mutable reference to the wrapped handler
+    pub fn inner_mut(&mut self) -> &mut H {
+        &mut self.inner
+    }
+}
+
+impl<H: ModbusHandler> ModbusHandler for Recorder<H> {
+    #[cfg(feature = "coils")]
+    fn read_coils_packed(&mut self, addr: usize, out: &mut BitsMut) -> Result<usize, Error> {
+        let len = out.len();
+        self.calls.push(Call::ReadCoils { addr, len });
+        self.inner.read_coils_packed(addr, out)
+    }
+
+    #[cfg(feature = "discrete-inputs")]
+    fn read_discrete_input_packed(
+        &mut self,
+        addr: usize,
+        out: &mut BitsMut,
+    ) -> Result<usize, Error> {
+        let len = out.len();
+        self.calls.push(Call::ReadDiscreteInputs { addr, len });
+        self.inner.read_discrete_input_packed(addr, out)
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn read_holding_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.calls.push(Call::ReadHoldingRegisters { addr, len });
+        self.inner.read_holding_registers(addr, len, out)
+    }
+
+    #[cfg(feature = "input-registers")]
+    fn read_input_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.calls.push(Call::ReadInputRegisters { addr, len });
+        self.inner.read_input_registers(addr, len, out)
+    }
+
+    #[cfg(feature = "coils")]
+    fn write_coils_packed(&mut self, addr: usize, bits: Bits) -> Result<usize, Error> {
+        let values = bits.iter().collect();
+        self.calls.push(Call::WriteCoils { addr, values });
+        self.inner.write_coils_packed(addr, bits)
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn write_registers(&mut self, addr: usize, len: usize, buf: &[u16]) -> Result<usize, Error> {
+        let values = buf.to_vec();
+        self.calls.push(Call::WriteRegisters { addr, values });
+        self.inner.write_registers(addr, len, buf)
+    }
+
+    fn take_unit_id_change(&mut self) -> Option<u8> {
+        self.inner.take_unit_id_change()
+    }
+}
+
+/// Handler holding the complete address space of all data types in memory
+///
+/// All accesses succeed, unless an error is set with [`MockHandler::fail_with`].
+pub struct MockHandler {
+    /// Coil values by address
+    pub coils: Vec<bool>,
+    /// Discrete input values by address
+    pub discrete_inputs: Vec<bool>,
+    /// Holding register values by address
+    pub holding_registers: Vec<u16>,
+    /// Input register values by address
+    pub input_registers: Vec<u16>,
+    /// Error returned by every access while set
+    error: Option<Error>,
+}
+
+impl MockHandler {
+    /// Create a handler with all values cleared
+    pub fn new() -> Self {
+        Self {
+            coils: vec![false; ADDRESS_SPACE],
+            discrete_inputs: vec![false; ADDRESS_SPACE],
+            holding_registers: vec![0; ADDRESS_SPACE],
+            input_registers: vec![0; ADDRESS_SPACE],
+            error: None,
+        }
+    }
+
+    /// Make every access fail with `error`, or succeed again with `None`
+    pub fn fail_with(&mut self, error: Option<Error>) {
+        self.error = error;
+    }
+
+    /// Fail with the error set by [`MockHandler::fail_with`]
+    fn check(&self) -> Result<(), Error> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Copy `len` values from `addr` into `out`, unless an error is set
+    fn read<T: Copy>(
+        &self,
+        values: &[T],
+        addr: usize,
+        len: usize,
+        out: &mut [T],
+    ) -> Result<usize, Error> {
+        self.check()?;
+        out[..len].copy_from_slice(&values[addr..addr + len]);
+        Ok(len)
+    }
+}
+
+impl Default for MockHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ModbusHandler for MockHandler {
+    #[cfg(feature = "coils")]
+    fn read_coils(&mut self, addr: usize, len: usize, out: &mut [bool]) -> Result<usize, Error> {
+        self.read(&self.coils, addr, len, out)
+    }
+
+    #[cfg(feature = "discrete-inputs")]
+    fn read_discrete_input(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [bool],
+    ) -> Result<usize, Error> {
+        self.read(&self.discrete_inputs, addr, len, out)
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn read_holding_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.read(&self.holding_registers, addr, len, out)
+    }
+
+    #[cfg(feature = "input-registers")]
+    fn read_input_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.read(&self.input_registers, addr, len, out)
+    }
+
+    #[cfg(feature = "coils")]
+    fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+        self.check()?;
+        self.coils[addr..addr + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    #[cfg(feature = "holding-registers")]
+    fn write_registers(&mut self, addr: usize, len: usize, buf: &[u16]) -> Result<usize, Error> {
+        self.check()?;
+        self.holding_registers[addr..addr + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// Decoded response which owns its data, see [`client::Response`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OwnedResponse {
+    /// Coil values, one per requested coil
+    ReadCoils(Vec<bool>),
+    /// Discrete input values, one per requested input
+    ReadDiscreteInputs(Vec<bool>),
+    /// Holding register values, one per requested register
+    ReadHoldingRegisters(Vec<u16>),
+    /// Input register values, one per requested register
+    ReadInputRegisters(Vec<u16>),
+    /// Echo of a written coil: address, value
+    WriteSingleCoil(u16, bool),
+    /// Echo of a written register: address, value
+    WriteSingleRegister(u16, u16),
+    /// Echo of written registers: address, quantity
+    WriteMultipleRegisters(u16, u16),
+}
+
+impl From<Response<'_>> for OwnedResponse {
+    fn from(response: Response) -> Self {
+        match response {
+            Response::ReadCoils(bits) => Self::ReadCoils(bits.iter().collect()),
+            Response::ReadDiscreteInputs(bits) => Self::ReadDiscreteInputs(bits.iter().collect()),
+            Response::ReadHoldingRegisters(regs) => {
+                Self::ReadHoldingRegisters(regs.iter().collect())
+            }
+            Response::ReadInputRegisters(regs) => Self::ReadInputRegisters(regs.iter().collect()),
+            Response::WriteSingleCoil(addr, value) => Self::WriteSingleCoil(addr, value),
+            Response::WriteSingleRegister(addr, value) => Self::WriteSingleRegister(addr, value),
+            Response::WriteMultipleRegisters(addr, len) => Self::WriteMultipleRegisters(addr, len),
+        }
+    }
+}
+
+/// Client and server connected back to back
+///
+/// Requests are encoded by [`client::encode_request`], processed by a [`ModbusServer`] with a
+/// [`Recorder`] around the handler under test, and the responses are checked and decoded by
+/// [`client::decode_response`].
+pub struct Loopback<H> {
+    /// Server under test
+    server: ModbusServer<Recorder<H>>,
+    /// Response frame to the last processed frame, empty if there was none
+    last_response: Vec<u8>,
+}
+
+impl<H: ModbusHandler> Loopback<H> {
+    /// Create a server for `unit_id` serving `handler`
+    pub fn new(unit_id: u8, handler: H) -> Self {
+        Self {
+            server: ModbusServer::new(unit_id, Recorder::new(handler)),
+            last_response: Vec::new(),
+        }
+    }
+
+    /// Send a request to the server and decode its response
+    ///
+    /// Exception responses are returned as [`Error::Exception`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the request can't be encoded, the server does not respond or its response
+    /// is not a valid answer to the request.
+    pub fn request(&mut self, request: Request) -> Result<OwnedResponse, Error> {
+        let unit_id = self.server.unit_id();
+        let mut tx = [0u8; MAX_FRAME_LEN];
+        let len = client::encode_request(unit_id, request, &mut tx).expect("invalid request");
+
+        match self.process(&tx[..len]) {
+            Ok(Outcome::Responded(_)) => {}
+            other => panic!("no response to {request:?}: {other:?}"),
+        }
+        match client::decode_response(unit_id, request, &self.last_response) {
+            Ok(response) => Ok(response.into()),
+            Err(Error::Exception(exception)) => Err(Error::Exception(exception)),
+            Err(e) => panic!("invalid response to {request:?}: {e:?}"),
+        }
+    }
+
+    /// Send a write request as broadcast, which is executed without a response
+    ///
+    /// # Panics
+    ///
+    /// Panics if the request can't be encoded as broadcast or the server does not report it as
+    /// [`Outcome::Broadcast`].
+    pub fn broadcast(&mut self, request: Request) {
+        let mut tx = [0u8; MAX_FRAME_LEN];
+        let len = client::encode_request(crate::BROADCAST_ID, request, &mut tx)
+            .expect("invalid broadcast request");
+        let outcome = self.process(&tx[..len]);
+        assert_eq!(outcome, Ok(Outcome::Broadcast));
+    }
+
+    /// Pass a raw frame to the server
+    ///
+    /// The response, if any, is available from [`Loopback::last_response`].
+    pub fn process(&mut self, frame: &[u8]) -> Result<Outcome, Error> {
+        let mut tx = [0u8; 256];
+        let outcome = self.server.process_frame(frame, &mut tx)?;
+        self.last_response = tx[..outcome.response_len()].to_vec();
+        Ok(outcome)
+    }
+
+    /// Response frame to the last processed frame, empty if there was none
+    pub fn last_response(&self) -> &[u8] {
+        &self.last_response
+    }
+
+    /// Calls the server made to the handler so far
+    pub fn calls(&self) -> &[Call] {
+        self.server.handler().calls()
+    }
+
+    /// Return and clear the recorded handler calls
+    pub fn take_calls(&mut self) -> Vec<Call> {
+        self.server.handler_mut().take_calls()
+    }
+
+    /// Reference to the handler under test
+    pub fn handler(&self) -> &H {
+        self.server.handler().inner()
+    }
+
+    /// Mutable reference to the handler under test
+    pub fn handler_mut(&mut self) -> &mut H {
+        self.server.handler_mut().inner_mut()
+    }
+
+    /// Server under test, e.g. to change its unit ID or mode
+    pub fn server_mut(&mut self) -> &mut ModbusServer<Recorder<H>> {
+        &mut self.server
+    }
+}
+
+/// Output pin for transport tests, clones share the pin state
+#[cfg(any(
+    feature = "embedded-io",
+    feature = "embedded-io-async",
+    feature = "embedded-hal-nb"
+))]
+#[derive(Debug, Clone, Default)]
+pub struct MockPin(Rc<Cell<bool>>);
+
+#[cfg(any(
+    feature = "embedded-io",
+    feature = "embedded-io-async",
+    feature = "embedded-hal-nb"
+))]
+impl MockPin {
+    /// Returns `true` if the pin is set high
+    pub fn is_high(&self) -> bool {
+        self.0.get()
+    }
+}
+
+#[cfg(any(
+    feature = "embedded-io",
+    feature = "embedded-io-async",
+    feature = "embedded-hal-nb"
+))]
+impl embedded_hal::digital::ErrorType for MockPin {
+    type Error = Infallible;
+}
+
+#[cfg(any(
+    feature = "embedded-io",
+    feature = "embedded-io-async",
+    feature = "embedded-hal-nb"
+))]
+impl embedded_hal::digital::OutputPin for MockPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set(true);
+        Ok(())
+    }
+}
+
+/// In-memory serial port for transport tests
+///
+/// Bytes passed to [`MockSerial::receive`] are returned by reads, reads of an empty port return
+/// no bytes (or [`nb::Error::WouldBlock`]). Written bytes are collected and returned by
+/// [`MockSerial::transmitted`]. Implements the serial traits of the enabled `embedded-io`,
+/// `embedded-io-async` and `embedded-hal-nb` features.
+#[cfg(any(
+    feature = "embedded-io",
+    feature = "embedded-io-async",
+    feature = "embedded-hal-nb"
+))]
+#[derive(Debug, Default)]
+pub struct MockSerial {
+    rx: VecDeque<u8>,
+    tx: Vec<u8>,
+    /// Driver enable pin, which has to be high while writing
+    de: Option<MockPin>,
+}
+
+#[cfg(any(
+    feature = "embedded-io",
+    feature = "embedded-io-async",
+    feature = "embedded-hal-nb"
+))]
+impl MockSerial {
+    /// Create an empty serial port
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty RS-485 port, writes panic unless `de` is set high
+    pub fn with_de_pin(de: MockPin) -> Self {
+        Self {
+            de: Some(de),
+            ..Self::default()
+        }
+    }
+
+    /// Queue bytes to be read
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.rx.extend(bytes);
+    }
+
+    /// Bytes written so far
+    pub fn transmitted(&self) -> &[u8] {
+        &self.tx
+    }
+
+    /// Return and clear the bytes written so far
+    pub fn take_transmitted(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.tx)
+    }
+
+    #[cfg(any(feature = "embedded-io", feature = "embedded-io-async"))]
+    fn read_bytes(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.rx.len());
+        for (slot, byte) in buf.iter_mut().zip(self.rx.drain(..len)) {
+            *slot = byte;
+        }
+        len
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> usize {
+        if let Some(de) = &self.de {
+            assert!(de.is_high(), "transmitting with the DE pin low");
+        }
+        self.tx.extend_from_slice(bytes);
+        bytes.len()
+    }
+}
+
+#[cfg(any(feature = "embedded-io", feature = "embedded-io-async"))]
+impl embedded_io::ErrorType for MockSerial {
+    type Error = Infallible;
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Read for MockSerial {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.read_bytes(buf))
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Write for MockSerial {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.write_bytes(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Read for MockSerial {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.read_bytes(buf))
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Write for MockSerial {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.write_bytes(buf))
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl embedded_hal_nb::serial::ErrorType for MockSerial {
+    type Error = Infallible;
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl embedded_hal_nb::serial::Read for MockSerial {
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.rx.pop_front().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl embedded_hal_nb::serial::Write for MockSerial {
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.write_bytes(&[byte]);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "coils",
+    feature = "discrete-inputs",
+    feature = "holding-registers",
+    feature = "input-registers"
+))]
+mod tests {
+    use super::*;
+    use modbus_core::Exception;
+
+    #[test]
+    fn reads_and_writes() {
+        let mut loopback = Loopback::new(5, MockHandler::new());
+        loopback.handler_mut().discrete_inputs[3] = true;
+        loopback.handler_mut().input_registers[40..80].fill(7);
+
+        let response = loopback.request(Request::ReadDiscreteInputs(2, 3));
+        assert_eq!(
+            response,
+            Ok(OwnedResponse::ReadDiscreteInputs(vec![false, true, false]))
+        );
+        let response = loopback.request(Request::ReadInputRegisters(40, 40));
+        assert_eq!(response, Ok(OwnedResponse::ReadInputRegisters(vec![7; 40])));
+        assert_eq!(
+            loopback.take_calls(),
+            [
+                Call::ReadDiscreteInputs { addr: 2, len: 3 },
+                Call::ReadInputRegisters { addr: 40, len: 40 },
+            ]
+        );
+
+        let response = loopback.request(Request::WriteSingleCoil(9, true));
+        assert_eq!(response, Ok(OwnedResponse::WriteSingleCoil(9, true)));
+        loopback.broadcast(Request::WriteSingleRegister(1, 0x55AA));
+        assert!(loopback.last_response().is_empty());
+        let response = loopback.request(Request::WriteMultipleRegisters(2, &[3, 4]));
+        assert_eq!(response, Ok(OwnedResponse::WriteMultipleRegisters(2, 2)));
+        assert_eq!(
+            loopback.take_calls(),
+            [
+                Call::WriteCoils {
+                    addr: 9,
+                    values: vec![true]
+                },
+                Call::WriteRegisters {
+                    addr: 1,
+                    values: vec![0x55AA]
+                },
+                Call::WriteRegisters {
+                    addr: 2,
+                    values: vec![3, 4]
+                },
+            ]
+        );
+        assert!(loopback.handler().coils[9]);
+        assert_eq!(loopback.handler().holding_registers[1..4], [0x55AA, 3, 4]);
+    }
+
+    #[test]
+    fn handler_errors() {
+        let mut loopback = Loopback::new(5, MockHandler::new());
+        loopback
+            .handler_mut()
+            .fail_with(Some(Error::InvalidAddress));
+
+        let response = loopback.request(Request::ReadCoils(0, 1));
+        assert_eq!(
+            response,
+            Err(Error::Exception(Exception::IllegalDataAddress))
+        );
+        assert_eq!(loopback.calls(), [Call::ReadCoils { addr: 0, len: 1 }]);
+        assert_eq!(loopback.last_response()[..3], [0x05, 0x81, 0x02]);
+    }
+}
@@ -0,0 +1,554 @@
+//! Serial port adapters
+//!
+//! An [`RtuTransport`] owns a serial port and the receive buffer. It assembles request frames
+//! with a [`FrameAssembler`], passes them to [`ModbusServer::process_frame`] and transmits the
+//! response. On RS-485 buses the driver enable (DE) pin is set high for the transmission and
+//! low again once it completed.
+//!
+//! The serial port is accessed through the traits of one of the following features:
+//!
+//! * `embedded-io`: blocking [`RtuTransport::poll`] and [`RtuTransport::idle`]
+//! * `embedded-io-async`: [`RtuTransport::poll_async`] and [`RtuTransport::idle_async`]
+//! * `embedded-hal-nb`: non-blocking [`RtuTransport::poll_nb`] and [`RtuTransport::idle_nb`]
+//!
+//! Frames are complete once the length given by their function code has been received. This
+//! length is the one of a request, so on a bus shared with other slaves their responses are
+//! framed wrongly. One of the `idle` methods has to be called after every inter-frame silence
+//! (see [`crate::rtu::inter_frame_delay`]), driven by a timer of the application, to end such
+//! frames and the frames of unknown function codes. Only on a point-to-point line with known
+//! function codes the `poll` methods suffice.
+//!
+//! The blocking [`RtuTransport::poll`] waits inside `read` of the serial port, so the
+//! application can only call `idle` if that `read` returns. Configure the serial port to return
+//! from `read` with an error after the inter-frame silence, e.g. with a UART receive timeout of
+//! 3.5 characters, and call `idle` when `poll` returns that error. Alternatively call `poll`
+//! only if `embedded_io::ReadReady::read_ready` reports received bytes, and `idle` from the
+//! timer otherwise.
+//!
+//! Writing the response must not return before the last byte left the UART, otherwise the DE
+//! pin is released too early. `flush` of the serial port has to wait for the end of the
+//! transmission.
+
+use core::convert::Infallible;
+
+use embedded_hal::digital::{ErrorType, OutputPin};
+use modbus_core::rtu::MAX_FRAME_LEN;
+
+use crate::{
+    ModbusServer, Outcome, error::Error, handler::ModbusHandler, observer::Observer,
+    rtu::FrameAssembler,
+};
+
+/// Number of bytes read from the serial port at once
+const RX_CHUNK_LEN: usize = 32;
+
+/// Error of an [`RtuTransport`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportError<S, P> {
+    /// Reading from or writing to the serial port failed
+    Serial(S),
+    /// Switching the DE pin failed
+    Pin(P),
+    /// Processing a frame failed
+    Server(Error),
+}
+
+/// Placeholder for a missing DE pin, e.g. on RS-232 or with automatic direction control
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoPin;
+
+impl ErrorType for NoPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for NoPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Bytes read from the serial port, which are not yet passed to the assembler
+struct Receiver {
+    assembler: FrameAssembler,
+    buf: [u8; RX_CHUNK_LEN],
+    /// Position of the next byte to pass to the assembler
+    pos: usize,
+    /// Number of bytes in `buf`
+    len: usize,
+}
+
+impl Receiver {
+    const fn new() -> Self {
+        Self {
+            assembler: FrameAssembler::new(),
+            buf: [0; RX_CHUNK_LEN],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    #[cfg(any(feature = "embedded-io", feature = "embedded-io-async"))]
+    fn is_drained(&self) -> bool {
+        self.pos == self.len
+    }
+
+    /// Buffer to read new bytes into, only call once drained
+    fn buf_mut(&mut self) -> &mut [u8] {
+        self.pos = 0;
+        self.len = 0;
+        &mut self.buf
+    }
+
+    /// `len` bytes were read into [`Receiver::buf_mut`]
+    fn filled(&mut self, len: usize) {
+        self.len = len;
+    }
+
+    /// Pass pending bytes to the assembler until a frame is complete and process it
+    fn process<H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+        tx: &mut [u8],
+    ) -> Option<Result<Outcome, Error>> {
+        while self.pos < self.len {
+            let byte = self.buf[self.pos];
+            self.pos += 1;
+            if let Some(frame) = self.assembler.push(byte) {
+                return Some(server.process_frame(frame, tx));
+            }
+        }
+        None
+    }
+
+    /// End the current frame after the inter-frame silence and process it
+    fn idle<H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+        tx: &mut [u8],
+    ) -> Option<Result<Outcome, Error>> {
+        // bytes still pending were received before the silence
+        if let Some(result) = self.process(server, tx) {
+            // bytes after the frame belong to the same transmission, they are dropped
+            self.pos = self.len;
+            self.assembler.reset();
+            return Some(result);
+        }
+        let frame = self.assembler.idle()?;
+        Some(server.process_frame(frame, tx))
+    }
+}
+
+/// Serves a [`ModbusServer`] on a serial port
+pub struct RtuTransport<S, D = NoPin> {
+    serial: S,
+    de: D,
+    rx: Receiver,
+    tx: [u8; MAX_FRAME_LEN],
+}
+
+impl<S> RtuTransport<S, NoPin> {
+    /// Serve on `serial` without a DE pin
+    pub const fn new(serial: S) -> Self {
+        Self::with_de_pin(serial, NoPin)
+    }
+}
+
+impl<S, D> RtuTransport<S, D> {
+    /// Serve on `serial`, setting `de` high while transmitting
+    ///
+    /// The pin should be low initially.
+    pub const fn with_de_pin(serial: S, de: D) -> Self {
+        Self {
+            serial,
+            de,
+            rx: Receiver::new(),
+            tx: [0; MAX_FRAME_LEN],
+        }
+    }
+
+    /// Return the serial port and the DE pin
+    pub fn release(self) -> (S, D) {
+        (self.serial, self.de)
+    }
+}
+
+#[cfg(any(feature = "embedded-io", feature = "embedded-hal-nb"))]
+impl<S, D: OutputPin> RtuTransport<S, D> {
+    /// Switch the DE pin around `transmit`, releasing it also if the transmission failed
+    ///
+    /// The exchange is completed on the server on every path, errors are returned afterwards.
+    fn transmit<E, H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+        result: Result<Outcome, Error>,
+        transmit: impl FnOnce(&mut S, &[u8]) -> Result<(), E>,
+    ) -> Result<Outcome, TransportError<E, D::Error>> {
+        let result = result.map_err(TransportError::Server).and_then(|outcome| {
+            if let Outcome::Responded(len) = outcome {
+                let sent = self
+                    .de
+                    .set_high()
+                    .map_err(TransportError::Pin)
+                    .and_then(|()| {
+                        transmit(&mut self.serial, &self.tx[..len]).map_err(TransportError::Serial)
+                    });
+                let released = self.de.set_low().map_err(TransportError::Pin);
+                sent.and(released)?;
+            }
+            Ok(outcome)
+        });
+        // the exchange is over also if it failed, apply a pending unit ID change
+        server.transmit_complete();
+        result
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<S, D> RtuTransport<S, D>
+where
+    S: embedded_io::Read + embedded_io::Write,
+    D: OutputPin,
+{
+    /// Receive from the serial port and answer a completed frame
+    ///
+    /// Blocks until at least one byte is received, unless bytes of a previous read are still
+    /// pending. Returns the outcome of the frame, or `None` if no frame was completed. An error
+    /// of `read`, such as the receive timeout after the inter-frame silence, is returned as
+    /// [`TransportError::Serial`] and the bytes received so far are kept, see the
+    /// [module documentation](self) for calling [`RtuTransport::idle`] then.
+    pub fn poll<H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+    ) -> Result<Option<Outcome>, TransportError<S::Error, D::Error>> {
+        if self.rx.is_drained() {
+            let len = self
+                .serial
+                .read(self.rx.buf_mut())
+                .map_err(TransportError::Serial)?;
+            self.rx.filled(len);
+        }
+        match self.rx.process(server, &mut self.tx) {
+            Some(result) => self.transmit(server, result, write_blocking).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Answer the frame received so far, call after the inter-frame silence
+    pub fn idle<H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+    ) -> Result<Option<Outcome>, TransportError<S::Error, D::Error>> {
+        match self.rx.idle(server, &mut self.tx) {
+            Some(result) => self.transmit(server, result, write_blocking).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+fn write_blocking<S: embedded_io::Write>(serial: &mut S, frame: &[u8]) -> Result<(), S::Error> {
+    serial.write_all(frame)?;
+    serial.flush()
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<S, D> RtuTransport<S, D>
+where
+    S: embedded_io_async::Read + embedded_io_async::Write,
+    D: OutputPin,
+{
+    /// Receive from the serial port and answer a completed frame
+    ///
+    /// Waits until at least one byte is received, unless bytes of a previous read are still
+    /// pending. Returns the outcome of the frame, or `None` if no frame was completed.
+    pub async fn poll_async<H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+    ) -> Result<Option<Outcome>, TransportError<S::Error, D::Error>> {
+        if self.rx.is_drained() {
+            let len = self
+                .serial
+                .read(self.rx.buf_mut())
+                .await
+                .map_err(TransportError::Serial)?;
+            self.rx.filled(len);
+        }
+        match self.rx.process(server, &mut self.tx) {
+            Some(result) => self.transmit_async(server, result).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Answer the frame received so far, call after the inter-frame silence
+    pub async fn idle_async<H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+    ) -> Result<Option<Outcome>, TransportError<S::Error, D::Error>> {
+        match self.rx.idle(server, &mut self.tx) {
+            Some(result) => self.transmit_async(server, result).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn transmit_async<H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+        result: Result<Outcome, Error>,
+    ) -> Result<Outcome, TransportError<S::Error, D::Error>> {
+        let result = match result {
+            Ok(Outcome::Responded(len)) => {
+                let sent = match self.de.set_high() {
+                    Ok(()) => match self.serial.write_all(&self.tx[..len]).await {
+                        Ok(()) => self.serial.flush().await.map_err(TransportError::Serial),
+                        Err(e) => Err(TransportError::Serial(e)),
+                    },
+                    Err(e) => Err(TransportError::Pin(e)),
+                };
+                let released = self.de.set_low().map_err(TransportError::Pin);
+                sent.and(released).map(|()| Outcome::Responded(len))
+            }
+            result => result.map_err(TransportError::Server),
+        };
+        // the exchange is over also if it failed, apply a pending unit ID change
+        server.transmit_complete();
+        result
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<S, D> RtuTransport<S, D>
+where
+    S: embedded_hal_nb::serial::Read + embedded_hal_nb::serial::Write,
+    D: OutputPin,
+{
+    /// Receive a byte and answer the frame if it is complete
+    ///
+    /// Returns [`nb::Error::WouldBlock`] if no byte was received or the frame is not yet
+    /// complete. The response is transmitted blocking.
+    pub fn poll_nb<H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+    ) -> nb::Result<Outcome, TransportError<S::Error, D::Error>> {
+        let byte = self
+            .serial
+            .read()
+            .map_err(|e| e.map(TransportError::Serial))?;
+        self.rx.buf_mut()[0] = byte;
+        self.rx.filled(1);
+        match self.rx.process(server, &mut self.tx) {
+            Some(result) => Ok(self.transmit(server, result, write_nb)?),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+
+    /// Answer the frame received so far, call after the inter-frame silence
+    pub fn idle_nb<H: ModbusHandler, O: Observer>(
+        &mut self,
+        server: &mut ModbusServer<H, O>,
+    ) -> Result<Option<Outcome>, TransportError<S::Error, D::Error>> {
+        match self.rx.idle(server, &mut self.tx) {
+            Some(result) => self.transmit(server, result, write_nb).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+fn write_nb<S: embedded_hal_nb::serial::Write>(
+    serial: &mut S,
+    frame: &[u8],
+) -> Result<(), S::Error> {
+    for &byte in frame {
+        nb::block!(serial.write(byte))?;
+    }
+    nb::block!(serial.flush())
+}
+
+#[cfg(all(test, feature = "testing", feature = "holding-registers"))]
+mod tests {
+    use super::*;
+    use crate::testing::{MockHandler, MockPin, MockSerial};
+
+    /// Read holding registers 0 and 1 of unit 1, and the response
+    const REQUEST: [u8; 8] = [0x01, 0x03, 0x00, 0x00, 0x00, 0x02, 0xC4, 0x0B];
+    const RESPONSE: [u8; 9] = [0x01, 0x03, 0x04, 0x00, 0x2A, 0x00, 0x07, 0x9A, 0x39];
+
+    fn server() -> ModbusServer<MockHandler> {
+        let mut handler = MockHandler::new();
+        handler.holding_registers[..2].copy_from_slice(&[42, 7]);
+        ModbusServer::new(1, handler)
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn blocking() {
+        let mut server = server();
+        let de = MockPin::default();
+        let mut serial = MockSerial::with_de_pin(de.clone());
+        // two requests in one read
+        serial.receive(&REQUEST);
+        serial.receive(&REQUEST);
+        let mut transport = RtuTransport::with_de_pin(serial, de.clone());
+
+        assert_eq!(
+            transport.poll(&mut server),
+            Ok(Some(Outcome::Responded(RESPONSE.len())))
+        );
+        assert_eq!(
+            transport.poll(&mut server),
+            Ok(Some(Outcome::Responded(RESPONSE.len())))
+        );
+        assert_eq!(transport.poll(&mut server), Ok(None));
+        assert_eq!(transport.idle(&mut server), Ok(None));
+
+        // a truncated frame after the silence
+        transport.serial.receive(&[0x02, 0x41, 0x00]);
+        assert_eq!(transport.poll(&mut server), Ok(None));
+        assert_eq!(transport.idle(&mut server), Ok(Some(Outcome::Malformed)));
+        assert_eq!(transport.idle(&mut server), Ok(None));
+        assert!(!de.is_high());
+
+        let (serial, _) = transport.release();
+        assert_eq!(serial.transmitted(), [RESPONSE, RESPONSE].concat());
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn failed_transmission() {
+        use embedded_io::ErrorKind;
+
+        /// Serial port which receives the given bytes and fails to write
+        struct Broken(&'static [u8]);
+
+        impl embedded_io::ErrorType for Broken {
+            type Error = ErrorKind;
+        }
+
+        impl embedded_io::Read for Broken {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+                let len = buf.len().min(self.0.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        impl embedded_io::Write for Broken {
+            fn write(&mut self, _buf: &[u8]) -> Result<usize, ErrorKind> {
+                Err(ErrorKind::Other)
+            }
+
+            fn flush(&mut self) -> Result<(), ErrorKind> {
+                Ok(())
+            }
+        }
+
+        let mut server = server();
+        server.set_unit_id_deferred(5).unwrap();
+        let de = MockPin::default();
+        let mut transport = RtuTransport::with_de_pin(Broken(&REQUEST), de.clone());
+
+        assert_eq!(
+            transport.poll(&mut server),
+            Err(TransportError::Serial(ErrorKind::Other))
+        );
+        assert!(!de.is_high());
+        assert_eq!(server.unit_id(), 5);
+        assert_eq!(server.pending_unit_id(), None);
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn shared_bus() {
+        let mut server = server();
+        let mut transport = RtuTransport::new(MockSerial::new());
+        // response of unit 2 to a read request, longer than the request, which is cut after the
+        // length of a request
+        let other = [0x02, 0x03, 0x04, 0x00, 0x2A, 0x00, 0x07, 0x90, 0xE1];
+
+        transport.serial.receive(&other);
+        assert_eq!(transport.poll(&mut server), Ok(Some(Outcome::CrcError)));
+        assert_eq!(transport.poll(&mut server), Ok(None));
+        assert_eq!(transport.idle(&mut server), Ok(None));
+        transport.serial.receive(&REQUEST);
+        assert_eq!(
+            transport.poll(&mut server),
+            Ok(Some(Outcome::Responded(RESPONSE.len())))
+        );
+
+        // a frame and the start of another one are still pending when the silence is detected
+        transport.serial.receive(&REQUEST);
+        transport.serial.receive(&REQUEST);
+        transport.serial.receive(&other[..1]);
+        assert_eq!(
+            transport.poll(&mut server),
+            Ok(Some(Outcome::Responded(RESPONSE.len())))
+        );
+        assert_eq!(
+            transport.idle(&mut server),
+            Ok(Some(Outcome::Responded(RESPONSE.len())))
+        );
+        transport.serial.receive(&REQUEST);
+        assert_eq!(
+            transport.poll(&mut server),
+            Ok(Some(Outcome::Responded(RESPONSE.len())))
+        );
+        assert_eq!(transport.release().0.transmitted(), RESPONSE.repeat(4));
+    }
+
+    #[cfg(feature = "embedded-io-async")]
+    #[test]
+    fn asynchronous() {
+        use core::{
+            future::Future,
+            pin::pin,
+            task::{Context, Poll, Waker},
+        };
+
+        /// Run a future of the mock serial port, which never waits
+        fn ready<F: Future>(future: F) -> F::Output {
+            match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+                Poll::Ready(output) => output,
+                Poll::Pending => panic!("mock serial port pending"),
+            }
+        }
+
+        let mut server = server();
+        let de = MockPin::default();
+        let mut serial = MockSerial::with_de_pin(de.clone());
+        serial.receive(&REQUEST);
+        let mut transport = RtuTransport::with_de_pin(serial, de.clone());
+        assert_eq!(
+            ready(transport.poll_async(&mut server)),
+            Ok(Some(Outcome::Responded(RESPONSE.len())))
+        );
+        assert_eq!(ready(transport.idle_async(&mut server)), Ok(None));
+        assert!(!de.is_high());
+        assert_eq!(transport.release().0.transmitted(), RESPONSE);
+    }
+
+    #[cfg(feature = "embedded-hal-nb")]
+    #[test]
+    fn nb() {
+        let mut server = server();
+        let de = MockPin::default();
+        let mut serial = MockSerial::with_de_pin(de.clone());
+        serial.receive(&REQUEST);
+        let mut transport = RtuTransport::with_de_pin(serial, de.clone());
+
+        for _ in 0..REQUEST.len() - 1 {
+            assert_eq!(transport.poll_nb(&mut server), Err(nb::Error::WouldBlock));
+        }
+        assert_eq!(
+            transport.poll_nb(&mut server),
+            Ok(Outcome::Responded(RESPONSE.len()))
+        );
+        assert_eq!(transport.poll_nb(&mut server), Err(nb::Error::WouldBlock));
+        assert!(!de.is_high());
+        assert_eq!(transport.release().0.transmitted(), RESPONSE);
+    }
+}
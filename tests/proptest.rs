@@ -0,0 +1,167 @@
+//! Property based tests for `ModbusServer::process_frame`
+//!
+//! Every response emitted by the server has to be a valid RTU frame: correct length, correct CRC,
+//! our unit ID and the function code of the request (with the exception bit for errors).
+
+use modbus_core::rtu::crc16;
+use modbus_server::{ModbusServer, Outcome, error::Error, handler::ModbusHandler};
+use proptest::prelude::*;
+
+const UNIT_ID: u8 = 7;
+
+/// Handler holding the complete address space of coils and registers
+struct ReferenceHandler {
+    coils: Vec<bool>,
+    registers: Vec<u16>,
+}
+
+impl ReferenceHandler {
+    fn new() -> Self {
+        Self {
+            coils: vec![false; 0x10000],
+            registers: vec![0; 0x10000],
+        }
+    }
+}
+
+impl ModbusHandler for ReferenceHandler {
+    fn read_coils(&mut self, addr: usize, len: usize, out: &mut [bool]) -> Result<usize, Error> {
+        out[..len].copy_from_slice(&self.coils[addr..addr + len]);
+        Ok(len)
+    }
+
+    fn read_discrete_input(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [bool],
+    ) -> Result<usize, Error> {
+        self.read_coils(addr, len, out)
+    }
+
+    fn read_holding_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        out[..len].copy_from_slice(&self.registers[addr..addr + len]);
+        Ok(len)
+    }
+
+    fn read_input_registers(
+        &mut self,
+        addr: usize,
+        len: usize,
+        out: &mut [u16],
+    ) -> Result<usize, Error> {
+        self.read_holding_registers(addr, len, out)
+    }
+
+    fn write_coils(&mut self, addr: usize, len: usize, buf: &[bool]) -> Result<usize, Error> {
+        self.coils[addr..addr + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn write_registers(&mut self, addr: usize, len: usize, buf: &[u16]) -> Result<usize, Error> {
+        self.registers[addr..addr + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// Append the CRC to an ADU (unit ID and PDU)
+fn with_crc(adu: &[u8]) -> Vec<u8> {
+    let mut frame = adu.to_vec();
+    frame.extend_from_slice(&crc16(adu).to_be_bytes());
+    frame
+}
+
+/// Process a frame and check the response (if any) is a valid answer to it
+fn process_checked(server: &mut ModbusServer<ReferenceHandler>, frame: &[u8]) -> Option<Vec<u8>> {
+    let mut tx = [0u8; 256];
+    let outcome = server.process_frame(frame, &mut tx).unwrap();
+    let Outcome::Responded(len) = outcome else {
+        return None;
+    };
+    let response = &tx[..len];
+
+    assert!(len >= 5);
+    let (adu, crc) = response.split_at(len - 2);
+    assert_eq!(crc16(adu), u16::from_be_bytes([crc[0], crc[1]]));
+    assert_eq!(adu[0], UNIT_ID);
+    assert_eq!(adu[1] & 0x7F, frame[1]);
+    if adu[1] & 0x80 != 0 {
+        assert_eq!(len, 5);
+    }
+    Some(response.to_vec())
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_bytes(frame in proptest::collection::vec(any::<u8>(), 0..300)) {
+        let mut server = ModbusServer::new(UNIT_ID, ReferenceHandler::new());
+        process_checked(&mut server, &frame);
+    }
+
+    #[test]
+    fn arbitrary_pdu(function in 0u8..0x20, pdu in proptest::collection::vec(any::<u8>(), 0..252)) {
+        let mut server = ModbusServer::new(UNIT_ID, ReferenceHandler::new());
+        let mut adu = vec![UNIT_ID, function];
+        adu.extend_from_slice(&pdu);
+        process_checked(&mut server, &with_crc(&adu));
+    }
+
+    #[test]
+    fn read_request(function in 1u8..=4, addr: u16, quantity: u16) {
+        let mut server = ModbusServer::new(UNIT_ID, ReferenceHandler::new());
+        let [addr_hi, addr_lo] = addr.to_be_bytes();
+        let [qty_hi, qty_lo] = quantity.to_be_bytes();
+        let frame = with_crc(&[UNIT_ID, function, addr_hi, addr_lo, qty_hi, qty_lo]);
+
+        let response = process_checked(&mut server, &frame).unwrap();
+
+        let max = if function <= 2 { 2000 } else { 125 };
+        if quantity == 0 || quantity > max {
+            prop_assert_eq!(response[2], 0x03);
+        } else if addr as usize + quantity as usize > 0x10000 {
+            prop_assert_eq!(response[2], 0x02);
+        } else {
+            let byte_count = if function <= 2 {
+                (quantity as usize).div_ceil(8)
+            } else {
+                quantity as usize * 2
+            };
+            prop_assert_eq!(response[1], function);
+            prop_assert_eq!(response[2] as usize, byte_count);
+            prop_assert_eq!(response.len(), 3 + byte_count + 2);
+        }
+    }
+
+    #[test]
+    fn register_round_trip(addr: u16, value: u16) {
+        let mut server = ModbusServer::new(UNIT_ID, ReferenceHandler::new());
+        let [addr_hi, addr_lo] = addr.to_be_bytes();
+        let [value_hi, value_lo] = value.to_be_bytes();
+
+        let write = with_crc(&[UNIT_ID, 0x06, addr_hi, addr_lo, value_hi, value_lo]);
+        prop_assert_eq!(process_checked(&mut server, &write).unwrap(), write);
+
+        let read = with_crc(&[UNIT_ID, 0x03, addr_hi, addr_lo, 0x00, 0x01]);
+        let response = process_checked(&mut server, &read).unwrap();
+        prop_assert_eq!(&response[..5], &[UNIT_ID, 0x03, 0x02, value_hi, value_lo]);
+    }
+
+    #[test]
+    fn coil_round_trip(addr: u16, state: bool) {
+        let mut server = ModbusServer::new(UNIT_ID, ReferenceHandler::new());
+        let [addr_hi, addr_lo] = addr.to_be_bytes();
+        let value = if state { 0xFF } else { 0x00 };
+
+        let write = with_crc(&[UNIT_ID, 0x05, addr_hi, addr_lo, value, 0x00]);
+        prop_assert_eq!(process_checked(&mut server, &write).unwrap(), write);
+
+        let read = with_crc(&[UNIT_ID, 0x01, addr_hi, addr_lo, 0x00, 0x01]);
+        let response = process_checked(&mut server, &read).unwrap();
+        prop_assert_eq!(&response[..4], &[UNIT_ID, 0x01, 0x01, u8::from(state)]);
+    }
+}
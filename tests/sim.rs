@@ -0,0 +1,138 @@
+//! Run the RTU slave simulator against a pseudo terminal pair
+//!
+//! The simulator opens the slave side of the pair, the test acts as master on the other side.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use modbus_core::Exception;
+use modbus_server::{
+    client::{self, Request, Response},
+    error::Error,
+};
+use serialport::{SerialPort, TTYPort};
+
+const CONFIG: &str = r#"
+unit_id = 17
+
+[serial]
+baud_rate = 115200
+
+[[holding_registers]]
+address = 100
+values = [1, 2, 3]
+
+[[coils]]
+address = 0
+values = [true, false, true]
+"#;
+
+/// Simulator process, killed when dropped
+struct Simulator {
+    child: Child,
+    config: PathBuf,
+}
+
+impl Simulator {
+    fn start(device: &str) -> Self {
+        let config =
+            std::env::temp_dir().join(format!("modbus-rtu-sim-{}.toml", std::process::id()));
+        fs::write(&config, CONFIG).unwrap();
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_modbus-rtu-sim"))
+            .arg(device)
+            .arg(&config)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // wait until the simulator opened the device
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut line = String::new();
+        stderr.read_line(&mut line).unwrap();
+        assert!(line.starts_with("serving unit 17"), "{line}");
+        // keep draining the log, the simulator fails on a closed pipe
+        thread::spawn(move || {
+            for line in stderr.lines() {
+                eprintln!("{}", line.unwrap());
+            }
+        });
+
+        Self { child, config }
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_file(&self.config);
+    }
+}
+
+/// Send a request and receive its response
+fn transfer(master: &mut TTYPort, request: Request) -> Vec<u8> {
+    let mut tx = [0u8; client::REQUEST_FRAME_LEN];
+    let len = client::encode_request(17, request, &mut tx).unwrap();
+    master.write_all(&tx[..len]).unwrap();
+
+    // read up to the length of a successful response, stop early for exception responses
+    let mut rx = vec![0u8; request.response_len()];
+    let mut received = 0;
+    while received < rx.len() && !(received == 5 && rx[1] & 0x80 != 0) {
+        received += master.read(&mut rx[received..]).unwrap();
+    }
+    rx.truncate(received);
+    rx
+}
+
+#[test]
+fn serve_over_pty() {
+    let (mut master, slave) = TTYPort::pair().unwrap();
+    master.set_timeout(Duration::from_secs(5)).unwrap();
+    let _sim = Simulator::start(&slave.name().unwrap());
+
+    let request = Request::ReadHoldingRegisters(100, 3);
+    let rx = transfer(&mut master, request);
+    let Ok(Response::ReadHoldingRegisters(registers)) = client::decode_response(17, request, &rx)
+    else {
+        panic!("unexpected response {rx:02X?}");
+    };
+    assert!(registers.iter().eq([1, 2, 3]));
+
+    let request = Request::WriteSingleRegister(101, 0xBEEF);
+    let rx = transfer(&mut master, request);
+    assert_eq!(
+        client::decode_response(17, request, &rx),
+        Ok(Response::WriteSingleRegister(101, 0xBEEF))
+    );
+
+    let request = Request::ReadCoils(0, 3);
+    let rx = transfer(&mut master, request);
+    let Ok(Response::ReadCoils(coils)) = client::decode_response(17, request, &rx) else {
+        panic!("unexpected response {rx:02X?}");
+    };
+    assert!(coils.iter().eq([true, false, true]));
+
+    // addresses outside of the register map
+    let request = Request::ReadHoldingRegisters(102, 2);
+    let rx = transfer(&mut master, request);
+    assert_eq!(
+        client::decode_response(17, request, &rx),
+        Err(Error::Exception(Exception::IllegalDataAddress))
+    );
+
+    let request = Request::ReadHoldingRegisters(101, 1);
+    let rx = transfer(&mut master, request);
+    let Ok(Response::ReadHoldingRegisters(registers)) = client::decode_response(17, request, &rx)
+    else {
+        panic!("unexpected response {rx:02X?}");
+    };
+    assert_eq!(registers.get(0), Some(0xBEEF));
+}